@@ -0,0 +1,269 @@
+//! `--cache`: remember the last scan of a directory in the user's cache
+//! directory (see `paths`), one file per scanned directory.
+//!
+//! The cache records every directory that was read and every archive found,
+//! each with its mtime. On the next run all of them are restatted; any change
+//! (a file added, removed or renamed changes its directory's mtime) throws the
+//! cache away and the tree is rescanned.
+
+use crate::{paths, scan_tree, Archive, ScanOptions, Scanned};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Bumped whenever the file format changes, so old caches are ignored.
+const CACHE_VERSION: u32 = 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ScanCache {
+    version: u32,
+    /// The scanned directory, in case two directories hash alike.
+    root: PathBuf,
+    /// Scanned with `--archive-root-only`; such a cache misses subdirectories.
+    #[serde(default)]
+    root_only: bool,
+    dirs: Vec<Stamp>,
+    archives: Vec<CachedArchive>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Stamp {
+    path: PathBuf,
+    mtime_ns: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedArchive {
+    #[serde(flatten)]
+    stamp: Stamp,
+    #[serde(rename = "type")]
+    archive_type: String,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Self> {
+        Some(Stamp {
+            path: path.to_path_buf(),
+            mtime_ns: mtime_ns(path)?,
+        })
+    }
+
+    fn is_current(&self) -> bool {
+        mtime_ns(&self.path) == Some(self.mtime_ns)
+    }
+}
+
+fn mtime_ns(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let since_epoch = modified.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+    u64::try_from(since_epoch.as_nanos()).ok()
+}
+
+impl ScanCache {
+    fn new(root: &Path, opts: &ScanOptions, dirs: &[PathBuf], archives: &[Archive]) -> Self {
+        ScanCache {
+            version: CACHE_VERSION,
+            root: root.to_path_buf(),
+            root_only: opts.root_only,
+            dirs: dirs.iter().filter_map(|d| Stamp::of(d)).collect(),
+            archives: archives
+                .iter()
+                .filter_map(|a| {
+                    Some(CachedArchive {
+                        stamp: Stamp::of(&a.path)?,
+                        archive_type: a.archive_type.as_str().to_string(),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn read(path: &Path) -> Option<Self> {
+        let cache: ScanCache = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+        (cache.version == CACHE_VERSION).then_some(cache)
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Cannot create cache directory")?;
+        }
+        let file = File::create(path).context("Cannot write scan cache")?;
+        serde_json::to_writer(file, self).context("Cannot write scan cache")
+    }
+
+    /// The cached archives, or None if anything on disk changed since the scan
+    /// (or the archive types are no longer recognized the same way).
+    fn validate(&self, root: &Path, opts: &ScanOptions) -> Option<Vec<Archive>> {
+        if self.root != root
+            || self.root_only != opts.root_only
+            || !self.dirs.iter().all(Stamp::is_current)
+        {
+            return None;
+        }
+        self.archives
+            .iter()
+            .map(|cached| {
+                let archive = Archive::detect(cached.stamp.path.clone(), &opts.custom_types)?;
+                let unchanged = cached.stamp.is_current()
+                    && archive.archive_type.as_str() == cached.archive_type;
+                unchanged.then_some(archive)
+            })
+            .collect()
+    }
+}
+
+/// Cache file for a scanned directory, or None without a usable cache directory.
+pub(crate) fn cache_file_for(dir: &Path) -> Option<PathBuf> {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    dir.hash(&mut hasher);
+    let name = format!("scan-{:016x}.json", hasher.finish());
+    paths::cache_dir().map(|cache| cache.join("scans").join(name))
+}
+
+/// `scan_archives` backed by the cache file at `cache_path`. The cache keeps
+/// stubs too, so one that finished downloading is picked up by its new size.
+pub(crate) fn cached_scan(dir: &Path, cache_path: &Path, opts: &ScanOptions) -> Result<Scanned> {
+    let dir = crate::resolve_scan_dir(dir)?;
+
+    let cached = ScanCache::read(cache_path).and_then(|c| c.validate(&dir, opts));
+    let archives = match cached {
+        Some(archives) => {
+            log::debug!("Using scan cache {}", cache_path.display());
+            archives
+        }
+        None => {
+            let (archives, dirs, _) = scan_tree(dir.clone(), opts);
+            if let Err(e) = ScanCache::new(&dir, opts, &dirs, &archives).write(cache_path) {
+                log::warn!("{:#}", e);
+            }
+            archives
+        }
+    };
+    let (archives, stubs) = crate::split_stubs(archives, opts.min_file_size);
+    Ok(Scanned {
+        archives,
+        stubs,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(archives: &[Archive]) -> Vec<PathBuf> {
+        archives.iter().map(|a| a.path.clone()).collect()
+    }
+
+    #[test]
+    fn test_cache_write_and_read() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir(temp.path().join("sub")).unwrap();
+        fs::write(temp.path().join("a.7z.001"), b"x").unwrap();
+        fs::write(temp.path().join("sub/b.part01.rar"), b"x").unwrap();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join("scans/scan.json");
+        let first = cached_scan(temp.path(), &cache_path, &ScanOptions::default())
+            .unwrap()
+            .archives;
+        assert_eq!(first.len(), 2);
+
+        let cache = ScanCache::read(&cache_path).unwrap();
+        assert_eq!(cache.dirs.len(), 2);
+        assert_eq!(cache.archives.len(), 2);
+        assert_eq!(cache.archives[0].archive_type, "7z");
+
+        // Nothing changed, so the cache is used as-is
+        let root = temp.path().canonicalize().unwrap();
+        let cached = cache.validate(&root, &ScanOptions::default()).unwrap();
+        assert_eq!(paths(&cached), paths(&first));
+        let second = cached_scan(temp.path(), &cache_path, &ScanOptions::default())
+            .unwrap()
+            .archives;
+        assert_eq!(paths(&second), paths(&first));
+
+        // A cache for another directory is never used
+        assert!(cache
+            .validate(&root.join("sub"), &ScanOptions::default())
+            .is_none());
+    }
+
+    #[test]
+    fn test_cache_invalidated_when_file_changes() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir(temp.path().join("sub")).unwrap();
+        let archive = temp.path().join("sub/a.7z.001");
+        fs::write(&archive, b"x").unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join("scans/scan.json");
+        cached_scan(temp.path(), &cache_path, &ScanOptions::default()).unwrap();
+
+        let later = SystemTime::now() + std::time::Duration::from_secs(60);
+        File::options()
+            .write(true)
+            .open(&archive)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let cache = ScanCache::read(&cache_path).unwrap();
+        assert!(cache.validate(&root, &ScanOptions::default()).is_none());
+
+        // A new archive in a subdirectory changes that directory's mtime
+        cached_scan(temp.path(), &cache_path, &ScanOptions::default()).unwrap();
+        fs::write(temp.path().join("sub/b.part01.rar"), b"x").unwrap();
+        let cache = ScanCache::read(&cache_path).unwrap();
+        assert!(cache.validate(&root, &ScanOptions::default()).is_none());
+        let rescanned = cached_scan(temp.path(), &cache_path, &ScanOptions::default())
+            .unwrap()
+            .archives;
+        assert_eq!(rescanned.len(), 2);
+    }
+
+    #[test]
+    fn test_cache_keeps_root_only_scans_apart() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir(temp.path().join("sub")).unwrap();
+        fs::write(temp.path().join("a.7z.001"), b"x").unwrap();
+        fs::write(temp.path().join("sub/b.part01.rar"), b"x").unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join("scan.json");
+        let root_only = ScanOptions {
+            root_only: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            cached_scan(temp.path(), &cache_path, &root_only)
+                .unwrap()
+                .archives
+                .len(),
+            1
+        );
+        let full = cached_scan(temp.path(), &cache_path, &ScanOptions::default())
+            .unwrap()
+            .archives;
+        assert_eq!(full.len(), 2);
+        assert_eq!(
+            cached_scan(temp.path(), &cache_path, &root_only)
+                .unwrap()
+                .archives
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_cache_ignores_other_versions() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("scan.json");
+        fs::write(&path, r#"{"version":0,"root":"/","dirs":[],"archives":[]}"#).unwrap();
+        assert!(ScanCache::read(&path).is_none());
+        fs::write(&path, "not json").unwrap();
+        assert!(ScanCache::read(&path).is_none());
+    }
+}
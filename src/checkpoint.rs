@@ -0,0 +1,194 @@
+//! `--checkpoint PATH`: the state of a whole batch, rewritten after every
+//! archive, so an interrupted run can be resumed by running it again with the
+//! same checkpoint. Unlike `--success-log` it records failures too; only
+//! archives that finished are left out on the next run, failed ones are
+//! retried.
+//!
+//! The file is JSON keyed by the archive's canonical path (the same key as the
+//! success log) and is replaced atomically, so an interrupted write never
+//! leaves it half-written.
+
+use crate::{success_log_key, Archive, Outcome};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Bumped whenever the file format changes; other versions are refused.
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// What became of an archive in an earlier run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Status {
+    /// Extracted; not run again.
+    Done,
+    /// Failed; retried on resume.
+    Failed,
+    /// Skipped, e.g. because its output already existed; checked again on resume.
+    Skipped,
+}
+
+impl Status {
+    /// The status an outcome leaves behind. None leaves the archive pending:
+    /// it was never started (`--deadline`), or only tested or previewed, which
+    /// must not stop a later run from extracting it.
+    pub(crate) fn of(result: &Result<Outcome>) -> Option<Self> {
+        match result {
+            Ok(Outcome::Extracted(_)) => Some(Status::Done),
+            Ok(Outcome::Skipped | Outcome::Incomplete(_)) => Some(Status::Skipped),
+            Ok(
+                Outcome::Tested
+                | Outcome::QuickChecked
+                | Outcome::Previewed(_)
+                | Outcome::OutOfTime,
+            ) => None,
+            Err(_) => Some(Status::Failed),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    version: u32,
+    archives: BTreeMap<PathBuf, Status>,
+}
+
+/// A checkpoint file and the state read from it, shared by parallel jobs.
+#[derive(Debug)]
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint at `path`; a missing file starts an empty one.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let state = match fs::read(path) {
+            Ok(bytes) => {
+                let state: State = serde_json::from_slice(&bytes)
+                    .with_context(|| format!("Invalid checkpoint {}", path.display()))?;
+                if state.version != CHECKPOINT_VERSION {
+                    anyhow::bail!(
+                        "Checkpoint {} has version {}, expected {}",
+                        path.display(),
+                        state.version,
+                        CHECKPOINT_VERSION
+                    );
+                }
+                state
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => State {
+                version: CHECKPOINT_VERSION,
+                archives: BTreeMap::new(),
+            },
+            Err(e) => {
+                return Err(e).with_context(|| format!("Cannot read checkpoint {}", path.display()))
+            }
+        };
+        Ok(Checkpoint {
+            path: path.to_path_buf(),
+            state: Mutex::new(state),
+        })
+    }
+
+    pub(crate) fn status(&self, archive: &Archive) -> Option<Status> {
+        let state = self.state.lock().unwrap();
+        state.archives.get(&success_log_key(archive)).copied()
+    }
+
+    /// Record `status` for `archive` and rewrite the file.
+    pub(crate) fn record(&self, archive: &Archive, status: Status) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.archives.insert(success_log_key(archive), status);
+        // Written under the lock, so parallel jobs never race on the file
+        let staging = self.path.with_extension("un7z-tmp");
+        let json = serde_json::to_vec_pretty(&*state)?;
+        fs::write(&staging, json)
+            .with_context(|| format!("Cannot write checkpoint {}", staging.display()))?;
+        fs::rename(&staging, &self.path)
+            .with_context(|| format!("Cannot write checkpoint {}", self.path.display()))
+    }
+
+    /// Split the selection into archives still to run and a count of those
+    /// the checkpoint has as done.
+    pub(crate) fn partition(
+        &self,
+        archives: &[Archive],
+        indices: Vec<usize>,
+    ) -> (Vec<usize>, usize) {
+        let (done, todo): (Vec<usize>, Vec<usize>) = indices
+            .into_iter()
+            .partition(|&i| self.status(&archives[i]) == Some(Status::Done));
+        (todo, done.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_write_and_resume() {
+        let temp = tempfile::tempdir().unwrap();
+        let archives: Vec<Archive> = ["a.7z.001", "b.part01.rar", "c.tar.gz", "d.tar.gz"]
+            .iter()
+            .map(|name| {
+                let path = temp.path().join(name);
+                fs::write(&path, "x").unwrap();
+                Archive::new(path).unwrap()
+            })
+            .collect();
+        let path = temp.path().join("batch.checkpoint");
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.status(&archives[0]), None);
+        let results = [
+            Ok(Outcome::Extracted(temp.path().join("a"))),
+            Err(anyhow::anyhow!("CRC failed")),
+            Ok(Outcome::Skipped),
+            Ok(Outcome::OutOfTime),
+        ];
+        for (archive, result) in archives.iter().zip(&results) {
+            if let Some(status) = Status::of(result) {
+                checkpoint.record(archive, status).unwrap();
+            }
+        }
+
+        // A second run reads back what the first left behind
+        let resumed = Checkpoint::load(&path).unwrap();
+        assert_eq!(resumed.status(&archives[0]), Some(Status::Done));
+        assert_eq!(resumed.status(&archives[1]), Some(Status::Failed));
+        assert_eq!(resumed.status(&archives[2]), Some(Status::Skipped));
+        assert_eq!(resumed.status(&archives[3]), None);
+        // Only the finished archive is left out; the failed one is retried
+        assert_eq!(
+            resumed.partition(&archives, vec![0, 1, 2, 3]),
+            (vec![1, 2, 3], 1)
+        );
+        assert!(!path.with_extension("un7z-tmp").exists());
+
+        // Testing or previewing doesn't count as done
+        for result in [
+            Ok(Outcome::Tested),
+            Ok(Outcome::QuickChecked),
+            Ok(Outcome::Previewed(temp.path().join("a"))),
+        ] {
+            assert_eq!(Status::of(&result), None);
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_rejects_other_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("batch.checkpoint");
+        fs::write(&path, "a.7z\n").unwrap();
+        let error = Checkpoint::load(&path).unwrap_err();
+        assert!(format!("{:#}", error).contains("Invalid checkpoint"));
+        fs::write(&path, r#"{"version": 9, "archives": {}}"#).unwrap();
+        let error = Checkpoint::load(&path).unwrap_err();
+        assert!(format!("{:#}", error).contains("version 9"));
+    }
+}
@@ -0,0 +1,377 @@
+//! `--checksum-archive`: check each volume's file digest against a `.sha256`
+//! or `.md5` sidecar before extracting. This verifies the download, not the
+//! archive's internal CRCs (that's `--test`).
+//!
+//! Digests come from `sha256sum`/`md5sum`, like every other backend here.
+
+use crate::{display_path, Archive};
+use anyhow::{Context, Result};
+use console::style;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Algorithm {
+    Sha256,
+    Md5,
+}
+
+impl Algorithm {
+    const ALL: [Algorithm; 2] = [Algorithm::Sha256, Algorithm::Md5];
+
+    fn extension(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Md5 => "md5",
+        }
+    }
+
+    /// Name of the directory-wide sums file (`SHA256SUMS`).
+    fn sums_file(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA256SUMS",
+            Algorithm::Md5 => "MD5SUMS",
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256sum",
+            Algorithm::Md5 => "md5sum",
+        }
+    }
+
+    fn hex_len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 64,
+            Algorithm::Md5 => 32,
+        }
+    }
+}
+
+/// One line of a sums file: `digest  name`, `digest *name` (binary mode) or a
+/// bare digest in a single-file sidecar.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SumLine {
+    pub digest: String,
+    pub file_name: Option<String>,
+}
+
+/// Parse a sums file, skipping blank lines, comments and anything that isn't
+/// a hex digest of the algorithm's length.
+pub(crate) fn parse_sums(content: &str, algorithm: Algorithm) -> Vec<SumLine> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|line| {
+            let (digest, rest) = match line.split_once(char::is_whitespace) {
+                Some((digest, rest)) => (digest, Some(rest)),
+                None => (line, None),
+            };
+            let valid = digest.len() == algorithm.hex_len()
+                && digest.bytes().all(|b| b.is_ascii_hexdigit());
+            let file_name = rest
+                .map(|r| r.trim_start().trim_start_matches('*').to_string())
+                .filter(|r| !r.is_empty());
+            valid.then(|| SumLine {
+                digest: digest.to_ascii_lowercase(),
+                file_name,
+            })
+        })
+        .collect()
+}
+
+/// The digest a sidecar next to `volume` expects for it. Looked up in order:
+/// `<volume>.sha256` (bare digest or sums format), `<base_name>.sha256`
+/// and `SHA256SUMS`, then the same for md5.
+pub(crate) fn expected_digest(volume: &Path, base_name: &str) -> Option<(Algorithm, String)> {
+    let file_name = volume.file_name()?.to_str()?;
+    let dir = volume.parent().unwrap_or(Path::new(""));
+    for algorithm in Algorithm::ALL {
+        let ext = algorithm.extension();
+        let own = dir.join(format!("{}.{}", file_name, ext));
+        if let Ok(content) = fs::read_to_string(&own) {
+            let sums = parse_sums(&content, algorithm);
+            let entry = sums
+                .iter()
+                .find(|s| s.file_name.as_deref().is_none_or(|n| n == file_name));
+            if let Some(entry) = entry {
+                return Some((algorithm, entry.digest.clone()));
+            }
+        }
+        let shared = [
+            dir.join(format!("{}.{}", base_name, ext)),
+            dir.join(algorithm.sums_file()),
+        ];
+        for sums_path in shared {
+            let Ok(content) = fs::read_to_string(&sums_path) else {
+                continue;
+            };
+            let found = parse_sums(&content, algorithm)
+                .into_iter()
+                .find(|s| s.file_name.as_deref() == Some(file_name));
+            if let Some(entry) = found {
+                return Some((algorithm, entry.digest));
+            }
+        }
+    }
+    None
+}
+
+/// Digest of a file via `sha256sum`/`md5sum`.
+pub(crate) fn file_digest(algorithm: Algorithm, path: &Path) -> Result<String> {
+    let output = Command::new(algorithm.program())
+        .arg("--")
+        .arg(path)
+        .output()
+        .with_context(|| format!("Failed to run {}", algorithm.program()))?;
+    if !output.status.success() {
+        anyhow::bail!("{} failed on {}", algorithm.program(), path.display());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let digest = stdout.split_whitespace().next().unwrap_or_default();
+    Ok(digest.trim_start_matches('\\').to_ascii_lowercase())
+}
+
+/// A volume whose digest differs from its sidecar.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct Mismatch {
+    pub volume: PathBuf,
+    pub algorithm: Algorithm,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Outcome of checking an archive's volumes against their sidecars.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct ChecksumReport {
+    /// Volumes whose digest matched.
+    pub verified: usize,
+    /// Volumes with no sidecar entry.
+    pub unlisted: Vec<PathBuf>,
+    /// Volumes whose digest differs from the sidecar.
+    pub mismatched: Vec<Mismatch>,
+}
+
+impl ChecksumReport {
+    /// The mismatch report printed before skipping an archive: each bad volume
+    /// with its expected (`-`) and actual (`+`) digest, then the counts.
+    pub(crate) fn format_mismatches(&self, base_name: &str) -> Vec<String> {
+        let mut lines = vec![format!("{} {}", style("✗").red(), style(base_name).red())];
+        for mismatch in &self.mismatched {
+            lines.push(format!(
+                "  {} Checksum mismatch: {} ({})",
+                style("┖─").dim(),
+                display_path(&mismatch.volume),
+                mismatch.algorithm.program()
+            ));
+            lines.push(format!(
+                "     {}",
+                style(format!("- expected {}", mismatch.expected)).green()
+            ));
+            lines.push(format!(
+                "     {}",
+                style(format!("+ actual   {}", mismatch.actual)).red()
+            ));
+        }
+        lines.push(format!(
+            "  {} {} matched, {} mismatched, {} missing",
+            style("┖─").dim(),
+            style(self.verified).green(),
+            style(self.mismatched.len()).red(),
+            style(self.unlisted.len()).yellow()
+        ));
+        lines.push(format!(
+            "  {} Skipped, re-download the volume(s) above",
+            style("┖─").dim()
+        ));
+        lines
+    }
+
+    /// `--json`: the report as one JSON object.
+    pub(crate) fn to_json(&self, archive: &Path) -> serde_json::Value {
+        serde_json::json!({
+            "archive": archive,
+            "matched": self.verified,
+            "mismatched": self.mismatched,
+            "missing": self.unlisted,
+        })
+    }
+}
+
+/// Check every volume that has a sidecar entry, using `digest` to hash it.
+pub(crate) fn verify_volumes<F>(
+    volumes: &[PathBuf],
+    base_name: &str,
+    digest: F,
+) -> Result<ChecksumReport>
+where
+    F: Fn(Algorithm, &Path) -> Result<String>,
+{
+    let mut report = ChecksumReport::default();
+    for volume in volumes {
+        match expected_digest(volume, base_name) {
+            Some((algorithm, expected)) => {
+                let actual = digest(algorithm, volume)?;
+                if actual == expected {
+                    report.verified += 1;
+                } else {
+                    report.mismatched.push(Mismatch {
+                        volume: volume.clone(),
+                        algorithm,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+            None => report.unlisted.push(volume.clone()),
+        }
+    }
+    Ok(report)
+}
+
+/// `verify_volumes` for all of an archive's volumes.
+pub(crate) fn verify_archive(archive: &Archive) -> Result<ChecksumReport> {
+    verify_volumes(&archive.volumes(), &archive.base_name, file_digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const ABC_MD5: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+    #[test]
+    fn test_parse_sums() {
+        let content = format!(
+            "# made by sha256sum\n{}  data.7z.001\n{} *data.7z.002\n\nnot-a-digest  x\n",
+            ABC_SHA256,
+            ABC_SHA256.to_uppercase()
+        );
+        let sums = parse_sums(&content, Algorithm::Sha256);
+        assert_eq!(
+            sums,
+            vec![
+                SumLine {
+                    digest: ABC_SHA256.to_string(),
+                    file_name: Some("data.7z.001".to_string()),
+                },
+                SumLine {
+                    digest: ABC_SHA256.to_string(),
+                    file_name: Some("data.7z.002".to_string()),
+                },
+            ]
+        );
+
+        // A bare digest in a single-file sidecar; md5 length doesn't pass as sha256
+        let bare = parse_sums(&format!("{}\n", ABC_MD5), Algorithm::Md5);
+        assert_eq!(bare[0].file_name, None);
+        assert!(parse_sums(ABC_MD5, Algorithm::Sha256).is_empty());
+    }
+
+    #[test]
+    fn test_expected_digest_lookup_order() {
+        let temp = tempfile::tempdir().unwrap();
+        let volume = temp.path().join("data.7z.001");
+        fs::write(&volume, "abc").unwrap();
+        assert_eq!(expected_digest(&volume, "data"), None);
+
+        fs::write(
+            temp.path().join("MD5SUMS"),
+            format!("{}  data.7z.001\n", ABC_MD5),
+        )
+        .unwrap();
+        assert_eq!(
+            expected_digest(&volume, "data"),
+            Some((Algorithm::Md5, ABC_MD5.to_string()))
+        );
+
+        // sha256 wins over md5, and the volume's own sidecar over shared ones
+        let other = "0".repeat(64);
+        fs::write(
+            temp.path().join("data.sha256"),
+            format!("{}  data.7z.001\n", other),
+        )
+        .unwrap();
+        assert_eq!(
+            expected_digest(&volume, "data"),
+            Some((Algorithm::Sha256, other))
+        );
+        fs::write(temp.path().join("data.7z.001.sha256"), ABC_SHA256).unwrap();
+        assert_eq!(
+            expected_digest(&volume, "data"),
+            Some((Algorithm::Sha256, ABC_SHA256.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_verify_volumes_per_volume() {
+        let temp = tempfile::tempdir().unwrap();
+        let volumes: Vec<PathBuf> = (1..=3)
+            .map(|n| temp.path().join(format!("data.7z.{:03}", n)))
+            .collect();
+        fs::write(&volumes[0], "abc").unwrap();
+        fs::write(&volumes[1], "abd").unwrap();
+        fs::write(&volumes[2], "abc").unwrap();
+        let sums = format!("{0}  data.7z.001\n{0}  data.7z.002\n", ABC_SHA256);
+        fs::write(temp.path().join("data.sha256"), sums).unwrap();
+
+        let report = verify_volumes(&volumes, "data", file_digest).unwrap();
+        assert_eq!(
+            report,
+            ChecksumReport {
+                verified: 1,
+                unlisted: vec![volumes[2].clone()],
+                mismatched: vec![Mismatch {
+                    volume: volumes[1].clone(),
+                    algorithm: Algorithm::Sha256,
+                    expected: ABC_SHA256.to_string(),
+                    actual: file_digest(Algorithm::Sha256, &volumes[1]).unwrap(),
+                }],
+            }
+        );
+        assert_eq!(file_digest(Algorithm::Md5, &volumes[0]).unwrap(), ABC_MD5);
+    }
+
+    #[test]
+    fn test_mismatch_report() {
+        let report = ChecksumReport {
+            verified: 2,
+            unlisted: vec![PathBuf::from("data.7z.004")],
+            mismatched: vec![Mismatch {
+                volume: PathBuf::from("data.7z.002"),
+                algorithm: Algorithm::Md5,
+                expected: ABC_MD5.to_string(),
+                actual: "0".repeat(32),
+            }],
+        };
+        let lines: Vec<String> = report
+            .format_mismatches("data")
+            .iter()
+            .map(|l| console::strip_ansi_codes(l).into_owned())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "✗ data".to_string(),
+                "  ┖─ Checksum mismatch: data.7z.002 (md5sum)".to_string(),
+                format!("     - expected {}", ABC_MD5),
+                format!("     + actual   {}", "0".repeat(32)),
+                "  ┖─ 2 matched, 1 mismatched, 1 missing".to_string(),
+                "  ┖─ Skipped, re-download the volume(s) above".to_string(),
+            ]
+        );
+
+        let json = report.to_json(Path::new("data.7z.001"));
+        assert_eq!(json["archive"], "data.7z.001");
+        assert_eq!(json["matched"], 2);
+        assert_eq!(json["missing"][0], "data.7z.004");
+        assert_eq!(json["mismatched"][0]["algorithm"], "md5");
+        assert_eq!(json["mismatched"][0]["expected"], ABC_MD5);
+    }
+}
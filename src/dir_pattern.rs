@@ -0,0 +1,187 @@
+//! `--dir` patterns: brace alternatives (`downloads/{movies,shows}`) and
+//! wildcards (`*`, `?`, `[...]`) that expand to several scan roots.
+//!
+//! A `--dir` without either, or one naming an existing directory (such as
+//! `Movies [2024]`), is used as it is, so a plain directory keeps its old errors.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Whether `s` uses any pattern syntax.
+fn is_pattern(s: &str) -> bool {
+    has_wildcard(s) || expand_braces(s).len() > 1
+}
+
+fn has_wildcard(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Expand the first `{a,b,...}` group (and recursively the rest), like a
+/// shell. Braces without a top-level comma or without a match stay literal.
+pub(crate) fn expand_braces(pattern: &str) -> Vec<String> {
+    let bytes = pattern.as_bytes();
+    for open in (0..bytes.len()).filter(|&i| bytes[i] == b'{') {
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut close = None;
+        for (i, &b) in bytes.iter().enumerate().skip(open) {
+            match b {
+                b'{' => depth += 1,
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(i);
+                        break;
+                    }
+                }
+                b',' if depth == 1 => commas.push(i),
+                _ => {}
+            }
+        }
+        let Some(close) = close else {
+            continue;
+        };
+        if commas.is_empty() {
+            continue;
+        }
+        let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+        let starts = std::iter::once(open)
+            .chain(commas.iter().copied())
+            .map(|i| i + 1);
+        let ends = commas.iter().copied().chain([close]);
+        return starts
+            .zip(ends)
+            .flat_map(|(start, end)| {
+                expand_braces(&format!("{}{}{}", prefix, &pattern[start..end], suffix))
+            })
+            .collect();
+    }
+    vec![pattern.to_string()]
+}
+
+/// Directories matching one brace-free pattern, in sorted order. Hidden
+/// entries match only a pattern that starts with a literal `.`, as in a shell.
+fn expand_wildcards(pattern: &str) -> Result<Vec<PathBuf>> {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    let pattern = match pattern.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    };
+    let paths = glob::glob_with(pattern, options)
+        .with_context(|| format!("Invalid --dir pattern '{}'", pattern))?;
+    Ok(paths
+        .filter_map(|p| p.ok())
+        .filter(|p| p.is_dir() && !hidden_by_wildcard(Path::new(pattern), p))
+        .collect())
+}
+
+/// Whether a wildcard component of `pattern` matched a hidden name in `path`.
+/// (The glob crate's own `require_literal_leading_dot` also drops `.h*`.)
+fn hidden_by_wildcard(pattern: &Path, path: &Path) -> bool {
+    let names = |p: &Path| {
+        p.components()
+            .rev()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+    names(pattern)
+        .iter()
+        .zip(names(path))
+        .any(|(glob, name)| has_wildcard(glob) && !glob.starts_with('.') && name.starts_with('.'))
+}
+
+/// The scan roots `--dir` stands for. A pattern that matches no directory is
+/// an error.
+pub(crate) fn expand(dir: &Path) -> Result<Vec<PathBuf>> {
+    let Some(pattern) = dir.to_str().filter(|d| is_pattern(d) && !dir.exists()) else {
+        return Ok(vec![dir.to_path_buf()]);
+    };
+    let mut dirs = Vec::new();
+    for alternative in expand_braces(pattern) {
+        for found in expand_wildcards(&alternative)? {
+            if !dirs.contains(&found) {
+                dirs.push(found);
+            }
+        }
+    }
+    if dirs.is_empty() {
+        anyhow::bail!("--dir pattern '{}' matches no directory", pattern);
+    }
+    Ok(dirs)
+}
+
+/// The part of `dir` before its first pattern component: the common root of
+/// everything it expands to (`downloads` for `downloads/{movies,shows}`).
+pub(crate) fn literal_prefix(dir: &Path) -> PathBuf {
+    let prefix: PathBuf = dir
+        .components()
+        .take_while(|c| c.as_os_str().to_str().is_none_or(|s| !is_pattern(s)))
+        .collect();
+    if prefix.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        prefix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_expand_braces() {
+        assert_eq!(
+            expand_braces("dl/{movies,shows}"),
+            ["dl/movies", "dl/shows"]
+        );
+        assert_eq!(expand_braces("{a,b}/{1,2}"), ["a/1", "a/2", "b/1", "b/2"]);
+        assert_eq!(expand_braces("a{b,c{d,e}}f"), ["abf", "acdf", "acef"]);
+        assert_eq!(expand_braces("x{,s}"), ["x", "xs"]);
+        // Not a brace group: left alone
+        assert_eq!(expand_braces("{x}"), ["{x}"]);
+        assert_eq!(expand_braces("a{b,c"), ["a{b,c"]);
+        assert_eq!(expand_braces("plain"), ["plain"]);
+    }
+
+    #[test]
+    fn test_expand_dir_patterns() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("downloads");
+        for dir in ["movies", "shows", "music", ".hidden", "movies/2024"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("misc.txt"), "").unwrap();
+        let pattern = |p: &str| expand(&root.join(p)).unwrap();
+
+        assert_eq!(
+            pattern("{movies,shows}"),
+            [root.join("movies"), root.join("shows")]
+        );
+        assert_eq!(
+            pattern("*"),
+            [root.join("movies"), root.join("music"), root.join("shows")]
+        );
+        assert_eq!(pattern("m[ou]*"), [root.join("movies"), root.join("music")]);
+        assert_eq!(pattern("{m*,shows,gone}/"), pattern("{movies,music,shows}"));
+        assert_eq!(pattern("*/20??"), [root.join("movies/2024")]);
+        assert_eq!(pattern(".h*"), [root.join(".hidden")]);
+
+        // A plain --dir is passed through even when it doesn't exist
+        assert_eq!(pattern("gone"), [root.join("gone")]);
+        // An existing directory is taken literally, whatever its name
+        fs::create_dir(root.join("Movies [2024]")).unwrap();
+        assert_eq!(pattern("Movies [2024]"), [root.join("Movies [2024]")]);
+        let err = expand(&root.join("{gone,lost}")).unwrap_err();
+        assert!(err.to_string().contains("matches no directory"), "{}", err);
+        assert!(expand(&root.join("misc*")).is_err());
+
+        assert_eq!(literal_prefix(&root.join("{movies,shows}")), root);
+        assert_eq!(literal_prefix(&root.join("*/2024")), root);
+        assert_eq!(literal_prefix(Path::new("*")), PathBuf::from("."));
+        assert_eq!(literal_prefix(&root), root);
+    }
+}
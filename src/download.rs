@@ -0,0 +1,268 @@
+//! `un7z URL`: fetch an archive over HTTP(S) into a temporary directory and
+//! extract it like a listed file. The download is deleted when the run ends.
+//!
+//! Each URL is a single file; split archives behind URLs aren't supported.
+//! Several URLs are fetched side by side, at most `--max-concurrent-downloads`
+//! at a time. Extraction starts once all of them have finished: the batch
+//! checks targets, collisions and sizes for every archive up front, so it
+//! needs the files on disk. A failed download doesn't stop the others; it is
+//! counted with the batch's failures.
+
+use anyhow::{Context, Result};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Whether a command-line FILE argument is an http(s) URL.
+pub(crate) fn is_url(arg: &Path) -> bool {
+    let Some(arg) = arg.to_str() else {
+        return false;
+    };
+    ["http://", "https://"].iter().any(|scheme| {
+        arg.get(..scheme.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    })
+}
+
+/// The file name a URL points at: its last path segment, percent-decoded,
+/// without query or fragment.
+pub(crate) fn url_file_name(url: &str) -> Option<String> {
+    let url = url.split(['?', '#']).next()?;
+    let (_, path) = url.split_once("://")?;
+    let (_, path) = path.split_once('/')?;
+    let name = percent_decode(path.rsplit('/').next()?);
+    let usable = !name.is_empty() && name != "." && name != ".." && !name.contains('/');
+    usable.then_some(name)
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// A downloaded archive; its temporary directory is removed on drop.
+#[derive(Debug)]
+pub(crate) struct Download {
+    dir: PathBuf,
+    pub path: PathBuf,
+}
+
+impl Download {
+    /// Fetch `url`, its progress shown as a line of `progress`.
+    pub(crate) fn fetch(url: &str, progress: &MultiProgress) -> Result<Self> {
+        Self::fetch_with(url, &std::env::temp_dir(), |url, dest| {
+            http_get(url, dest, progress)
+        })
+    }
+
+    /// Create a fresh directory under `temp_root` and let `fetch` write the
+    /// file into it. On failure the directory is already gone.
+    fn fetch_with<F>(url: &str, temp_root: &Path, fetch: F) -> Result<Self>
+    where
+        F: FnOnce(&str, &Path) -> Result<()>,
+    {
+        let name =
+            url_file_name(url).with_context(|| format!("Cannot tell the file name of {}", url))?;
+        let dir = crate::create_temp_dir(temp_root, "un7z-download")?;
+        let download = Download {
+            path: dir.join(name),
+            dir,
+        };
+        fetch(url, &download.path)?;
+        Ok(download)
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            log::warn!("Cannot remove download {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+/// Run `fetch` on every URL, at most `max_concurrent` at a time, and return
+/// each URL's result in URL order. A failure doesn't keep the rest from
+/// being fetched.
+pub(crate) fn fetch_all<T, F>(urls: &[String], max_concurrent: usize, fetch: F) -> Vec<Result<T>>
+where
+    T: Send,
+    F: Fn(&str) -> Result<T> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<T>>>> = Mutex::new(urls.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..max_concurrent.clamp(1, urls.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(url) = urls.get(i) else {
+                    break;
+                };
+                let result = fetch(url);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    // Every worker runs until the URLs are used up, so every slot is filled
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every URL is fetched"))
+        .collect()
+}
+
+/// Download `url` to `dest`, showing the bytes received so far. Redirects
+/// are followed; an error status fails the download.
+fn http_get(url: &str, dest: &Path, progress: &MultiProgress) -> Result<()> {
+    let response = ureq::get(url).call().context("Download failed")?;
+
+    let bar = progress.add(ProgressBar::new_spinner());
+    bar.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.cyan} {msg} {bytes} ({bytes_per_sec})")
+            .unwrap(),
+    );
+    bar.set_message(format!("Downloading {}", url));
+    bar.enable_steady_tick(Duration::from_millis(100));
+    let mut file =
+        fs::File::create_new(dest).with_context(|| format!("Cannot create {}", dest.display()))?;
+    let copied = std::io::copy(&mut bar.wrap_read(response.into_reader()), &mut file);
+    bar.finish_and_clear();
+    copied.with_context(|| format!("Download of {} failed", url))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_url_and_file_name() {
+        assert!(is_url(Path::new("https://example.com/file.7z.001")));
+        assert!(is_url(Path::new("HTTP://example.com/a.tgz")));
+        assert!(!is_url(Path::new("ftp://example.com/a.tgz")));
+        assert!(!is_url(Path::new("https.7z.001")));
+        assert!(!is_url(Path::new("/data/http://x")));
+
+        let name = url_file_name;
+        assert_eq!(
+            name("https://example.com/d/file.7z.001").unwrap(),
+            "file.7z.001"
+        );
+        assert_eq!(
+            name("https://example.com/My%20Movie.tgz?token=a/b#top").unwrap(),
+            "My Movie.tgz"
+        );
+        assert_eq!(name("https://example.com/100%.tgz").unwrap(), "100%.tgz");
+        assert_eq!(name("https://example.com/"), None);
+        assert_eq!(name("https://example.com"), None);
+        assert_eq!(name("https://example.com/a/%2E%2E"), None);
+        assert_eq!(name("https://example.com/a%2Fb.tgz"), None);
+    }
+
+    #[test]
+    fn test_download_removed_on_drop() {
+        let temp = tempfile::tempdir().unwrap();
+        let download =
+            Download::fetch_with("https://example.com/data.tgz", temp.path(), |_, dest| {
+                fs::write(dest, "archive").map_err(Into::into)
+            })
+            .unwrap();
+        assert_eq!(download.path.file_name().unwrap(), "data.tgz");
+        assert_eq!(fs::read_to_string(&download.path).unwrap(), "archive");
+        let dir = download.path.parent().unwrap().to_path_buf();
+        assert!(dir.starts_with(temp.path()));
+
+        drop(download);
+        assert!(!dir.exists());
+
+        // Every download gets a directory of its own, created fresh
+        let fetch = |_: &str, dest: &Path| fs::write(dest, "archive").map_err(Into::into);
+        let first = Download::fetch_with("https://example.com/a.tgz", temp.path(), fetch).unwrap();
+        let second = Download::fetch_with("https://example.com/a.tgz", temp.path(), fetch).unwrap();
+        assert_ne!(first.path, second.path);
+        drop((first, second));
+
+        // A failed download leaves nothing behind either
+        let failed =
+            Download::fetch_with("https://example.com/data.tgz", temp.path(), |_, dest| {
+                fs::write(dest, "partial")?;
+                anyhow::bail!("connection reset")
+            });
+        assert!(failed.is_err());
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_fetch_all_bounds_concurrency() {
+        let urls: Vec<String> = (1..=6)
+            .map(|n| format!("https://example.com/{}.tgz", n))
+            .collect();
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let fetch = |url: &str| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(30));
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(url_file_name(url).unwrap())
+        };
+        let names: Vec<_> = fetch_all(&urls, 2, fetch)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            names,
+            ["1.tgz", "2.tgz", "3.tgz", "4.tgz", "5.tgz", "6.tgz"]
+        );
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+
+        most.store(0, Ordering::SeqCst);
+        fetch_all(&urls, 1, fetch);
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+        assert!(fetch_all(&[], 3, fetch).is_empty());
+
+        // A failure is only that URL's result; the rest are still fetched
+        let started = AtomicUsize::new(0);
+        let failing = |url: &str| {
+            started.fetch_add(1, Ordering::SeqCst);
+            if url.ends_with("/2.tgz") {
+                anyhow::bail!("404 for {}", url);
+            }
+            Ok(())
+        };
+        let results = fetch_all(&urls, 1, failing);
+        assert_eq!(started.load(Ordering::SeqCst), 6);
+        let failed: Vec<_> = results
+            .iter()
+            .enumerate()
+            .filter_map(|(i, result)| Some((i, result.as_ref().err()?.to_string())))
+            .collect();
+        assert_eq!(
+            failed,
+            [(1, "404 for https://example.com/2.tgz".to_string())]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! `--events PATH`: a newline-delimited JSON stream of what happens during a
+//! run, one event per line, for log shippers and other tooling.
+//!
+//! Each line is flushed as soon as it is written, so a reader tailing the
+//! file sees events as they happen.
+
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Something that happened during a run.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event<'a> {
+    /// Archives were found (or read from the command line / stdin).
+    ScanComplete {
+        archives: usize,
+    },
+    ArchiveStarted {
+        archive: &'a Path,
+        name: &'a str,
+    },
+    /// The backend reported a new percentage.
+    ArchiveProgress {
+        archive: &'a Path,
+        percent: u64,
+    },
+    ArchiveFinished {
+        archive: &'a Path,
+        /// extracted, tested, quick_checked, previewed, skipped, incomplete or failed
+        outcome: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dir: Option<&'a Path>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// An `archive_finished` event read back from a log (`--show-report`).
+#[derive(Debug, Deserialize)]
+pub(crate) struct Finished {
+    pub archive: PathBuf,
+    pub outcome: String,
+    pub dir: Option<PathBuf>,
+    pub error: Option<String>,
+}
+
+/// The `archive_finished` events of an event log, in order. Other events and
+/// blank lines are skipped; a line that isn't JSON is an error.
+pub(crate) fn read_finished(reader: impl BufRead) -> Result<Vec<Finished>> {
+    let mut finished = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = serde_json::from_str(&line)
+            .with_context(|| format!("Line {} of the report is not JSON", number + 1))?;
+        if value["event"] == "archive_finished" {
+            let record = serde_json::from_value(value)
+                .with_context(|| format!("Line {} of the report is malformed", number + 1))?;
+            finished.push(record);
+        }
+    }
+    Ok(finished)
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts: String,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Where events go; each one is written as a line of JSON.
+pub(crate) struct EventLog {
+    path: PathBuf,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl EventLog {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Cannot create event log {}", path.display()))?;
+        Ok(Self::from_writer(path, Box::new(file)))
+    }
+
+    fn from_writer(path: &Path, out: Box<dyn Write + Send>) -> Self {
+        EventLog {
+            path: path.to_path_buf(),
+            out: Mutex::new(out),
+        }
+    }
+
+    /// Write one event. Failures are logged, never fatal to the run.
+    pub(crate) fn emit(&self, event: &Event) {
+        let line = Line {
+            ts: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            event,
+        };
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        let written = serde_json::to_writer(&mut *out, &line)
+            .map_err(std::io::Error::from)
+            .and_then(|()| out.write_all(b"\n"))
+            .and_then(|()| out.flush());
+        if let Err(e) = written {
+            log::warn!("Cannot write event to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// A writer the test can read back, counting flushes.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<(Vec<u8>, usize)>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.lock().unwrap().1 += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_events_are_json_lines() {
+        let shared = Shared::default();
+        let log = EventLog::from_writer(Path::new("events.ndjson"), Box::new(shared.clone()));
+        let archive = Path::new("/data/movie.7z.001");
+
+        log.emit(&Event::ScanComplete { archives: 2 });
+        log.emit(&Event::ArchiveStarted {
+            archive,
+            name: "movie",
+        });
+        log.emit(&Event::ArchiveProgress {
+            archive,
+            percent: 42,
+        });
+        log.emit(&Event::ArchiveFinished {
+            archive,
+            outcome: "extracted",
+            dir: Some(Path::new("/data/movie")),
+            error: None,
+        });
+        log.emit(&Event::ArchiveFinished {
+            archive,
+            outcome: "failed",
+            dir: None,
+            error: Some("Wrong password".to_string()),
+        });
+
+        let (bytes, flushes) = shared.0.lock().unwrap().clone();
+        assert_eq!(flushes, 5);
+        let text = String::from_utf8(bytes).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 5);
+
+        let kinds: Vec<_> = lines.iter().map(|l| l["event"].as_str().unwrap()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "scan_complete",
+                "archive_started",
+                "archive_progress",
+                "archive_finished",
+                "archive_finished"
+            ]
+        );
+        for line in &lines {
+            let ts = line["ts"].as_str().unwrap();
+            assert!(chrono::DateTime::parse_from_rfc3339(ts).is_ok(), "{}", ts);
+        }
+        assert_eq!(lines[0]["archives"], 2);
+        assert_eq!(lines[1]["archive"], "/data/movie.7z.001");
+        assert_eq!(lines[1]["name"], "movie");
+        assert_eq!(lines[2]["percent"], 42);
+        assert_eq!(lines[3]["outcome"], "extracted");
+        assert_eq!(lines[3]["dir"], "/data/movie");
+        assert!(lines[3].get("error").is_none());
+        assert_eq!(lines[4]["error"], "Wrong password");
+        assert!(lines[4].get("dir").is_none());
+    }
+}
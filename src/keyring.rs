@@ -0,0 +1,87 @@
+//! `--keyring-use` / `--keyring-store`: archive passwords kept in the system
+//! keyring rather than on the command line or in a file, one entry per
+//! archive base name under the service `un7z`.
+//!
+//! The keyring is the platform's own store (Secret Service on Linux and the
+//! BSDs, the login keychain on macOS), reached through the `keyring` crate.
+
+use anyhow::{Context, Result};
+
+/// The service every un7z entry is filed under.
+const SERVICE: &str = "un7z";
+
+/// Where one archive set's password is kept.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub(crate) service: String,
+    pub(crate) account: String,
+}
+
+impl Entry {
+    /// The entry for an archive set, named by its base name (`movie` for
+    /// `movie.part01.rar`), so all volumes and later downloads share it.
+    pub(crate) fn for_base_name(base_name: &str) -> Self {
+        Entry {
+            service: SERVICE.to_string(),
+            account: base_name.trim().to_string(),
+        }
+    }
+
+    fn open(&self) -> Result<::keyring::Entry> {
+        ::keyring::Entry::new(&self.service, &self.account)
+            .with_context(|| format!("Cannot open keyring entry for {}", self.account))
+    }
+}
+
+/// The stored password for `entry`, None if there is none.
+pub(crate) fn get(entry: &Entry) -> Result<Option<String>> {
+    password_of(&entry.open()?)
+}
+
+/// Store `password` for `entry`, replacing what was there.
+pub(crate) fn set(entry: &Entry, password: &str) -> Result<()> {
+    entry.open()?.set_password(password).with_context(|| {
+        format!(
+            "Cannot store the password for {} in the keyring",
+            entry.account
+        )
+    })
+}
+
+fn password_of(stored: &::keyring::Entry) -> Result<Option<String>> {
+    match stored.get_password() {
+        Ok(password) => Ok(Some(password)),
+        Err(::keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e).context("Cannot read the keyring"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_naming() {
+        let entry = Entry::for_base_name("movie ");
+        assert_eq!(entry.service, "un7z");
+        assert_eq!(entry.account, "movie");
+        assert_eq!(entry, Entry::for_base_name("movie"));
+    }
+
+    #[test]
+    fn test_keyring_with_mock_store() {
+        use ::keyring::mock::{self, MockCredential};
+        ::keyring::set_default_credential_builder(mock::default_credential_builder());
+
+        let stored = Entry::for_base_name("movie").open().unwrap();
+        assert_eq!(password_of(&stored).unwrap(), None);
+        stored.set_password("s3cret pw").unwrap();
+        assert_eq!(password_of(&stored).unwrap().as_deref(), Some("s3cret pw"));
+
+        // A keyring that can't be read is an error, not a missing password
+        let mock: &MockCredential = stored.get_credential().downcast_ref().unwrap();
+        mock.set_error(::keyring::Error::NoStorageAccess("locked".into()));
+        let error = password_of(&stored).unwrap_err();
+        assert!(format!("{:#}", error).contains("Cannot read the keyring"));
+    }
+}
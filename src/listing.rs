@@ -0,0 +1,844 @@
+//! Archive contents as reported by the backends' listing commands, and
+//! extraction of selected entries only.
+
+use crate::{password_args, Archive, ArchiveType, BackendError, OutputTail};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// One entry of an archive listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub path: String,
+    pub is_dir: bool,
+    /// Unpacked size, when the listing gives one (`tar tzf` doesn't).
+    pub size: Option<u64>,
+}
+
+/// Command printing a parseable listing: `7zz l -slt`, `unrar lt` or `tar tzf`.
+fn listing_command(archive: &Archive, password: &Option<String>) -> Result<Command> {
+    let cmd = match &archive.archive_type {
+        ArchiveType::SevenZip | ArchiveType::Zip => {
+            let mut cmd = Command::new("7zz");
+            cmd.arg("l")
+                .arg("-slt")
+                .args(password_args(&archive.archive_type, password)?);
+            cmd.arg(&archive.path);
+            cmd
+        }
+        ArchiveType::Rar => {
+            let mut cmd = Command::new("unrar");
+            cmd.arg("lt")
+                .args(password_args(&archive.archive_type, password)?);
+            cmd.arg(&archive.path);
+            cmd
+        }
+        ArchiveType::TarGz => {
+            let mut cmd = Command::new("tar");
+            cmd.arg("tzf").arg(&archive.path);
+            cmd
+        }
+        ArchiveType::Custom(custom) => {
+            anyhow::bail!("Cannot list custom type .{}", custom.extension)
+        }
+    };
+    Ok(cmd)
+}
+
+/// Parse `7zz l -slt`: `Key = value` blocks, one per entry, after the `----------`
+/// line (the blocks before it describe the archive itself).
+pub(crate) fn parse_7z_slt(listing: &str) -> Vec<Entry> {
+    let Some((_, body)) = listing.split_once("\n----------") else {
+        return Vec::new();
+    };
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    for line in body.lines() {
+        if let Some(path) = line.strip_prefix("Path = ") {
+            entries.extend(current.take());
+            current = Some(Entry {
+                path: path.to_string(),
+                is_dir: false,
+                size: None,
+            });
+        } else if let (Some(entry), Some(size)) = (&mut current, line.strip_prefix("Size = ")) {
+            entry.size = size.trim().parse().ok();
+        } else if let (Some(entry), Some(flag)) = (&mut current, line.strip_prefix("Folder = ")) {
+            entry.is_dir = flag.trim() == "+";
+        } else if let (Some(entry), Some(attributes)) =
+            (&mut current, line.strip_prefix("Attributes = "))
+        {
+            entry.is_dir |= attributes.starts_with('D');
+        }
+    }
+    entries.extend(current);
+    entries
+}
+
+/// The ciphers of the encrypted entries in a `7zz l -slt` listing, each once:
+/// `ZipCrypto` or `AES-256` for ZIP, `7zAES` for 7z (from `Method = ...`).
+pub(crate) fn parse_7z_ciphers(listing: &str) -> Vec<String> {
+    let Some((_, body)) = listing.split_once("\n----------") else {
+        return Vec::new();
+    };
+    let mut ciphers: Vec<String> = Vec::new();
+    for block in body.split("\nPath = ") {
+        if slt_field(block, "Encrypted") != Some("+") {
+            continue;
+        }
+        let method = slt_field(block, "Method").unwrap_or_default();
+        let found = method.split([' ', ':']).filter(|part| is_cipher(part));
+        for cipher in found {
+            if !ciphers.iter().any(|c| c == cipher) {
+                ciphers.push(cipher.to_string());
+            }
+        }
+    }
+    ciphers
+}
+
+/// The value of `key = value` in one block of a `7zz l -slt` listing.
+fn slt_field<'a>(block: &'a str, key: &str) -> Option<&'a str> {
+    block.lines().find_map(|line| {
+        let (k, value) = line.split_once(" = ")?;
+        (k == key).then(|| value.trim())
+    })
+}
+
+/// An encryption step in a 7zz method chain (`7zAES`, `AES-256`, `ZipCrypto`).
+fn is_cipher(method: &str) -> bool {
+    method.starts_with("ZipCrypto") || method.contains("AES")
+}
+
+/// Legacy ZIP encryption, broken by known-plaintext attacks.
+pub(crate) fn is_weak_cipher(cipher: &str) -> bool {
+    cipher.starts_with("ZipCrypto")
+}
+
+/// The ciphers a ZIP's entries use. Entry headers aren't encrypted in ZIP, so
+/// this lists with the empty password.
+pub(crate) fn zip_ciphers(archive: &Archive) -> Result<Vec<String>> {
+    let output = listing_command(archive, &Some(String::new()))?
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run backend")?;
+    Ok(parse_7z_ciphers(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Archive-level facts for `--probe`, read from the backend's listing.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Probe {
+    pub encrypted: bool,
+    pub solid: bool,
+    /// Total size of the files once extracted.
+    pub unpacked_size: u64,
+    /// Files, not counting directories.
+    pub files: usize,
+    /// Compression methods, without parameters or encryption.
+    pub methods: Vec<String>,
+}
+
+impl Probe {
+    fn add_method(&mut self, method: &str) {
+        if !method.is_empty() && !self.methods.iter().any(|m| m == method) {
+            self.methods.push(method.to_string());
+        }
+    }
+
+    fn add_file(&mut self, size: Option<&str>) {
+        self.files += 1;
+        self.unpacked_size += size.and_then(|s| s.trim().parse::<u64>().ok()).unwrap_or(0);
+    }
+}
+
+/// Probe a `7zz l -slt` listing: `Solid` and (for 7z) `Method` from the
+/// archive block, sizes, encryption and (for zip) methods from the entries.
+pub(crate) fn parse_7z_probe(listing: &str) -> Probe {
+    let mut probe = Probe::default();
+    let Some((header, body)) = listing.split_once("\n----------") else {
+        return probe;
+    };
+    let header = header
+        .rsplit_once("\n--\n")
+        .map_or(header, |(_, archive)| archive);
+    probe.solid = slt_field(header, "Solid") == Some("+");
+    let chain_methods = |probe: &mut Probe, chain: &str| {
+        for step in chain.split_whitespace().filter(|step| !is_cipher(step)) {
+            probe.add_method(step.split(':').next().unwrap_or(step));
+        }
+    };
+    if let Some(chain) = slt_field(header, "Method") {
+        chain_methods(&mut probe, chain);
+    }
+    let per_entry = probe.methods.is_empty();
+    for block in body.split("\nPath = ").skip(1) {
+        let is_dir = slt_field(block, "Folder") == Some("+")
+            || slt_field(block, "Attributes").is_some_and(|a| a.starts_with('D'));
+        if is_dir {
+            continue;
+        }
+        probe.add_file(slt_field(block, "Size"));
+        probe.encrypted |= slt_field(block, "Encrypted") == Some("+");
+        if per_entry {
+            chain_methods(&mut probe, slt_field(block, "Method").unwrap_or_default());
+        }
+    }
+    probe
+}
+
+/// Probe an `unrar lt` listing: `Details:` says solid (and encrypted
+/// headers), each file block its `Size:`, `Compression:` and `Flags:`.
+pub(crate) fn parse_unrar_probe(listing: &str) -> Probe {
+    let mut probe = Probe::default();
+    let mut is_file = false;
+    for line in listing.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once(": ") else {
+            continue;
+        };
+        match key {
+            "Details" => {
+                let details: Vec<&str> = value.split(',').map(str::trim).collect();
+                probe.solid = details.contains(&"solid");
+                probe.encrypted |= details.iter().any(|d| d.starts_with("encrypted"));
+            }
+            "Type" => is_file = value == "File",
+            "Size" if is_file => probe.add_file(Some(value)),
+            "Compression" if is_file => {
+                // `RAR 5.0(v50) -m3 -md=4M` -> `RAR 5.0 -m3`
+                let format = value.split('(').next().unwrap_or(value).trim();
+                let level = value.split_whitespace().find(|t| {
+                    t.strip_prefix("-m")
+                        .is_some_and(|l| l.bytes().all(|b| b.is_ascii_digit()) && !l.is_empty())
+                });
+                match level {
+                    Some(level) => probe.add_method(&format!("{} {}", format, level)),
+                    None => probe.add_method(format),
+                }
+            }
+            "Flags" if is_file => {
+                probe.encrypted |= value.split_whitespace().any(|f| f == "encrypted")
+            }
+            _ => {}
+        }
+    }
+    probe
+}
+
+/// Probe a `tar tzvf` listing: `mode owner size date time path` per entry.
+/// A gzip stream is always solid and never encrypted.
+pub(crate) fn parse_tar_probe(listing: &str) -> Probe {
+    let mut probe = Probe {
+        solid: true,
+        methods: vec!["gzip".to_string()],
+        ..Default::default()
+    };
+    for line in listing.lines().filter(|line| line.starts_with('-')) {
+        probe.add_file(line.split_whitespace().nth(2));
+    }
+    probe
+}
+
+/// Run the backend's listing and probe it.
+pub(crate) fn probe(archive: &Archive, password: &Option<String>) -> Result<Probe> {
+    let mut cmd = match archive.archive_type {
+        ArchiveType::TarGz => {
+            let mut cmd = Command::new("tar");
+            cmd.arg("tzvf").arg(&archive.path);
+            cmd
+        }
+        _ => listing_command(archive, password)?,
+    };
+    let output = cmd
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run backend")?;
+    if !output.status.success() {
+        let reason = crate::exit_status_reason(output.status);
+        anyhow::bail!("Cannot list archive: {}", reason);
+    }
+    let listing = String::from_utf8_lossy(&output.stdout);
+    Ok(match archive.archive_type {
+        ArchiveType::SevenZip | ArchiveType::Zip => parse_7z_probe(&listing),
+        ArchiveType::Rar => parse_unrar_probe(&listing),
+        ArchiveType::TarGz | ArchiveType::Custom(_) => parse_tar_probe(&listing),
+    })
+}
+
+/// Parse `unrar lt`: blocks of `Name: ...` followed by `Type: File|Directory`.
+pub(crate) fn parse_unrar_lt(listing: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    for line in listing.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix("Name: ") {
+            entries.extend(current.take());
+            current = Some(Entry {
+                path: name.to_string(),
+                is_dir: false,
+                size: None,
+            });
+        } else if let (Some(entry), Some(kind)) = (&mut current, line.strip_prefix("Type: ")) {
+            entry.is_dir = kind.trim() == "Directory";
+        } else if let (Some(entry), Some(size)) = (&mut current, line.strip_prefix("Size: ")) {
+            entry.size = size.trim().parse().ok();
+        }
+    }
+    entries.extend(current);
+    entries
+}
+
+/// Parse `tar tzf`: one path per line, directories end in `/`.
+pub(crate) fn parse_tar(listing: &str) -> Vec<Entry> {
+    listing
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| Entry {
+            path: line.to_string(),
+            is_dir: line.ends_with('/'),
+            size: None,
+        })
+        .collect()
+}
+
+/// Run the backend's listing and parse it.
+pub(crate) fn list_entries(archive: &Archive, password: &Option<String>) -> Result<Vec<Entry>> {
+    let output = listing_command(archive, password)?
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run backend")?;
+    if !output.status.success() {
+        let mut tail = OutputTail::default();
+        tail.push(&output.stdout);
+        tail.push(&output.stderr);
+        let err = BackendError {
+            reason: crate::exit_status_reason(output.status),
+            output: tail.into_lines(),
+        };
+        return Err(anyhow::Error::new(err).context("Cannot list archive"));
+    }
+    let listing = String::from_utf8_lossy(&output.stdout);
+    Ok(match archive.archive_type {
+        ArchiveType::SevenZip | ArchiveType::Zip => parse_7z_slt(&listing),
+        ArchiveType::Rar => parse_unrar_lt(&listing),
+        ArchiveType::TarGz | ArchiveType::Custom(_) => parse_tar(&listing),
+    })
+}
+
+/// How an extracted tree differs from its archive's listing.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Mismatch {
+    Missing(String),
+    WrongSize {
+        path: String,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mismatch::Missing(path) => write!(f, "{} is missing", path),
+            Mismatch::WrongSize {
+                path,
+                expected,
+                actual,
+            } => write!(f, "{} is {} bytes, expected {}", path, actual, expected),
+        }
+    }
+}
+
+/// `--verify-extraction`: check that every listed entry exists under `root`,
+/// and that files have their listed size where the listing gives one.
+/// Symlinks are only checked for existence.
+pub(crate) fn compare_to_tree(entries: &[Entry], root: &Path) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for entry in entries {
+        let relative = entry.path.trim_end_matches('/');
+        if relative.is_empty() {
+            continue;
+        }
+        let Ok(meta) = std::fs::symlink_metadata(root.join(relative)) else {
+            mismatches.push(Mismatch::Missing(relative.to_string()));
+            continue;
+        };
+        match entry.size {
+            Some(expected) if !entry.is_dir && meta.is_file() && meta.len() != expected => {
+                mismatches.push(Mismatch::WrongSize {
+                    path: relative.to_string(),
+                    expected,
+                    actual: meta.len(),
+                });
+            }
+            _ => {}
+        }
+    }
+    mismatches
+}
+
+/// Paths of the first `n` files (directories don't count) in listing order.
+pub(crate) fn first_files(entries: &[Entry], n: usize) -> Vec<String> {
+    entries
+        .iter()
+        .filter(|entry| !entry.is_dir)
+        .take(n)
+        .map(|entry| entry.path.clone())
+        .collect()
+}
+
+/// Test only the entry `name` (literal, as listed) with the backend.
+fn test_entry_command(archive: &Archive, password: &Option<String>, name: &str) -> Result<Command> {
+    let mut cmd = match &archive.archive_type {
+        ArchiveType::SevenZip | ArchiveType::Zip => {
+            let mut cmd = Command::new("7zz");
+            cmd.arg("t")
+                .args(password_args(&archive.archive_type, password)?);
+            cmd.arg("-spd");
+            cmd
+        }
+        ArchiveType::Rar => {
+            let mut cmd = Command::new("unrar");
+            cmd.arg("t")
+                .args(password_args(&archive.archive_type, password)?);
+            cmd
+        }
+        ArchiveType::TarGz | ArchiveType::Custom(_) => {
+            anyhow::bail!(
+                "{} archives have no password",
+                archive.archive_type.as_str()
+            )
+        }
+    };
+    cmd.arg("--").arg(&archive.path).arg(name);
+    Ok(cmd)
+}
+
+/// Whether `password` opens the archive: it lists with it, and the smallest
+/// non-empty file tests OK, which is cheap even for a large archive.
+pub(crate) fn password_works(archive: &Archive, password: &str) -> bool {
+    let password = Some(password.to_string());
+    let Ok(entries) = list_entries(archive, &password) else {
+        return false;
+    };
+    let probe = entries
+        .iter()
+        .filter(|entry| !entry.is_dir)
+        .min_by_key(|entry| (entry.size == Some(0), entry.size));
+    let Some(entry) = probe else {
+        return true;
+    };
+    test_entry_command(archive, &password, &entry.path)
+        .and_then(|mut cmd| {
+            let status = cmd
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()?;
+            Ok(status.success())
+        })
+        .unwrap_or(false)
+}
+
+/// Extract only `names` (exact paths from the listing, no wildcards) into `out_dir`.
+pub(crate) fn extract_only_command(
+    archive: &Archive,
+    password: &Option<String>,
+    out_dir: &Path,
+    names: &[String],
+) -> Result<Command> {
+    let cmd = match &archive.archive_type {
+        ArchiveType::SevenZip | ArchiveType::Zip => {
+            let mut out = std::ffi::OsString::from("-o");
+            out.push(out_dir);
+            let mut cmd = Command::new("7zz");
+            // -spd: names are literal, not wildcards
+            cmd.arg("x")
+                .arg("-y")
+                .args(password_args(&archive.archive_type, password)?);
+            cmd.arg(out)
+                .arg("-spd")
+                .arg("--")
+                .arg(&archive.path)
+                .args(names);
+            cmd
+        }
+        ArchiveType::Rar => {
+            let mut out = std::ffi::OsString::from(out_dir);
+            out.push("/");
+            let mut cmd = Command::new("unrar");
+            cmd.arg("x")
+                .arg("-y")
+                .args(password_args(&archive.archive_type, password)?);
+            cmd.arg("--").arg(&archive.path).args(names).arg(out);
+            cmd
+        }
+        ArchiveType::TarGz => {
+            let mut cmd = Command::new("tar");
+            cmd.arg("xzf")
+                .arg(&archive.path)
+                .arg("-C")
+                .arg(out_dir)
+                .arg("--")
+                .args(names);
+            cmd
+        }
+        ArchiveType::Custom(custom) => {
+            anyhow::bail!(
+                "Cannot extract single entries of custom type .{}",
+                custom.extension
+            )
+        }
+    };
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_probe_from_7z_listing() {
+        let listing = "\
+7-Zip (z) 23.01 (x64)
+
+Listing archive: data.7z
+
+--
+Path = data.7z
+Type = 7z
+Physical Size = 2048
+Headers Size = 210
+Method = LZMA2:24 BCJ 7zAES
+Solid = +
+Blocks = 1
+
+----------
+Path = movie
+Size = 0
+Attributes = D
+Encrypted = -
+Method = 
+
+Path = movie/a.mkv
+Size = 1000
+Attributes = A
+Encrypted = +
+Method = LZMA2:24 BCJ 7zAES
+
+Path = movie/b.srt
+Size = 24
+Attributes = A
+Encrypted = +
+Method = LZMA2:24 BCJ 7zAES
+";
+        assert_eq!(
+            parse_7z_probe(listing),
+            Probe {
+                encrypted: true,
+                solid: true,
+                unpacked_size: 1024,
+                files: 2,
+                methods: vec!["LZMA2".to_string(), "BCJ".to_string()],
+            }
+        );
+
+        // Zip: no archive-level method, never solid
+        let zip = "\
+--
+Path = docs.zip
+Type = zip
+Physical Size = 300
+
+----------
+Path = a.txt
+Folder = -
+Size = 10
+Encrypted = -
+Method = Deflate
+
+Path = b.bin
+Folder = -
+Size = 5
+Encrypted = -
+Method = Store
+";
+        let probe = parse_7z_probe(zip);
+        assert!(!probe.solid && !probe.encrypted);
+        assert_eq!((probe.files, probe.unpacked_size), (2, 15));
+        assert_eq!(probe.methods, ["Deflate", "Store"]);
+    }
+
+    #[test]
+    fn test_probe_from_unrar_and_tar_listings() {
+        let listing = "\
+UNRAR 6.24 freeware      Copyright (c) 1993-2023 Alexander Roshal
+
+Archive: data.part01.rar
+Details: RAR 5, solid, volume
+
+        Name: movie/a.mkv
+        Type: File
+        Size: 1048576
+ Packed size: 524288
+       Ratio: 50%
+  Attributes: -rw-r--r--
+ Compression: RAR 5.0(v50) -m3 -md=4M
+       Flags: encrypted
+
+        Name: movie
+        Type: Directory
+ Compression: RAR 5.0(v50) -m0 -md=0K
+";
+        assert_eq!(
+            parse_unrar_probe(listing),
+            Probe {
+                encrypted: true,
+                solid: true,
+                unpacked_size: 1048576,
+                files: 1,
+                methods: vec!["RAR 5.0 -m3".to_string()],
+            }
+        );
+        let plain = listing
+            .replace("solid, ", "")
+            .replace("Flags: encrypted", "Flags:");
+        let probe = parse_unrar_probe(&plain);
+        assert!(!probe.solid && !probe.encrypted);
+
+        let tar = "\
+drwxr-xr-x user/user         0 2024-01-01 12:00 movie/
+-rw-r--r-- user/user      1000 2024-01-01 12:00 movie/a.mkv
+-rw-r--r-- user/user        24 2024-01-01 12:00 movie/b srt file
+";
+        let probe = parse_tar_probe(tar);
+        assert_eq!((probe.files, probe.unpacked_size), (2, 1024));
+        assert!(probe.solid && !probe.encrypted);
+    }
+
+    #[test]
+    fn test_compare_listing_to_tree() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("movie/subs")).unwrap();
+        fs::write(root.join("movie/a.mkv"), [0u8; 1000]).unwrap();
+        fs::write(root.join("movie/b.nfo"), "short").unwrap();
+
+        let listing = "\
+----------
+Path = movie
+Size = 0
+Folder = +
+
+Path = movie/subs
+Folder = +
+
+Path = movie/a.mkv
+Size = 1000
+Folder = -
+
+Path = movie/b.nfo
+Size = 24
+Folder = -
+
+Path = movie/subs/en.srt
+Size = 10
+Folder = -
+";
+        let entries = parse_7z_slt(&format!("\n{}", listing));
+        assert_eq!(entries[2].size, Some(1000));
+        let mismatches = compare_to_tree(&entries, root);
+        assert_eq!(
+            mismatches,
+            [
+                Mismatch::WrongSize {
+                    path: "movie/b.nfo".to_string(),
+                    expected: 24,
+                    actual: 5,
+                },
+                Mismatch::Missing("movie/subs/en.srt".to_string()),
+            ]
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            "movie/b.nfo is 5 bytes, expected 24"
+        );
+
+        // Without sizes (tar), only presence counts
+        let tar = parse_tar("movie/\nmovie/a.mkv\nmovie/b.nfo\n");
+        assert!(compare_to_tree(&tar, root).is_empty());
+    }
+
+    #[test]
+    fn test_cipher_from_7z_listing() {
+        let listing = "\
+Listing archive: secret.zip
+
+--
+Path = secret.zip
+Type = zip
+Physical Size = 2048
+
+----------
+Path = docs
+Folder = +
+Encrypted = -
+Method = Store
+
+Path = docs/a.txt
+Folder = -
+Encrypted = +
+Method = ZipCrypto Deflate
+
+Path = docs/b.txt
+Folder = -
+Encrypted = +
+Method = ZipCrypto Store
+
+Path = docs/c.txt
+Folder = -
+Encrypted = +
+Method = AES-256 Deflate
+
+Path = readme.txt
+Folder = -
+Encrypted = -
+Method = Deflate
+";
+        let ciphers = parse_7z_ciphers(listing);
+        assert_eq!(ciphers, ["ZipCrypto", "AES-256"]);
+        assert!(is_weak_cipher(&ciphers[0]));
+        assert!(!is_weak_cipher(&ciphers[1]));
+
+        let seven = "----------\nPath = a.bin\nEncrypted = +\nMethod = LZMA2:24 7zAES:19\n";
+        assert_eq!(parse_7z_ciphers(&format!("\n{}", seven)), ["7zAES"]);
+        let plain = listing.replace("Encrypted = +", "Encrypted = -");
+        assert!(parse_7z_ciphers(&plain).is_empty());
+    }
+
+    #[test]
+    fn test_first_files_from_7z_listing() {
+        let listing = "\
+7-Zip (z) 23.01 (x64)
+
+Listing archive: movie.7z.001
+
+--
+Path = movie.7z.001
+Type = Split
+Physical Size = 1048576
+
+----------
+Path = Movie
+Size = 0
+Folder = +
+Attributes = D
+
+Path = Movie/cover.jpg
+Size = 53021
+Folder = -
+Attributes = A
+
+Path = Movie/part one.mkv
+Size = 734003200
+Folder = -
+
+Path = Movie/Extras
+Attributes = D_ -rwxr-xr-x
+
+Path = Movie/Extras/trailer.mkv
+Size = 1000
+";
+        let entries = parse_7z_slt(listing);
+        assert_eq!(entries.len(), 5);
+        assert!(entries[0].is_dir && entries[3].is_dir);
+        assert_eq!(
+            first_files(&entries, 2),
+            ["Movie/cover.jpg", "Movie/part one.mkv"]
+        );
+        assert_eq!(first_files(&entries, 10).len(), 3);
+        assert!(first_files(&entries, 0).is_empty());
+    }
+
+    #[test]
+    fn test_unrar_and_tar_listings() {
+        let listing = "\
+Archive: show.part01.rar
+Details: RAR 5, volume
+
+        Name: Show
+        Type: Directory
+
+        Name: Show/e01.mkv
+        Type: File
+        Size: 1234
+
+        Name: Show/e02.mkv
+        Type: File
+";
+        let entries = parse_unrar_lt(listing);
+        assert_eq!(first_files(&entries, 5), ["Show/e01.mkv", "Show/e02.mkv"]);
+
+        let entries = parse_tar("docs/\ndocs/a.txt\ndocs/b.txt\n");
+        assert_eq!(first_files(&entries, 1), ["docs/a.txt"]);
+    }
+
+    #[test]
+    fn test_extract_only_first_entries_of_tarball() {
+        let temp = tempfile::tempdir().unwrap();
+        let src = temp.path().join("src");
+        fs::create_dir_all(src.join("docs")).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(src.join("docs").join(name), name).unwrap();
+        }
+        let tarball = temp.path().join("docs.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(&src)
+            .args(["docs/a.txt", "docs/b.txt", "docs/c.txt"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let archive = Archive::new(tarball).unwrap();
+        let names = first_files(&list_entries(&archive, &None).unwrap(), 2);
+        assert_eq!(names, ["docs/a.txt", "docs/b.txt"]);
+
+        let out = temp.path().join("docs.preview");
+        fs::create_dir(&out).unwrap();
+        let status = extract_only_command(&archive, &None, &out, &names)
+            .unwrap()
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(out.join("docs/a.txt").exists());
+        assert!(out.join("docs/b.txt").exists());
+        assert!(!out.join("docs/c.txt").exists());
+    }
+
+    #[test]
+    fn test_extract_only_command_keeps_names_literal() {
+        let archive = Archive::new(PathBuf::from("/dl/a.7z.001")).unwrap();
+        let names = vec!["dir/*.txt".to_string()];
+        let cmd = extract_only_command(&archive, &None, Path::new("/out"), &names).unwrap();
+        let args: Vec<_> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "x",
+                "-y",
+                "-o/out",
+                "-spd",
+                "--",
+                "/dl/a.7z.001",
+                "dir/*.txt"
+            ]
+        );
+    }
+}
@@ -1,61 +1,705 @@
 use anyhow::{Context, Result};
 use chrono::Local;
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
 use console::style;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
 use pty::fork::Fork;
+use regex::Regex;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::ffi::OsString;
 use std::fs::{self, OpenOptions};
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
 use walkdir::WalkDir;
 
+use events::{Event, EventLog};
+use repack::RepackFormat;
+
+/// Set by `--compact`: per-archive output gives way to one status line.
+static COMPACT: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// `println!` for per-archive output, which `--compact` hides.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !COMPACT.load(std::sync::atomic::Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod cache;
+mod checkpoint;
+mod checksum;
+mod dir_pattern;
+mod download;
+mod events;
+mod keyring;
+mod listing;
+mod normalize;
+mod paths;
+mod repack;
+mod spec;
+
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, disable_help_subcommand = true)]
 struct Args {
-    /// Directory to scan for archives (default: current directory)
-    #[arg(short, long, default_value = ".")]
+    /// Archives, directories to scan or http(s) URLs to extract instead of --dir (implies --all)
+    #[arg(value_name = "FILE")]
+    files: Vec<PathBuf>,
+
+    /// Directory to scan for archives; may use {a,b} and * ? [..] to scan several (default/-: .)
+    #[arg(short, long, default_value = ".", value_parser = parse_scan_dir)]
     dir: PathBuf,
 
+    /// Only pick up archives directly in the scan directory, not in its subdirectories
+    #[arg(long)]
+    archive_root_only: bool,
+
+    /// Also list scanned files that look like archives but weren't recognized, and why
+    #[arg(long)]
+    show_unrecognized: bool,
+
     /// Extract without asking (all found archives)
     #[arg(short, long)]
     all: bool,
 
+    /// Extract these archives without asking, numbered as in the prompt (e.g. 1,3,5-7)
+    #[arg(long, value_name = "NUMBERS", conflicts_with_all = ["all", "load_selection"])]
+    select: Option<String>,
+
+    /// Stop waiting at the selection prompt after this long (e.g. 60s), see --on-prompt-timeout
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    prompt_timeout: Option<Duration>,
+
+    /// What an unanswered selection prompt does after --prompt-timeout
+    #[arg(
+        long,
+        value_name = "ACTION",
+        value_enum,
+        default_value_t,
+        requires = "prompt_timeout"
+    )]
+    on_prompt_timeout: OnPromptTimeout,
+
     /// Run integrity test before extraction
     #[arg(short, long)]
     test: bool,
 
+    /// Only check headers/CRC tables by listing each archive (fast, not a full test)
+    #[arg(long, conflicts_with = "test")]
+    quick_test: bool,
+
+    /// Check each volume against a .sha256/.md5 sidecar first; skip archives that don't match
+    #[arg(long)]
+    checksum_archive: bool,
+
+    /// Stop and remove an extraction whose output grows past this size (e.g. 500M, 20G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_output_size: Option<u64>,
+
+    /// List each archive first and ask (or skip, without a terminal) if it has more than N entries
+    #[arg(long, value_name = "N")]
+    max_files: Option<usize>,
+
+    /// After extracting, check every listed entry exists with its listed size
+    #[arg(long)]
+    verify_extraction: bool,
+
+    /// Hide the backends' raw terminal output and show a plain progress bar instead
+    #[arg(long)]
+    no_pty_progress: bool,
+
+    /// Run backends under bwrap or firejail (Linux): no network, writes only to the output
+    #[arg(long)]
+    sandbox: bool,
+
+    /// Read ahead the later volumes of split archives in the background (slow/remote storage)
+    #[arg(long)]
+    prefetch: bool,
+
+    /// When a RAR archive fails, rebuild it from its recovery record (`rar r`) and retry
+    #[arg(long)]
+    repair: bool,
+
+    /// Extract only the first N files of each archive into BASE_NAME.preview/
+    #[arg(long, value_name = "N", conflicts_with_all = ["test", "quick_test"])]
+    preview: Option<usize>,
+
     /// Password for encrypted archives
     #[arg(short, long)]
     password: Option<String>,
 
-    /// Verbose output
+    /// What to do with encrypted archives when no --password is given
+    #[arg(long, value_enum, value_name = "ACTION", default_value_t = OnEncrypted::Prompt)]
+    on_encrypted: OnEncrypted,
+
+    /// Look up passwords for encrypted archives in the system keyring (by base name)
+    #[arg(long)]
+    keyring_use: bool,
+
+    /// Save passwords typed at the prompt to the system keyring once they worked
+    #[arg(long)]
+    keyring_store: bool,
+
+    /// Verbose output (same as --log-level debug)
     #[arg(short, long)]
     verbose: bool,
+
+    /// Log level: off, error, warn, info, debug or trace (overrides RUST_LOG)
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<log::LevelFilter>,
+
+    /// Only include archives modified within this window (e.g. 30m, 24h, 7d)
+    #[arg(long, value_parser = parse_duration)]
+    since: Option<Duration>,
+
+    /// Skip scanned archives whose (first) file is smaller than SIZE, e.g. download stubs
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = DEFAULT_MIN_FILE_SIZE,
+        value_parser = parse_size
+    )]
+    min_file_size: u64,
+
+    /// Give extracted files the archive's modification time instead of the backend's
+    #[arg(long)]
+    touch: bool,
+
+    /// Remove extracted device nodes, FIFOs, sockets and symlinks leading out of the output
+    #[arg(long)]
+    no_special: bool,
+
+    /// Rename extracted files to this Unicode normalization form
+    #[arg(long, value_name = "FORM")]
+    normalize_unicode: Option<normalize::UnicodeForm>,
+
+    /// Write a .un7z-complete marker after extraction and only skip archives that have one
+    #[arg(long)]
+    marker: bool,
+
+    /// Append "archive<TAB>extract_dir" for every successful extraction to this file
+    #[arg(long, value_name = "PATH")]
+    map_log: Option<PathBuf>,
+
+    /// Never delete partial output: keep incomplete directories and what a failed extraction left
+    #[arg(long)]
+    no_cleanup: bool,
+
+    /// Use the archives saved with --save-selection in PATH instead of asking
+    #[arg(long, value_name = "PATH")]
+    load_selection: Option<PathBuf>,
+
+    /// Save the chosen archives to PATH (one path per line) for --load-selection
+    #[arg(long, value_name = "PATH")]
+    save_selection: Option<PathBuf>,
+
+    /// Record extracted archives in PATH and skip those already recorded there
+    #[arg(long, value_name = "PATH")]
+    success_log: Option<PathBuf>,
+
+    /// Keep the batch's state (done, failed, skipped) in PATH after each archive;
+    /// rerunning with it resumes, skipping the archives already done
+    #[arg(long, value_name = "PATH")]
+    checkpoint: Option<PathBuf>,
+
+    /// Extract under this directory instead of next to each archive
+    #[arg(short, long, value_name = "DIR")]
+    output: Option<PathBuf>,
+
+    /// Extract into the current directory instead of next to each archive
+    #[arg(long, conflicts_with = "output")]
+    cwd: bool,
+
+    /// Mirror each archive's directory relative to --dir under --output (out/a/b/x/)
+    #[arg(long, requires = "output")]
+    preserve_tree: bool,
+
+    /// Extract into a per-type subfolder (7z/, rar/, ...) of the output root
+    #[arg(long)]
+    group_by_type: bool,
+
+    /// Put the lone file of a single-file tar.gz next to the archive, without a folder
+    #[arg(long)]
+    no_subdir: bool,
+
+    /// Extract straight into the archive's directory (or --output), without a base-name folder
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "no_subdir", "marker", "move_to", "repack", "touch", "no_special",
+            "normalize_unicode", "clean_empty", "interactive_overwrite", "extract_to_temp"
+        ]
+    )]
+    extract_here: bool,
+
+    /// File suffix marking a download still in progress (repeatable, replaces the defaults)
+    #[arg(
+        long = "in-progress-suffix",
+        value_name = "SUFFIX",
+        default_values_t = DEFAULT_IN_PROGRESS_SUFFIXES.map(String::from)
+    )]
+    in_progress_suffixes: Vec<String>,
+
+    /// Teach a new type: EXT=TEMPLATE, e.g. "lzh=lha xw={outdir} {archive}" (repeatable)
+    #[arg(long = "custom-type", value_name = "EXT=TEMPLATE", value_parser = CustomType::parse)]
+    custom_types: Vec<CustomType>,
+
+    /// Treat listed files (FILE or --stdin) as this type whatever their name: 7z, zip, rar, tar.gz
+    #[arg(long, value_name = "FORMAT", value_parser = parse_format)]
+    format: Option<ArchiveType>,
+
+    /// Read newline-separated archive paths from stdin instead of scanning (implies --all)
+    #[arg(long)]
+    stdin: bool,
+
+    /// Extract the archives listed in this spec file, with its per-archive settings (implies --all)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["files", "stdin"])]
+    spec: Option<PathBuf>,
+
+    /// Extract into existing output, replacing only files older than the archive's copies
+    #[arg(long, conflicts_with_all = ["test", "quick_test", "interactive_overwrite"])]
+    overwrite_newer: bool,
+
+    /// What to do when the output already has files, instead of skipping it
+    #[arg(
+        long,
+        value_name = "ACTION",
+        value_enum,
+        conflicts_with_all = ["interactive_overwrite", "overwrite_newer"]
+    )]
+    dest_exists_action: Option<ExistsAction>,
+
+    /// Ask whether to skip, overwrite or rename when the output already has files
+    #[arg(long)]
+    interactive_overwrite: bool,
+
+    /// Don't re-extract output that looks incomplete; list it at the end instead
+    #[arg(long)]
+    report_incomplete_only: bool,
+
+    /// Don't ask before deleting output that looks incomplete for re-extraction
+    #[arg(short = 'y', long)]
+    yes: bool,
+
+    /// Drop already-extracted archives from the selection before extracting
+    #[arg(long)]
+    only_missing: bool,
+
+    /// Stop at the first failed archive (the summary still covers what was done)
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Start no archive after this long (e.g. 30m); the one running is finished
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    deadline: Option<Duration>,
+
+    /// Extract archives on different disks in parallel, at most N at a time per disk
+    /// (backend output is replaced by progress bars)
+    #[arg(long, value_name = "N", conflicts_with = "interactive_overwrite")]
+    jobs_per_disk: Option<std::num::NonZeroUsize>,
+
+    /// Download at most N of the URLs given as FILE at a time (all finish before extracting)
+    #[arg(long, value_name = "N", default_value = "1")]
+    max_concurrent_downloads: std::num::NonZeroUsize,
+
+    /// Print the detected archives (path, type, volumes, size) and exit
+    #[arg(long)]
+    list_archives: bool,
+
+    /// Show one in-place status line ([3/50] extracting name (43%)) instead of per-archive output
+    #[arg(long)]
+    compact: bool,
+
+    /// Print each archive's metadata (files, size, solid, encrypted, methods) and exit
+    #[arg(long, conflicts_with = "list_archives")]
+    probe: bool,
+
+    /// Machine-readable JSON output where supported
+    #[arg(long)]
+    json: bool,
+
+    /// Disable colored output
+    #[arg(long)]
+    no_color: bool,
+
+    /// Send a desktop notification with the summary when the run ends
+    #[arg(long)]
+    notify: bool,
+
+    /// Write a newline-delimited JSON event stream (scan, start, progress, finish) to PATH
+    #[arg(long, value_name = "PATH")]
+    events: Option<PathBuf>,
+
+    /// Print the summary of an earlier run from its --events file, without extracting
+    #[arg(long, value_name = "PATH")]
+    show_report: Option<PathBuf>,
+
+    /// Write `ARCHIVE_NUMBER PERCENT` progress lines to the already open file descriptor N
+    #[arg(long, value_name = "N")]
+    progress_fd: Option<u32>,
+
+    /// Move each extracted directory into DIR once extraction succeeds
+    #[arg(long, value_name = "DIR")]
+    move_to: Option<PathBuf>,
+
+    /// Resolve a name already taken where --move-to or --no-subdir puts output (default: skip)
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    collision: Option<Collision>,
+
+    /// Also pack each extracted output into FORMAT beside it (tar.gz, tar.zst, zip, 7z)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    repack: Option<RepackFormat>,
+
+    /// Delete the extracted files once --repack has packed them
+    #[arg(long, requires = "repack")]
+    repack_remove: bool,
+
+    /// Run this command after each successful extraction ({dir} and {name} are substituted)
+    #[arg(long, value_name = "CMD")]
+    on_success: Option<String>,
+
+    /// Run this command after each failed archive ({dir} and {name} are substituted)
+    #[arg(long, value_name = "CMD")]
+    on_failure: Option<String>,
+
+    /// Kill a hook command that runs longer than this (e.g. 30s, 5m)
+    #[arg(long, value_name = "DURATION", default_value = "5m", value_parser = parse_duration)]
+    hook_timeout: Duration,
+
+    /// Reuse the last scan of DIR (kept in the user cache directory) while nothing in it changed
+    #[arg(long)]
+    cache: bool,
+
+    /// Concatenate generic splits (name.001, name.002, ...) 7zz cannot read as-is, then exit
+    #[arg(long)]
+    join: bool,
+
+    /// Concatenate every bare split (name.001, name.002, ...) into name, archive or not, then exit
+    #[arg(long)]
+    concat: bool,
+
+    /// With --join/--concat, delete the parts once they are joined
+    #[arg(long)]
+    delete_parts: bool,
+
+    /// Remove empty extraction directories left by interrupted runs and exit
+    #[arg(long)]
+    clean_empty: bool,
+
+    /// Print a shell completion script (bash, zsh, fish, powershell, elvish) and exit
+    #[arg(long, value_name = "SHELL", hide = true)]
+    generate_completions: Option<clap_complete::Shell>,
+
+    /// List the archive types un7z recognizes and the backend for each, then exit
+    #[arg(long)]
+    list_types: bool,
+
+    /// Show which backends (7zz, unrar, tar, ...) are installed, their versions and PTY support
+    #[arg(long)]
+    doctor: bool,
+
+    /// Print un7z's version, the backends' versions and the platform as JSON and exit
+    #[arg(long)]
+    version_json: bool,
+
+    /// Integrity-test a single volume file (any part, e.g. data.part05.rar) and exit
+    #[arg(long, value_name = "FILE")]
+    test_part: Option<PathBuf>,
+
+    /// Extract the archive into a new temporary directory and print only its path
+    #[arg(
+        long,
+        requires = "files",
+        conflicts_with_all = ["output", "cwd", "move_to", "test", "quick_test", "preview"]
+    )]
+    extract_to_temp: bool,
+
+    /// Extract the archive into a temporary directory and write it to stdout as a tar stream
+    #[arg(
+        long,
+        requires = "files",
+        conflicts_with_all = [
+            "output", "cwd", "move_to", "test", "quick_test", "preview", "extract_to_temp"
+        ]
+    )]
+    pipe_tar: bool,
+
+    /// Extract straight into DIR (used by --extract-to-temp)
+    #[arg(long, value_name = "DIR", hide = true)]
+    extract_into: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Cmd>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Print the type un7z detects for each FILE ("unknown" if none); fails if any is unknown
+    Detect {
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// One JSON object per file
+        #[arg(long)]
+        json: bool,
+    },
 }
 
+/// Default `--min-file-size`, smaller than any real archive: 7z's signature
+/// header alone is 32 bytes.
+const DEFAULT_MIN_FILE_SIZE: &str = "32";
+
+/// Suffixes browsers and download managers use for files still being written.
+const DEFAULT_IN_PROGRESS_SUFFIXES: [&str; 6] = [
+    ".crdownload",
+    ".part",
+    ".partial",
+    ".download",
+    ".aria2",
+    ".!qB",
+];
+
 pub(crate) struct Archive {
     pub path: PathBuf,
     pub base_name: String,
     pub archive_type: ArchiveType,
+    /// Extract into `base_name.type` because an archive of another type in the
+    /// same directory has the same base name (see `disambiguate_mixed_types`).
+    pub type_suffix: bool,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) enum ArchiveType {
     SevenZip,
     Zip,
     Rar,
     TarGz,
+    Custom(CustomType),
 }
 
 impl ArchiveType {
-    fn as_str(&self) -> &'static str {
+    fn as_str(&self) -> &str {
         match self {
             ArchiveType::SevenZip => "7z",
             ArchiveType::Zip => "zip",
             ArchiveType::Rar => "rar",
             ArchiveType::TarGz => "tar.gz",
+            ArchiveType::Custom(custom) => &custom.extension,
+        }
+    }
+}
+
+/// How a built-in type is recognized from a file name.
+#[derive(Debug, Clone, Copy)]
+enum NamePattern {
+    /// `base<suffix>.001`, the first volume with any zero padding (`.0001`, ...).
+    FirstVolume(&'static str),
+    /// `base<suffix>`.
+    Suffix(&'static str),
+}
+
+impl NamePattern {
+    fn suffix(self) -> &'static str {
+        match self {
+            NamePattern::FirstVolume(suffix) | NamePattern::Suffix(suffix) => suffix,
+        }
+    }
+
+    fn base_name(self, filename: &str) -> Option<&str> {
+        match self {
+            NamePattern::FirstVolume(suffix) => strip_first_volume(filename)?.strip_suffix(suffix),
+            NamePattern::Suffix(suffix) => filename.strip_suffix(suffix),
+        }
+    }
+
+    /// Glob-style description for `--list-types`.
+    fn example(self) -> String {
+        match self {
+            NamePattern::FirstVolume(suffix) => format!("*{}.001", suffix),
+            NamePattern::Suffix(suffix) => format!("*{}", suffix),
+        }
+    }
+}
+
+/// A file name pattern of a built-in type and the tool that extracts it.
+struct BuiltinType {
+    archive_type: ArchiveType,
+    pattern: NamePattern,
+    backend: &'static str,
+}
+
+/// Every file name `parse_type` recognizes; also what `--list-types` prints.
+const BUILTIN_TYPES: [BuiltinType; 6] = [
+    BuiltinType {
+        archive_type: ArchiveType::SevenZip,
+        pattern: NamePattern::FirstVolume(".7z"),
+        backend: "7zz",
+    },
+    BuiltinType {
+        archive_type: ArchiveType::Zip,
+        pattern: NamePattern::FirstVolume(".zip"),
+        backend: "7zz",
+    },
+    BuiltinType {
+        archive_type: ArchiveType::TarGz,
+        pattern: NamePattern::Suffix(".tar.gz"),
+        backend: "tar",
+    },
+    BuiltinType {
+        archive_type: ArchiveType::TarGz,
+        pattern: NamePattern::Suffix(".tgz"),
+        backend: "tar",
+    },
+    BuiltinType {
+        archive_type: ArchiveType::Rar,
+        pattern: NamePattern::Suffix(".part01.rar"),
+        backend: "unrar",
+    },
+    BuiltinType {
+        archive_type: ArchiveType::Rar,
+        pattern: NamePattern::Suffix(".part001.rar"),
+        backend: "unrar",
+    },
+];
+
+/// `--list-types`: every recognized file name and its backend.
+fn print_types(custom_types: &[CustomType]) {
+    println!("\n{}", style("Supported archive types").bold());
+    for builtin in &BUILTIN_TYPES {
+        println!(
+            "  {:<7} {:<16} {}",
+            builtin.archive_type.as_str(),
+            builtin.pattern.example(),
+            style(builtin.backend).dim()
+        );
+    }
+    println!(
+        "  {:<7} {:<16} {}",
+        "7z/zip",
+        "*.001",
+        style("7zz (recognized by header; other types need --join)").dim()
+    );
+    for custom in custom_types {
+        println!(
+            "  {:<7} {:<16} {}",
+            custom.extension,
+            format!("*.{}", custom.extension),
+            style(&custom.template).dim()
+        );
+    }
+}
+
+/// A user-defined archive type: files ending in `.{extension}` are extracted by
+/// running `template` with `{archive}` and `{outdir}` substituted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct CustomType {
+    pub extension: String,
+    pub template: String,
+}
+
+impl CustomType {
+    /// Parse `EXT=TEMPLATE` (a leading dot on EXT is optional).
+    fn parse(input: &str) -> Result<Self> {
+        let (extension, template) = input
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected EXT=TEMPLATE, got '{}'", input))?;
+        let extension = extension.trim().trim_start_matches('.').to_string();
+        let template = template.trim().to_string();
+        if extension.is_empty() || template.is_empty() {
+            anyhow::bail!("Expected EXT=TEMPLATE, got '{}'", input);
+        }
+        Ok(CustomType {
+            extension,
+            template,
+        })
+    }
+}
+
+/// Build a command from a whitespace-separated template, substituting `{name}`
+/// placeholders. A token that is exactly a placeholder is passed through as-is,
+/// so paths are never split or mangled.
+fn expand_template(template: &str, vars: &[(&str, &Path)]) -> Result<Command> {
+    let mut tokens = template.split_whitespace().map(|token| {
+        if let Some((_, value)) = vars
+            .iter()
+            .find(|(name, _)| token == format!("{{{}}}", name))
+        {
+            return value.as_os_str().to_os_string();
+        }
+        let mut expanded = token.to_string();
+        for (name, value) in vars {
+            expanded = expanded.replace(&format!("{{{}}}", name), &value.to_string_lossy());
         }
+        OsString::from(expanded)
+    });
+    let program = tokens
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Empty command template"))?;
+    let mut cmd = Command::new(program);
+    cmd.args(tokens);
+    Ok(cmd)
+}
+
+/// Options that affect which files `scan_archives` recognizes.
+#[derive(Debug, Default)]
+pub(crate) struct ScanOptions {
+    pub custom_types: Vec<CustomType>,
+    /// Read only the scan directory itself, not its subdirectories.
+    pub root_only: bool,
+    /// Archives whose primary volume is smaller are stubs, not archives.
+    pub min_file_size: u64,
+}
+
+/// What scanning a directory found.
+#[derive(Default)]
+pub(crate) struct Scanned {
+    pub archives: Vec<Archive>,
+    /// Archives under `--min-file-size`, left out of `archives`.
+    pub stubs: Vec<Archive>,
+    /// Files that look like archives but weren't recognized, with the reason;
+    /// for `archives_from_paths`, every listed path that was rejected.
+    pub unrecognized: Vec<Rejected>,
+}
+
+/// Where extracted output goes, relative to each archive.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct OutputLayout {
+    /// Extract under this root instead of the archive's parent directory.
+    pub root: Option<PathBuf>,
+    /// Prepend a type subfolder (`7z/base_name`, `rar/base_name`, ...).
+    pub group_by_type: bool,
+    /// With a root, mirror the archive's directory relative to this scan root.
+    pub scan_root: Option<PathBuf>,
+    /// Extract into the directory itself, without a `base_name` folder.
+    pub here: bool,
+}
+
+impl OutputLayout {
+    fn from_args(args: &Args) -> Result<Self> {
+        // Absolute, because the backend runs from the archive's directory
+        let root = match &args.output {
+            Some(dir) => Some(std::path::absolute(dir).context("Cannot resolve output directory")?),
+            None if args.cwd => {
+                Some(std::env::current_dir().context("Cannot resolve current directory")?)
+            }
+            None => None,
+        };
+        let scan_root = if args.preserve_tree {
+            let scan_root = dir_pattern::literal_prefix(&args.dir);
+            Some(std::path::absolute(scan_root).context("Cannot resolve scan directory")?)
+        } else {
+            None
+        };
+        Ok(OutputLayout {
+            root,
+            group_by_type: args.group_by_type,
+            scan_root,
+            here: args.extract_here,
+        })
     }
 }
 
@@ -68,41 +712,353 @@ impl Archive {
             path,
             base_name,
             archive_type,
+            type_suffix: false,
         })
     }
 
-    /// Returns the directory where files will be extracted (parent of archive + base_name).
-    pub(crate) fn extract_dir(&self) -> Result<PathBuf> {
-        self.path
+    /// Returns the directory where files will be extracted
+    /// (parent of archive, or the layout's root, + base_name[.type] unless
+    /// the layout extracts here).
+    pub(crate) fn extract_dir(&self, layout: &OutputLayout) -> Result<PathBuf> {
+        let parent = self
+            .path
             .parent()
-            .ok_or_else(|| anyhow::anyhow!("Archive has no parent"))
-            .map(|p| p.join(&self.base_name))
+            .ok_or_else(|| anyhow::anyhow!("Archive has no parent"))?;
+        let mut dir = match &layout.root {
+            Some(root) => root.clone(),
+            None => parent.to_path_buf(),
+        };
+        if layout.group_by_type {
+            dir.push(self.archive_type.as_str());
+        }
+        if let (Some(_), Some(scan_root)) = (&layout.root, &layout.scan_root) {
+            // Archives outside the scan root (explicit FILE arguments) go to the root itself
+            let parent = std::path::absolute(parent).context("Cannot resolve archive directory")?;
+            if let Ok(relative) = parent.strip_prefix(scan_root) {
+                dir.push(relative);
+            }
+        }
+        if layout.here {
+            // Absolute, because the backend runs from the archive's directory
+            if dir.as_os_str().is_empty() {
+                dir.push(".");
+            }
+            return std::path::absolute(dir).context("Cannot resolve archive directory");
+        }
+        if self.type_suffix {
+            return Ok(dir.join(format!("{}.{}", self.base_name, self.archive_type.as_str())));
+        }
+        Ok(dir.join(&self.base_name))
     }
 
-    fn parse_type(filename: &str) -> Option<(ArchiveType, String)> {
-        if filename.ends_with(".7z.001") {
-            let base = filename.strip_suffix(".7z.001")?;
-            Some((ArchiveType::SevenZip, base.to_string()))
-        } else if filename.ends_with(".zip.001") {
-            let base = filename.strip_suffix(".zip.001")?;
-            Some((ArchiveType::Zip, base.to_string()))
-        } else if let Some(name) = filename.strip_suffix(".tar.gz") {
-            Some((ArchiveType::TarGz, name.to_string()))
-        } else if let Some(name) = filename.strip_suffix(".tgz") {
-            Some((ArchiveType::TarGz, name.to_string()))
-        } else if filename.ends_with(".part01.rar") {
-            let base = filename.strip_suffix(".part01.rar")?;
-            Some((ArchiveType::Rar, base.to_string()))
-        } else if filename.ends_with(".part001.rar") {
-            let base = filename.strip_suffix(".part001.rar")?;
-            Some((ArchiveType::Rar, base.to_string()))
+    /// All volume files of this archive set in volume order (only `path` for single-file archives).
+    pub(crate) fn volumes(&self) -> Vec<PathBuf> {
+        let Some(pattern) = self.volume_pattern() else {
+            return vec![self.path.clone()];
+        };
+        let Ok(entries) = fs::read_dir(self.dir()) else {
+            return vec![self.path.clone()];
+        };
+
+        let mut volumes: Vec<(u32, PathBuf)> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name();
+                let number = pattern.captures(name.to_str()?)?[1].parse().ok()?;
+                Some((number, self.path.with_file_name(&name)))
+            })
+            .collect();
+        volumes.sort();
+        volumes.into_iter().map(|(_, path)| path).collect()
+    }
+
+    /// Total on-disk size of all volumes.
+    pub(crate) fn total_size(&self) -> u64 {
+        self.volumes()
+            .iter()
+            .filter_map(|v| fs::metadata(v).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    /// Directory containing the archive (`.` for a bare file name).
+    fn dir(&self) -> &Path {
+        match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Regex matching a volume file name of this set, capturing the volume number.
+    fn volume_pattern(&self) -> Option<Regex> {
+        let base = regex::escape(&self.base_name);
+        let pattern = match self.archive_type {
+            // The primary volume's name without its number: `base.7z` usually,
+            // just `base` for a generic split, whose later volumes may still
+            // carry the type (`base.001`, `base.7z.002`)
+            ArchiveType::SevenZip | ArchiveType::Zip => {
+                let stem = strip_first_volume(self.path.file_name()?.to_str()?)?;
+                let typed = format!(".{}", self.archive_type.as_str());
+                if stem.ends_with(&typed) {
+                    format!(r"^{}\.(\d+)$", regex::escape(stem))
+                } else {
+                    let (stem, typed) = (regex::escape(stem), regex::escape(&typed));
+                    format!(r"^{}(?:{})?\.(\d+)$", stem, typed)
+                }
+            }
+            ArchiveType::Rar => format!(r"^{}\.part(\d+)\.rar$", base),
+            ArchiveType::TarGz | ArchiveType::Custom(_) => return None,
+        };
+        Regex::new(&pattern).ok()
+    }
+
+    /// Recognize any volume of a multi-volume set, not just the first one.
+    /// The returned archive points at that volume; the number is 1-based.
+    pub(crate) fn from_volume(path: PathBuf) -> Option<(Self, u32)> {
+        let file_name = path.file_name()?.to_str()?;
+        let (archive_type, base_name, number) = if let Some((stem, number)) = file_name
+            .rsplit_once('.')
+            .filter(|(_, n)| n.len() >= 3 && n.bytes().all(|b| b.is_ascii_digit()))
+        {
+            let (base, archive_type) = match stem.rsplit_once('.')? {
+                (base, "7z") => (base, ArchiveType::SevenZip),
+                (base, "zip") => (base, ArchiveType::Zip),
+                _ => return None,
+            };
+            (archive_type, base, number)
         } else {
-            None
+            let (base, number) = file_name.strip_suffix(".rar")?.rsplit_once(".part")?;
+            (ArchiveType::Rar, base, number)
+        };
+        if base_name.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let number = number.parse().ok().filter(|&n| n > 0)?;
+        let base_name = base_name.to_string();
+        let archive = Archive {
+            path,
+            base_name,
+            archive_type,
+            type_suffix: false,
+        };
+        Some((archive, number))
+    }
+
+    /// Command for `--quick-test`: list the archive, which makes the backend
+    /// read and verify its headers (and CRC tables) without decompressing data.
+    fn quick_test_command(&self, password: &Option<String>) -> Result<Command> {
+        let cmd = match &self.archive_type {
+            ArchiveType::SevenZip | ArchiveType::Zip => {
+                let mut cmd = Command::new("7zz");
+                cmd.arg("l")
+                    .arg("-slt")
+                    .args(password_args(&self.archive_type, password)?);
+                cmd.arg(&self.path);
+                cmd
+            }
+            ArchiveType::Rar => {
+                let mut cmd = Command::new("unrar");
+                cmd.arg("lt")
+                    .args(password_args(&self.archive_type, password)?);
+                cmd.arg(&self.path);
+                cmd
+            }
+            ArchiveType::TarGz => {
+                // Reads the gzip header and trailer (size and CRC) only
+                let mut cmd = Command::new("gzip");
+                cmd.arg("-l").arg(&self.path);
+                cmd
+            }
+            ArchiveType::Custom(custom) => {
+                anyhow::bail!("No quick test for custom type .{}", custom.extension)
+            }
+        };
+        Ok(cmd)
+    }
+
+    /// Test command for volume `number` of this set on its own.
+    ///
+    /// unrar tests the files that start in the given volume; 7z/zip splits are
+    /// plain byte ranges, so only the first volume can be opened and the whole
+    /// set is needed.
+    fn part_test_command(&self, number: u32, password: &Option<String>) -> Result<Command> {
+        match self.archive_type {
+            ArchiveType::Rar => {
+                let mut cmd = Command::new("unrar");
+                cmd.arg("t")
+                    .args(password_args(&self.archive_type, password)?);
+                cmd.arg(&self.path);
+                Ok(cmd)
+            }
+            ArchiveType::SevenZip | ArchiveType::Zip if number == 1 => {
+                self.extract_command(true, password, self.dir())
+            }
+            ArchiveType::SevenZip | ArchiveType::Zip => anyhow::bail!(
+                "{} volumes cannot be tested on their own; 7zz needs the full set \
+                 starting at {}.{}.001",
+                self.archive_type.as_str(),
+                display_name(&self.base_name),
+                self.archive_type.as_str()
+            ),
+            ArchiveType::TarGz | ArchiveType::Custom(_) => {
+                anyhow::bail!("{} archives have no volumes", self.archive_type.as_str())
+            }
+        }
+    }
+
+    /// Take `path` as `archive_type` whatever its name (`--format`). The base
+    /// name is the file name without its final extension.
+    pub(crate) fn forced(path: PathBuf, archive_type: ArchiveType) -> Option<Self> {
+        let base_name = path.file_stem()?.to_str()?.to_string();
+        Some(Archive {
+            path,
+            base_name,
+            archive_type,
+            type_suffix: false,
+        })
+    }
+
+    /// Like `new`, but also recognizes user-defined types when no built-in type matches.
+    pub(crate) fn detect(path: PathBuf, custom_types: &[CustomType]) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        if Self::parse_type(file_name).is_some() {
+            return Self::new(path);
+        }
+        let custom = custom_types.iter().find_map(|custom| {
+            let base = file_name
+                .strip_suffix(&custom.extension)?
+                .strip_suffix('.')?;
+            (!base.is_empty()).then(|| (custom.clone(), base.to_string()))
+        });
+        if let Some((custom, base_name)) = custom {
+            return Some(Archive {
+                path,
+                base_name,
+                archive_type: ArchiveType::Custom(custom),
+                type_suffix: false,
+            });
+        }
+        Self::probe_split(path)
+    }
+
+    /// A generic split (`backup.001`, `backup.002`, ...) with no type in its
+    /// name, recognized by the type in its siblings' names (`backup.7z.002`,
+    /// when only the first volume lost it) or else by the header magic of the
+    /// joined volumes. Only 7z and zip qualify: 7zz reads such splits
+    /// directly, other types need `--join`.
+    fn probe_split(path: PathBuf) -> Option<Self> {
+        let base_name = strip_first_volume(path.file_name()?.to_str()?)?.to_string();
+        if let Some(archive_type) = sibling_split_type(&path) {
+            log::debug!(
+                "{} is {} by its sibling volumes' names",
+                path.display(),
+                archive_type.as_str()
+            );
+            return Some(Archive {
+                path,
+                base_name,
+                archive_type,
+                type_suffix: false,
+            });
+        }
+        let archive_type = match sniff_type(&read_split_header(&path, 8)) {
+            Some(archive_type @ (ArchiveType::SevenZip | ArchiveType::Zip)) => archive_type,
+            Some(other) => {
+                log::info!(
+                    "{} is a split {} archive, join it with --join to extract",
+                    path.display(),
+                    other.as_str()
+                );
+                return None;
+            }
+            None => return None,
+        };
+        Some(Archive {
+            path,
+            base_name,
+            archive_type,
+            type_suffix: false,
+        })
+    }
+
+    /// Built-in type and base name from a file name. When several patterns
+    /// match, the longest suffix wins, so table order doesn't matter.
+    fn parse_type(filename: &str) -> Option<(ArchiveType, String)> {
+        BUILTIN_TYPES
+            .iter()
+            .filter_map(|builtin| Some((builtin, builtin.pattern.base_name(filename)?)))
+            .max_by_key(|(builtin, _)| builtin.pattern.suffix().len())
+            .map(|(builtin, base)| (builtin.archive_type.clone(), base.to_string()))
+    }
+
+    /// Command for `--repair`: rebuild a damaged RAR from its recovery record
+    /// as `fixed.<name>` in `out_dir`. Needs `rar`, since `unrar` can't repair.
+    /// None for other types.
+    fn repair_command(&self, out_dir: &Path) -> Option<Command> {
+        if self.archive_type != ArchiveType::Rar {
+            return None;
         }
+        let mut cmd = Command::new("rar");
+        cmd.arg("r").arg("-y").arg(&self.path);
+        let mut out = OsString::from(out_dir);
+        out.push("/");
+        cmd.arg(out);
+        Some(cmd)
     }
 
-    fn extract_command(&self, test: bool, password: &Option<String>) -> Command {
+    /// How this archive's backend shows progress.
+    fn progress_profile(&self) -> ProgressProfile {
         match self.archive_type {
+            ArchiveType::SevenZip | ArchiveType::Zip => seven_zip_profile(),
+            ArchiveType::Rar => ProgressProfile::Unrar,
+            ArchiveType::TarGz | ArchiveType::Custom(_) => ProgressProfile::Generic,
+        }
+    }
+
+    /// Backend switches that overwrite existing files without asking, for
+    /// `--dest-exists-action merge`.
+    fn merge_args(&self) -> &'static [&'static str] {
+        match self.archive_type {
+            ArchiveType::SevenZip | ArchiveType::Zip => &["-aoa"],
+            ArchiveType::Rar => &["-o+"],
+            ArchiveType::TarGz => &["--overwrite"],
+            ArchiveType::Custom(_) => &[],
+        }
+    }
+
+    /// Backend switches that apply `action` to each file that already exists,
+    /// for `--extract-here`; None where the backend can't do it.
+    fn here_args(&self, action: ExistsAction) -> Option<&'static [&'static str]> {
+        match (action, &self.archive_type) {
+            (ExistsAction::Overwrite | ExistsAction::Merge, _) => Some(self.merge_args()),
+            (ExistsAction::Skip, ArchiveType::SevenZip | ArchiveType::Zip) => Some(&["-aos"]),
+            (ExistsAction::Skip, ArchiveType::Rar) => Some(&["-o-"]),
+            (ExistsAction::Skip, ArchiveType::TarGz) => Some(&["--skip-old-files"]),
+            (ExistsAction::Rename, ArchiveType::SevenZip | ArchiveType::Zip) => Some(&["-aou"]),
+            (ExistsAction::Rename, ArchiveType::Rar) => Some(&["-or"]),
+            (_, ArchiveType::TarGz | ArchiveType::Custom(_)) => None,
+        }
+    }
+
+    /// Backend switches that extract over existing files but keep those newer
+    /// than the archive's copies; None where the backend has no such mode.
+    fn update_args(&self) -> Option<&'static [&'static str]> {
+        match self.archive_type {
+            ArchiveType::TarGz => Some(&["--keep-newer-files"]),
+            ArchiveType::Rar => Some(&["-u"]),
+            ArchiveType::SevenZip | ArchiveType::Zip | ArchiveType::Custom(_) => None,
+        }
+    }
+
+    fn extract_command(
+        &self,
+        test: bool,
+        password: &Option<String>,
+        out_dir: &Path,
+    ) -> Result<Command> {
+        #[cfg(windows)]
+        let out_dir = &*backend_path(out_dir);
+        let cmd = match &self.archive_type {
             ArchiveType::SevenZip | ArchiveType::Zip => {
                 let mut cmd = Command::new("7zz");
                 if test {
@@ -112,11 +1068,11 @@ impl Archive {
                 }
                 cmd.arg(&self.path);
 
-                if let Some(pwd) = password {
-                    cmd.arg(format!("-p{}", pwd));
-                }
+                cmd.args(password_args(&self.archive_type, password)?);
 
-                cmd.arg(format!("-o{}", self.base_name));
+                let mut out = OsString::from("-o");
+                out.push(out_dir);
+                cmd.arg(out);
                 cmd
             }
             ArchiveType::Rar => {
@@ -131,15 +1087,14 @@ impl Archive {
                 };
                 cmd.arg(&self.path);
 
-                if let Some(pwd) = password {
-                    cmd.arg("-p").arg(pwd);
-                } else {
-                    cmd.arg("-p-");
-                }
+                cmd.args(password_args(&self.archive_type, password)?);
 
-                // Specify output directory for RAR
+                // Specify output directory for RAR (trailing separator marks it as a
+                // directory; a verbatim Windows path only takes a backslash)
                 if !test {
-                    cmd.arg(&self.base_name);
+                    let mut out = OsString::from(out_dir);
+                    out.push(std::path::MAIN_SEPARATOR_STR);
+                    cmd.arg(out);
                 }
 
                 cmd
@@ -151,499 +1106,8828 @@ impl Archive {
                     cmd
                 } else {
                     let mut cmd = Command::new("tar");
-                    // Extract to base_name directory
-                    cmd.arg("xzf").arg(&self.path).arg("-C").arg(&self.base_name);
+                    // Extract to the (pre-created) output directory, listing each
+                    // entry so we can drive a progress bar
+                    cmd.arg("xzvf").arg(&self.path).arg("-C").arg(out_dir);
                     cmd
                 }
             }
-        }
+            ArchiveType::Custom(custom) => {
+                if test {
+                    anyhow::bail!(
+                        "Integrity test is not supported for custom type .{}",
+                        custom.extension
+                    );
+                }
+                expand_template(
+                    &custom.template,
+                    &[("archive", &self.path), ("outdir", out_dir)],
+                )?
+            }
+        };
+        Ok(cmd)
     }
 }
 
-pub(crate) fn scan_archives(dir: &Path) -> Result<Vec<Archive>> {
-    let dir = dir
-        .canonicalize()
-        .context("Cannot resolve scan directory")?;
-    let mut archives = Vec::new();
+/// Whether `unrar l` output shows encryption: a `*` in front of a file's
+/// attributes, or an archive whose headers (file names) are encrypted too.
+fn rar_listing_encrypted(listing: &str) -> bool {
+    listing.lines().any(|line| {
+        let line = line.trim_start();
+        line.starts_with('*')
+            || line.contains("encrypted headers")
+            || line.contains("password is incorrect")
+            || line.contains("Incorrect password")
+    })
+}
 
-    for entry in WalkDir::new(&dir)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(archive) = Archive::new(path.to_path_buf()) {
-                archives.push(archive);
-            }
+/// How to place output whose name is already taken (`--collision`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+enum Collision {
+    /// Add a counter: `name (2)`, `name (3)`, ...
+    Counter,
+    /// Add a short hash of the new content: `name (1a2b3c4d)`
+    Hash,
+    /// Keep what is there
+    #[default]
+    Skip,
+    /// Replace what is there
+    Overwrite,
+}
+
+/// What the selection prompt does when `--prompt-timeout` passes unanswered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+enum OnPromptTimeout {
+    /// Extract every archive found
+    All,
+    /// Extract nothing and exit with an error
+    #[default]
+    Abort,
+}
+
+/// What to do with an encrypted archive when no `--password` was given.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+enum OnEncrypted {
+    /// Leave it alone and count it as skipped
+    Skip,
+    /// Ask for the password on the terminal
+    #[default]
+    Prompt,
+    /// Try the empty password
+    Empty,
+    /// Count it as failed
+    Fail,
+}
+
+/// The keyring's password for `archive` with `--keyring-use`; a keyring that
+/// can't be read is reported and treated as having none.
+fn keyring_password(opts: &ExtractOptions, archive: &Archive) -> Option<String> {
+    if !opts.keyring_use {
+        return None;
+    }
+    match keyring::get(&keyring::Entry::for_base_name(&archive.base_name)) {
+        Ok(password) => password,
+        Err(e) => {
+            eprintln!("{} {:#}", style("⚠").yellow(), e);
+            None
         }
     }
+}
 
-    archives.sort_by(|a, b| a.base_name.cmp(&b.base_name));
-    Ok(archives)
+/// Password to hand the backend when the archive did not look encrypted.
+/// Without `--password`, every policy but `prompt` passes the empty password,
+/// which stops both backends from prompting: 7zz gets `-p`, unrar `-p-`.
+fn effective_password(policy: OnEncrypted, password: &Option<String>) -> Option<String> {
+    match (password, policy) {
+        (Some(password), _) => Some(password.clone()),
+        (None, OnEncrypted::Prompt) => None,
+        (None, _) => Some(String::new()),
+    }
 }
 
-fn select_archives(archives: &[Archive]) -> Result<Vec<usize>> {
-    use console::Term;
+/// The first password typed at the prompt that extracted an archive, kept in
+/// memory only, for the rest of the run, and tried before prompting again.
+#[derive(Default)]
+struct SessionPassword(std::sync::Mutex<Option<String>>);
 
-    println!(
-        "\n{} {} {} {}\n",
-        style("Found").bold().cyan(),
-        style(archives.len()).bold().yellow(),
-        style("archives:").bold().cyan(),
-        style("(Select with space, confirm with Enter)").dim()
-    );
+impl SessionPassword {
+    /// The kept password if `works` accepts it, else what `prompt` returns.
+    /// The flag tells whether the password came from the prompt.
+    fn get_or_prompt<W, P>(&self, works: W, prompt: P) -> (Option<String>, bool)
+    where
+        W: FnOnce(&str) -> bool,
+        P: FnOnce() -> Option<String>,
+    {
+        let kept = self.0.lock().unwrap().clone();
+        match kept.filter(|password| works(password)) {
+            Some(password) => (Some(password), false),
+            None => (prompt(), true),
+        }
+    }
 
-    let term = Term::stdout();
+    /// Keep a typed password that worked, unless one is kept already.
+    fn remember(&self, password: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| password.to_string());
+    }
+}
 
-    for (i, archive) in archives.iter().enumerate() {
-        let name = &archive.base_name;
-        let ext = match archive.archive_type {
-            ArchiveType::SevenZip => "7z",
-            ArchiveType::Zip => "zip",
-            ArchiveType::Rar => "rar",
-            ArchiveType::TarGz => "tar.gz",
-        };
+/// Whether `7zz l -slt` output shows encryption: an encrypted entry, or
+/// headers that could not be read without the password.
+fn sevenzip_listing_encrypted(listing: &str) -> bool {
+    listing.lines().any(|line| {
+        let line = line.trim();
+        line == "Encrypted = +"
+            || line.contains("Can not open encrypted archive")
+            || line.contains("Wrong password")
+    })
+}
 
-        println!(
-            "{:>3}. [{}] {} ({})",
-            style(i + 1).bold().dim(),
-            style(" ").white().on_black(),
-            style(name).bold().white(),
-            style(ext).cyan()
+/// `--verify-extraction`: compare the archive's listing with what is under
+/// `root` (where the listed paths start), printing the differences.
+fn verify_extraction(archive: &Archive, password: &Option<String>, root: &Path) -> Result<()> {
+    const SHOWN: usize = 10;
+    let entries = listing::list_entries(archive, password).context("Cannot verify extraction")?;
+    let mismatches = listing::compare_to_tree(&entries, root);
+    if mismatches.is_empty() {
+        log::debug!(
+            "Verified {} entries under {}",
+            entries.len(),
+            root.display()
         );
+        return Ok(());
     }
+    for mismatch in mismatches.iter().take(SHOWN) {
+        say!("  {} {}", style("≠").red(), mismatch);
+    }
+    if mismatches.len() > SHOWN {
+        say!(
+            "  {} and {} more",
+            style("…").dim(),
+            mismatches.len() - SHOWN
+        );
+    }
+    anyhow::bail!(
+        "{} of {} listed entries missing or of the wrong size",
+        mismatches.len(),
+        entries.len()
+    )
+}
 
-    println!("\n{}", style("Enter numbers (e.g., 1,3,5-7) or 'all':").bold());
-    print!("{} ", style(">").cyan());
-
-    let _ = term.flush();
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input)?;
+/// What `--max-files` does with an archive of `entries` entries.
+#[derive(Debug, PartialEq, Eq)]
+enum FileCountCheck {
+    Within,
+    /// Over the limit, but `--yes` was given
+    Proceed,
+    Ask,
+    Skip,
+}
 
-    let input = input.trim();
-    if input.is_empty() || input.eq_ignore_ascii_case("all") {
-        Ok((0..archives.len()).collect())
+fn file_count_check(
+    entries: usize,
+    max_files: usize,
+    assume_yes: bool,
+    interactive: bool,
+) -> FileCountCheck {
+    if entries <= max_files {
+        FileCountCheck::Within
+    } else if assume_yes {
+        FileCountCheck::Proceed
+    } else if interactive {
+        FileCountCheck::Ask
     } else {
-        parse_selection(input, archives.len())
+        FileCountCheck::Skip
     }
 }
 
-fn parse_selection(input: &str, max: usize) -> Result<Vec<usize>> {
-    let mut selected = Vec::new();
+/// `--max-files` preflight: whether to go on extracting. An archive that
+/// can't be listed goes on, and extraction reports the problem.
+fn check_file_count(
+    archive: &Archive,
+    password: &Option<String>,
+    max_files: usize,
+    assume_yes: bool,
+) -> bool {
+    let entries = match listing::list_entries(archive, password) {
+        Ok(entries) => entries.len(),
+        Err(e) => {
+            log::debug!(
+                "Cannot count entries of {}: {:#}",
+                archive.path.display(),
+                e
+            );
+            return true;
+        }
+    };
+    let interactive = std::io::stdin().is_terminal();
+    let over = format!("{} entries, more than --max-files {}", entries, max_files);
+    let go_on = match file_count_check(entries, max_files, assume_yes, interactive) {
+        FileCountCheck::Within => return true,
+        FileCountCheck::Proceed => {
+            say!("  {} {}, extracting anyway", style("⚠").yellow(), over);
+            return true;
+        }
+        FileCountCheck::Ask => confirm(&format!(
+            "{} has {}. Extract anyway?",
+            display_name(&archive.base_name),
+            over
+        )),
+        FileCountCheck::Skip => false,
+    };
+    if !go_on {
+        let base_name = display_name(&archive.base_name);
+        say!("{} {}", style("⊘").yellow(), style(base_name).yellow());
+        say!("  {} {}, skipping", style("┖─").dim(), over);
+    }
+    go_on
+}
 
-    for part in input.split(',') {
-        let part = part.trim();
-        if part.contains('-') {
-            let range: Vec<&str> = part.split('-').collect();
-            if range.len() == 2 {
-                let start: usize = range[0].parse().context("Invalid range start")?;
-                let end: usize = range[1].parse().context("Invalid range end")?;
-                for i in start..=end {
-                    if i > 0 && i <= max {
-                        selected.push(i - 1);
-                    }
+/// Warn when a ZIP uses legacy ZipCrypto, which is easily broken. Listing
+/// errors are left to extraction.
+fn warn_weak_zip_encryption(archive: &Archive) {
+    match listing::zip_ciphers(archive) {
+        Ok(ciphers) if ciphers.iter().any(|c| listing::is_weak_cipher(c)) => say!(
+            "  {} {}",
+            style("⚠").yellow(),
+            style("Encrypted with legacy ZipCrypto, which is weak; prefer AES-256").yellow()
+        ),
+        Ok(ciphers) => log::debug!("{} ciphers: {:?}", archive.path.display(), ciphers),
+        Err(e) => log::debug!("Cannot list {}: {}", archive.path.display(), e),
+    }
+}
+
+/// Whether an archive needs a password, from a listing with the empty one.
+/// Errors (e.g. backend missing) count as not encrypted and are left to extraction.
+fn archive_is_encrypted(archive: &Archive) -> bool {
+    match archive.archive_type {
+        ArchiveType::Rar => rar_is_encrypted(&archive.path),
+        ArchiveType::SevenZip | ArchiveType::Zip => {
+            let output = Command::new("7zz")
+                .arg("l")
+                .arg("-slt")
+                .arg("-p")
+                .arg(&archive.path)
+                .stdin(Stdio::null())
+                .output();
+            match output {
+                Ok(output) => {
+                    sevenzip_listing_encrypted(&String::from_utf8_lossy(&output.stdout))
+                        || sevenzip_listing_encrypted(&String::from_utf8_lossy(&output.stderr))
+                }
+                Err(e) => {
+                    log::debug!("Cannot list {}: {}", archive.path.display(), e);
+                    false
                 }
-            }
-        } else {
-            let num: usize = part.parse().context("Invalid number")?;
-            if num > 0 && num <= max {
-                selected.push(num - 1);
             }
         }
+        ArchiveType::TarGz | ArchiveType::Custom(_) => false,
     }
+}
 
-    selected.sort();
-    selected.dedup();
-    Ok(selected)
+/// List a RAR without a password (`-p-`) to see if it is encrypted. Errors
+/// (e.g. unrar missing) count as not encrypted and are left to extraction.
+fn rar_is_encrypted(path: &Path) -> bool {
+    let output = Command::new("unrar")
+        .arg("l")
+        .arg("-p-")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output();
+    match output {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            rar_listing_encrypted(&stdout) || rar_listing_encrypted(&stderr)
+        }
+        Err(e) => {
+            log::debug!("Cannot list {}: {}", path.display(), e);
+            false
+        }
+    }
 }
 
-/// Run a command using PTY so it thinks it's in a real terminal
-/// This makes unrar/7zz display percentage progress
-fn run_with_pty(cmd: &mut Command, archive_path: &Path) -> Result<()> {
-    use std::os::unix::process::CommandExt;
+/// Make a file name safe to print: control characters (newlines, ANSI escapes)
+/// are shown escaped instead of being interpreted by the terminal.
+fn display_name(name: &str) -> String {
+    let mut safe = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_control() {
+            safe.extend(c.escape_default());
+        } else {
+            safe.push(c);
+        }
+    }
+    safe
+}
 
-    // Change to the directory containing the archive
-    let archive_dir = archive_path
-        .parent()
-        .ok_or_else(|| anyhow::anyhow!("Cannot get parent directory"))?
-        .canonicalize()
-        .context("Cannot canonicalize archive directory")?;
+/// `display_name` for a path.
+fn display_path(path: &Path) -> String {
+    display_name(&path.to_string_lossy())
+}
 
-    // Get the archive filename (without path) for use after changing directory
-    let archive_name = archive_path
-        .file_name()
-        .ok_or_else(|| anyhow::anyhow!("Cannot get archive filename"))?;
+// Windows support is pending: the PTY layer and the volume links are Unix-only,
+// so the crate doesn't build there yet. The long-path helpers below are only
+// compiled for it.
 
-    // Create fork using from_ptmx
-    let fork = Fork::from_ptmx().context("Failed to create PTY")?;
+/// Windows paths past this length need the `\\?\` prefix to be opened.
+#[cfg(windows)]
+const WINDOWS_MAX_PATH: usize = 260;
 
-    // Handle child process
-    if let Ok(mut _slave) = fork.is_child() {
-        // Change directory before exec
-        std::env::set_current_dir(archive_dir)?;
-
-        // Build new command, replacing the full archive path with just the filename
-        let program = cmd.get_program();
-        let mut new_cmd = std::process::Command::new(program);
-
-        // Find and replace the archive path argument with filename
-        let mut found_archive = false;
-        for arg in cmd.get_args() {
-            if arg == archive_path.as_os_str() {
-                // This is the archive path, replace with filename
-                new_cmd.arg(archive_name);
-                found_archive = true;
-            } else {
-                // Keep other arguments as-is
-                new_cmd.arg(arg);
-            }
-        }
+/// `path` in Windows' verbatim form (`\\?\C:\...`, `\\?\UNC\server\...`),
+/// which lifts the MAX_PATH limit. Verbatim paths skip normalization, so
+/// separators are turned into backslashes. None for a relative path, which
+/// can't be made verbatim.
+#[cfg(windows)]
+fn verbatim_windows_path(path: &str) -> Option<String> {
+    let path = path.replace('/', "\\");
+    if path.starts_with("\\\\?\\") {
+        return Some(path);
+    }
+    if let Some(unc) = path.strip_prefix("\\\\") {
+        return Some(format!("\\\\?\\UNC\\{}", unc));
+    }
+    let drive = path.as_bytes();
+    if drive.len() >= 3 && drive[0].is_ascii_alphabetic() && &drive[1..3] == b":\\" {
+        return Some(format!("\\\\?\\{}", path));
+    }
+    None
+}
 
-        // If we didn't find the archive path, something is wrong
-        if !found_archive {
-            return Err(anyhow::anyhow!("Archive path not found in command arguments"));
-        }
+/// An output path as handed to the backend: a long one is made verbatim so
+/// deep archives don't fail at MAX_PATH.
+#[cfg(windows)]
+fn backend_path(path: &Path) -> Cow<'_, Path> {
+    if path.as_os_str().len() < WINDOWS_MAX_PATH {
+        return Cow::Borrowed(path);
+    }
+    match verbatim_windows_path(&path.to_string_lossy()) {
+        Some(verbatim) => Cow::Owned(PathBuf::from(verbatim)),
+        None => Cow::Borrowed(path),
+    }
+}
 
-        let err = new_cmd.exec();
-        return Err(anyhow::anyhow!("exec failed: {:?}", err));
+/// Password switch for a backend, always a single argument (no shell involved).
+///
+/// Both 7zz and unrar only accept the password glued to the switch (`-pSECRET`);
+/// a separate `-p` makes unrar prompt and treat the password as a file mask.
+/// An empty password means "no password": 7zz gets `-p` (tries the empty
+/// password instead of prompting), unrar gets `-p-` (never prompt).
+fn password_args(archive_type: &ArchiveType, password: &Option<String>) -> Result<Vec<String>> {
+    let pwd = password.as_deref();
+    match archive_type {
+        ArchiveType::SevenZip | ArchiveType::Zip => Ok(match pwd {
+            None => vec![],
+            Some(pwd) => vec![format!("-p{}", pwd)],
+        }),
+        ArchiveType::Rar => match pwd {
+            None | Some("") => Ok(vec!["-p-".to_string()]),
+            // `-p-` is unrar's "no password" switch, so this one can't be passed
+            Some("-") => anyhow::bail!("unrar cannot accept '-' as a password"),
+            Some(pwd) => Ok(vec![format!("-p{}", pwd)]),
+        },
+        ArchiveType::TarGz | ArchiveType::Custom(_) => Ok(vec![]),
     }
+}
 
-    // Handle parent process
-    let mut master = match fork.is_parent() {
-        Ok(m) => m,
-        Err(_) => return Ok(()),
+/// Archive type from leading magic bytes.
+fn sniff_type(header: &[u8]) -> Option<ArchiveType> {
+    if header.starts_with(b"7z\xBC\xAF\x27\x1C") {
+        Some(ArchiveType::SevenZip)
+    } else if header.starts_with(b"PK\x03\x04") {
+        Some(ArchiveType::Zip)
+    } else if header.starts_with(b"Rar!\x1A\x07") {
+        Some(ArchiveType::Rar)
+    } else if header.starts_with(&[0x1F, 0x8B]) {
+        Some(ArchiveType::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Consecutive volumes `name.001`, `name.002`, ... of a split, starting at
+/// `first` and stopping at the first gap.
+fn split_volumes(first: &Path) -> Vec<PathBuf> {
+    let name = first.file_name().and_then(|n| n.to_str());
+    let Some((stem, number)) = name.and_then(|n| n.rsplit_once('.')) else {
+        return vec![first.to_path_buf()];
     };
+    let width = number.len();
+    (1..)
+        .map(|n| first.with_file_name(format!("{}.{:0width$}", stem, n, width = width)))
+        .take_while(|volume| volume.is_file())
+        .collect()
+}
 
-    // Forward output from PTY to stdout
-    let mut buf = [0u8; 8192];
-    loop {
-        match master.read(&mut buf) {
-            Ok(0) => break,
-            Ok(n) => {
-                let _ = std::io::stdout().write_all(&buf[..n]);
-                let _ = std::io::stdout().flush();
-            }
-            Err(_) => break,
+/// First `len` bytes of the split starting at `first`, read across volume
+/// boundaries (a tiny first volume may not hold the whole header).
+fn read_split_header(first: &Path, len: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(len);
+    for volume in split_volumes(first) {
+        let Ok(file) = fs::File::open(&volume) else {
+            break;
+        };
+        let missing = (len - header.len()) as u64;
+        if file.take(missing).read_to_end(&mut header).is_err() || header.len() >= len {
+            break;
         }
     }
+    header
+}
 
-    // Wait for child process and check exit status
-    // On Unix, wait() returns the raw wait status; decode to get actual exit code or signal
-    let raw_status = fork.wait()?;
-    if raw_status == 0 {
-        Ok(())
-    } else {
-        let reason = decode_wait_status(raw_status);
-        anyhow::bail!("{}", reason)
+/// The volumes of the split starting at `first`, failing if numbering has a
+/// gap (e.g. `.001`, `.002`, `.004` is missing `.003`).
+fn contiguous_split_volumes(first: &Path) -> Result<Vec<PathBuf>> {
+    let volumes = split_volumes(first);
+    let name = first
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let Some((stem, number)) = name.rsplit_once('.') else {
+        return Ok(volumes);
+    };
+    let pattern = Regex::new(&format!(r"^{}\.(\d+)$", regex::escape(stem)))?;
+    let dir = first
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let highest = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            pattern.captures(e.file_name().to_str()?)?[1]
+                .parse::<usize>()
+                .ok()
+        })
+        .max()
+        .unwrap_or(0);
+    if highest > volumes.len() {
+        anyhow::bail!(
+            "Part {}.{:0width$} is missing (found parts up to {})",
+            display_name(stem),
+            volumes.len() + 1,
+            highest,
+            width = number.len()
+        );
     }
+    Ok(volumes)
 }
 
-/// Decode Unix wait status into a human-readable string (actual exit code or signal).
-fn decode_wait_status(raw: i32) -> String {
-    #[cfg(unix)]
-    {
-        const WIFEXITED_MASK: i32 = 0x7F;
-        const WEXITSTATUS_SHIFT: i32 = 8;
-        if (raw & WIFEXITED_MASK) == 0 {
-            let code = (raw >> WEXITSTATUS_SHIFT) & 0xFF;
-            let hint = exit_code_hint(code);
-            if hint.is_empty() {
-                format!("Command failed with exit code {}", code)
-            } else {
-                format!("Command failed with exit code {} ({})", code, hint)
-            }
-        } else {
-            let sig = raw & 0x7F;
-            format!("Command killed by signal {} (raw wait status: {})", sig, raw)
-        }
+/// `--join`/`--concat`: concatenate the volumes of the split starting at
+/// `first`, in numeric order, into the file named without the volume number
+/// (`movie.mkv.001` -> `movie.mkv`). With `delete_parts` the volumes are
+/// removed once the joined file is complete.
+fn join_split(first: &Path, delete_parts: bool) -> Result<PathBuf> {
+    let volumes = contiguous_split_volumes(first)?;
+    let target = first.with_extension("");
+    let mut out = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&target)
+        .with_context(|| format!("Cannot create {}", display_path(&target)))?;
+    let copied = volumes.iter().try_for_each(|volume| -> Result<()> {
+        let mut input = fs::File::open(volume)?;
+        std::io::copy(&mut input, &mut out)?;
+        Ok(())
+    });
+    if let Err(e) = copied.and_then(|()| Ok(out.sync_all()?)) {
+        let _ = fs::remove_file(&target);
+        return Err(e.context(format!("Cannot join into {}", display_path(&target))));
     }
-    #[cfg(not(unix))]
-    {
-        format!("Command failed with status {}", raw)
+    if delete_parts {
+        for volume in &volumes {
+            fs::remove_file(volume)
+                .with_context(|| format!("Cannot remove {}", display_path(volume)))?;
+        }
     }
+    Ok(target)
 }
 
-/// Short hint for common unrar/7zz exit codes to help diagnose failed.log.
-fn exit_code_hint(code: i32) -> &'static str {
-    match code {
-        0 => "success",
-        1 => "warning (non-fatal)",
-        2 => "fatal error",
-        3 => "checksum error / damaged data",
-        6 => "file open error (missing part?)",
-        10 => "no files matching",
-        11 => "wrong password",
-        50 => "unrar 50 (often: missing next volume .part02.rar etc)",
-        255 => "user break",
-        _ => "",
-    }
+/// First volumes of splits whose names carry no archive type (`name.001`):
+/// the listed files if any, otherwise everything under `dirs`.
+fn bare_splits(files: &[PathBuf], dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let candidates: Vec<PathBuf> = if files.is_empty() {
+        dirs.iter()
+            .flat_map(WalkDir::new)
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect()
+    } else {
+        files.to_vec()
+    };
+    candidates
+        .into_iter()
+        .filter(|path| {
+            path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+                strip_first_volume(n).is_some() && Archive::parse_type(n).is_none()
+            })
+        })
+        .collect()
 }
 
-/// Append a detailed failure entry to failed.log for later inspection.
-fn log_failed_archive(archive: &Archive, err: &anyhow::Error) {
-    let log_path = "failed.log";
-    let Ok(mut file) = OpenOptions::new().append(true).create(true).open(log_path) else {
-        return;
+/// The bare splits that are not extractable as they are (see `probe_split`).
+fn joinable_splits(
+    files: &[PathBuf],
+    dirs: &[PathBuf],
+    custom_types: &[CustomType],
+) -> Vec<PathBuf> {
+    bare_splits(files, dirs)
+        .into_iter()
+        .filter(|path| Archive::detect(path.clone(), custom_types).is_none())
+        .collect()
+}
+
+/// Strip a first-volume number like `.001` or `.0001` (three or more digits, all
+/// zeros but a final `1`), returning the rest of the name.
+fn strip_first_volume(filename: &str) -> Option<&str> {
+    let (stem, number) = filename.rsplit_once('.')?;
+    let (zeros, last) = number.split_at(number.len().checked_sub(1)?);
+    let is_first = number.len() >= 3 && last == "1" && zeros.bytes().all(|b| b == b'0');
+    is_first.then_some(stem)
+}
+
+/// The type named by the other volumes of a bare first volume: `data.001`
+/// next to `data.7z.002` is a 7z set. Only 7z and zip splits look like this.
+fn sibling_split_type(first: &Path) -> Option<ArchiveType> {
+    let stem = strip_first_volume(first.file_name()?.to_str()?)?;
+    let dir = match first.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
     };
-    let ts = Local::now().format("%Y-%m-%d %H:%M:%S");
-    // Full error chain (anyhow {:#} shows causes)
-    let err_text = format!("{:#}", err);
-    let entry = format!(
-        "---\n\
-         time: {}\n\
-         path: {}\n\
-         base_name: {}\n\
-         type: {}\n\
-         error: {}\n",
-        ts,
-        archive.path.display(),
-        archive.base_name,
-        archive.archive_type.as_str(),
-        err_text.replace('\n', " "),
-    );
-    let _ = writeln!(file, "{}", entry);
+    let pattern = Regex::new(&format!(r"^{}\.(7z|zip)\.\d{{3,}}$", regex::escape(stem))).ok()?;
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .find_map(|e| match &pattern.captures(e.file_name().to_str()?)?[1] {
+            "7z" => Some(ArchiveType::SevenZip),
+            _ => Some(ArchiveType::Zip),
+        })
 }
 
-fn extract_archive(
-    archive: &Archive,
-    _multi_progress: &MultiProgress,
-    test: bool,
-    password: &Option<String>,
-    force: bool,
-) -> Result<()> {
-    let base_name = &archive.base_name;
-    let extract_dir = archive.extract_dir()?;
+/// Links giving every volume of a set one naming scheme, for a set like
+/// `data.001` + `data.7z.002` that 7zz can't follow; removed on drop.
+struct VolumeLinks {
+    dir: PathBuf,
+}
 
-    // Check if already extracted (but skip this check if force is enabled)
-    if !force && extract_dir.exists() {
-        // Check if the directory contains actual files (not just empty stubs)
-        let has_valid_files = WalkDir::new(&extract_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .any(|e| {
-                e.file_type().is_file()
-                    && e.metadata().map(|m| m.len() > 0).unwrap_or(false)
-            });
+impl VolumeLinks {
+    /// None when the volumes already share a naming scheme. Otherwise links
+    /// `base.type.NNN` to each volume in a temporary directory and returns
+    /// the archive pointing at the linked first volume.
+    fn for_mixed(archive: &Archive) -> Result<Option<(Self, Archive)>> {
+        if !matches!(
+            archive.archive_type,
+            ArchiveType::SevenZip | ArchiveType::Zip
+        ) {
+            return Ok(None);
+        }
+        let volumes = archive.volumes();
+        let split = |v: &Path| -> Option<(String, String)> {
+            let (stem, number) = v.file_name()?.to_str()?.rsplit_once('.')?;
+            Some((stem.to_string(), number.to_string()))
+        };
+        let names: Vec<(String, String)> = volumes.iter().filter_map(|v| split(v)).collect();
+        if names.len() != volumes.len() || names.iter().all(|(stem, _)| *stem == names[0].0) {
+            return Ok(None);
+        }
 
-        if has_valid_files {
-            println!(
-                "{} {}",
-                style("⊘").yellow(),
-                style(base_name).yellow(),
-            );
-            println!("  {} Already exists with valid files, skipping", style("┖─").dim());
-            return Ok(());
-        } else {
-            println!(
-                "{} {}",
-                style("⚠").yellow(),
-                style(base_name).yellow(),
-            );
-            println!("  {} Exists but appears incomplete, re-extracting", style("┖─").dim());
-            fs::remove_dir_all(&extract_dir)?;
+        let dir = create_temp_dir(&std::env::temp_dir(), "un7z-volumes")?;
+        let links = VolumeLinks { dir };
+        let ext = archive.archive_type.as_str();
+        let mut first = None;
+        for (volume, (_, number)) in volumes.iter().zip(&names) {
+            let link = links
+                .dir
+                .join(format!("{}.{}.{}", archive.base_name, ext, number));
+            std::os::unix::fs::symlink(std::path::absolute(volume)?, &link)
+                .with_context(|| format!("Cannot link {}", link.display()))?;
+            first.get_or_insert(link);
         }
+        log::debug!("Linked mixed-name volumes into {}", links.dir.display());
+        let linked = Archive {
+            path: first.unwrap_or_else(|| archive.path.clone()),
+            base_name: archive.base_name.clone(),
+            archive_type: archive.archive_type.clone(),
+            type_suffix: archive.type_suffix,
+        };
+        Ok(Some((links, linked)))
     }
+}
 
-    // Tar requires the target directory to exist before extraction
-    if !test && matches!(archive.archive_type, ArchiveType::TarGz) {
+impl Drop for VolumeLinks {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            log::warn!("Cannot remove {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn scan_archives(dir: &Path, opts: &ScanOptions) -> Result<Vec<Archive>> {
+    Ok(scan_report(dir, opts)?.archives)
+}
+
+/// `scan_archives`, keeping the stubs it left out and the files that look
+/// like archives but weren't recognized as one (`--show-unrecognized`).
+fn scan_report(dir: &Path, opts: &ScanOptions) -> Result<Scanned> {
+    let (archives, _, unrecognized) = scan_tree(resolve_scan_dir(dir)?, opts);
+    let (archives, stubs) = split_stubs(archives, opts.min_file_size);
+    Ok(Scanned {
+        archives,
+        stubs,
+        unrecognized,
+    })
+}
+
+/// Why a file named `file_name` that looks like an archive (or a volume of
+/// one) isn't detected, or None when it doesn't look like one. A volume also
+/// gives its set's base name, to leave out volumes of sets that were found.
+fn unrecognized_reason(file_name: &str) -> Option<(String, Option<String>)> {
+    if let Some((archive, number)) = Archive::from_volume(PathBuf::from(file_name)) {
+        let reason = if number > 1 {
+            format!("volume {}, its set's first volume is missing", number)
+        } else {
+            "first volume not numbered like .part01.rar or .7z.001".to_string()
+        };
+        return Some((reason, Some(archive.base_name)));
+    }
+    let (stem, extension) = file_name
+        .rsplit_once('.')
+        .filter(|(stem, _)| !stem.is_empty())?;
+    let extension = extension.to_ascii_lowercase();
+    if extension.len() >= 3 && extension.bytes().all(|b| b.is_ascii_digit()) {
+        let reason = match extension.parse::<u32>() {
+            Ok(1) => "split with no 7z or zip header, other types need --join".to_string(),
+            _ => format!("volume {} of a split whose .001 is missing", extension),
+        };
+        return Some((reason, Some(stem.to_string())));
+    }
+    let old_volume = |prefix: char| {
+        extension.len() == 3
+            && extension.starts_with(prefix)
+            && extension[1..].bytes().all(|b| b.is_ascii_digit())
+    };
+    if old_volume('r') || old_volume('z') {
+        return Some((
+            format!("old-style .{} volume naming is not supported", extension),
+            None,
+        ));
+    }
+    const OTHER_TYPES: [&str; 16] = [
+        "tar", "tbz", "tbz2", "txz", "tzst", "gz", "bz2", "xz", "zst", "lz", "lzh", "lha", "cab",
+        "arj", "iso", "cpio",
+    ];
+    match extension.as_str() {
+        "7z" | "zip" | "rar" => Some((
+            format!(
+                "a single .{0} archive, only split sets are detected; pass it with --format {0}",
+                extension
+            ),
+            None,
+        )),
+        other if OTHER_TYPES.contains(&other) => Some((
+            format!("not a built-in type, teach .{} with --custom-type", other),
+            None,
+        )),
+        _ => None,
+    }
+}
+
+/// `--dir` as given; `-` (or nothing) is the current directory.
+fn parse_scan_dir(input: &str) -> Result<PathBuf> {
+    match input.trim() {
+        "" | "-" => Ok(PathBuf::from(".")),
+        _ => Ok(PathBuf::from(input)),
+    }
+}
+
+/// The canonical form of a scan directory, with an error naming the path
+/// when it is missing or not a directory.
+pub(crate) fn resolve_scan_dir(dir: &Path) -> Result<PathBuf> {
+    let shown = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    match fs::metadata(dir) {
+        Ok(meta) if meta.is_dir() => {}
+        Ok(_) => anyhow::bail!("Scan directory {} is not a directory", shown.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => anyhow::bail!(
+            "Scan directory {} does not exist, check the --dir path",
+            shown.display()
+        ),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Cannot read scan directory {}", shown.display()))
+        }
+    }
+    dir.canonicalize()
+        .with_context(|| format!("Cannot resolve scan directory {}", shown.display()))
+}
+
+/// Give archives of different types that share a base name in one directory
+/// (`data.7z.001` next to `data.part01.rar`) type-suffixed output directories,
+/// since they'd otherwise extract into the same folder. Returns each such
+/// directory and base name, for a warning.
+fn disambiguate_mixed_types(archives: &mut [Archive]) -> Vec<(PathBuf, String)> {
+    let mut groups: std::collections::BTreeMap<(PathBuf, String), Vec<usize>> = Default::default();
+    for (i, archive) in archives.iter().enumerate() {
+        let key = (archive.dir().to_path_buf(), archive.base_name.clone());
+        groups.entry(key).or_default().push(i);
+    }
+
+    let mut mixed = Vec::new();
+    for (key, members) in groups {
+        let first_type = &archives[members[0]].archive_type;
+        if members
+            .iter()
+            .all(|&i| archives[i].archive_type == *first_type)
+        {
+            continue;
+        }
+        for &i in &members {
+            archives[i].type_suffix = true;
+        }
+        mixed.push(key);
+    }
+    mixed
+}
+
+/// Scan an already canonical directory, returning the archives found and every
+/// directory that was read.
+fn scan_tree(dir: PathBuf, opts: &ScanOptions) -> (Vec<Archive>, Vec<PathBuf>, Vec<Rejected>) {
+    let mut archives = Vec::new();
+    let mut unrecognized = Vec::new();
+
+    let threads = std::thread::available_parallelism()
+        .map_or(4, |n| n.get())
+        .clamp(4, 16);
+    let (tx, rx) = std::sync::mpsc::channel();
+    let queue = WalkQueue::new(dir, !opts.root_only);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let (queue, tx) = (&queue, tx.clone());
+            scope.spawn(move || queue.work(&tx));
+        }
+        drop(tx);
+
+        for path in rx {
+            if let Some(archive) = Archive::detect(path.clone(), &opts.custom_types) {
+                log::debug!(
+                    "Detected {} as {} ({})",
+                    archive.path.display(),
+                    archive.archive_type.as_str(),
+                    archive.base_name
+                );
+                archives.push(archive);
+            } else if let Some((reason, volume_of)) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(unrecognized_reason)
+            {
+                unrecognized.push((path, reason, volume_of));
+            }
+        }
+    });
+
+    // Workers finish in any order, so tie-break on the path
+    archives.sort_by(|a, b| {
+        a.base_name
+            .cmp(&b.base_name)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    // Later volumes of the sets that were found aren't missing anything
+    let found: std::collections::HashSet<_> = archives
+        .iter()
+        .map(|archive| (archive.dir(), archive.base_name.as_str()))
+        .collect();
+    let mut unrecognized: Vec<Rejected> = unrecognized
+        .into_iter()
+        .filter(|(path, _, volume_of)| match (path.parent(), volume_of) {
+            (Some(dir), Some(base)) => !found.contains(&(dir, base.as_str())),
+            _ => true,
+        })
+        .map(|(path, reason, _)| (path.to_string_lossy().into_owned(), reason))
+        .collect();
+    unrecognized.sort();
+    (archives, queue.into_visited(), unrecognized)
+}
+
+/// Directories still to be read by the parallel scan, shared by all workers.
+struct WalkQueue {
+    /// Pending directories and the number of workers currently reading one.
+    state: std::sync::Mutex<(Vec<PathBuf>, usize)>,
+    changed: std::sync::Condvar,
+    /// Directories that were read successfully.
+    visited: std::sync::Mutex<Vec<PathBuf>>,
+    /// Whether to read subdirectories, or just the root.
+    descend: bool,
+}
+
+impl WalkQueue {
+    fn new(root: PathBuf, descend: bool) -> Self {
+        WalkQueue {
+            state: std::sync::Mutex::new((vec![root], 0)),
+            changed: std::sync::Condvar::new(),
+            visited: std::sync::Mutex::new(Vec::new()),
+            descend,
+        }
+    }
+
+    fn into_visited(self) -> Vec<PathBuf> {
+        self.visited.into_inner().unwrap()
+    }
+
+    /// Worker loop: read directories until none are pending and no other worker
+    /// can add more, sending every file found to `files`. Symlinked directories
+    /// are not followed; unreadable directories are skipped.
+    fn work(&self, files: &std::sync::mpsc::Sender<PathBuf>) {
+        loop {
+            let dir = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    if let Some(dir) = state.0.pop() {
+                        state.1 += 1;
+                        break dir;
+                    }
+                    if state.1 == 0 {
+                        return;
+                    }
+                    state = self.changed.wait(state).unwrap();
+                }
+            };
+
+            // Hands the subdirectories back and releases the claim even if this
+            // worker panics, so the others never wait for it forever
+            let mut claim = Claim {
+                queue: self,
+                subdirs: Vec::new(),
+            };
+            let subdirs = &mut claim.subdirs;
+            match fs::read_dir(&dir) {
+                Ok(entries) => {
+                    for entry in entries.filter_map(|e| e.ok()) {
+                        let path = entry.path();
+                        match entry.file_type() {
+                            Ok(t) if t.is_dir() => {
+                                if self.descend {
+                                    subdirs.push(path);
+                                }
+                            }
+                            Ok(_) if path.is_file() => {
+                                let _ = files.send(path);
+                            }
+                            _ => log::trace!("Not an archive: {}", path.display()),
+                        }
+                    }
+                    self.visited.lock().unwrap().push(dir);
+                }
+                Err(e) => log::debug!("Cannot read {}: {}", dir.display(), e),
+            }
+        }
+    }
+}
+
+/// A worker's claim on the directory it is reading, in `WalkQueue::work`.
+struct Claim<'a> {
+    queue: &'a WalkQueue,
+    subdirs: Vec<PathBuf>,
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.0.append(&mut self.subdirs);
+        state.1 -= 1;
+        self.queue.changed.notify_all();
+    }
+}
+
+/// A listed path that isn't a usable archive, and why.
+type Rejected = (String, String);
+
+/// Build archives from newline-separated paths (e.g. `find` output), keeping input order.
+/// Returns the recognized archives and the lines that were not.
+fn archives_from_reader<R: BufRead>(
+    reader: R,
+    scan_opts: &ScanOptions,
+    format: Option<&ArchiveType>,
+) -> Result<Scanned> {
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("Failed to read archive list")?;
+        let line = line.trim();
+        if !line.is_empty() {
+            paths.push(PathBuf::from(line));
+        }
+    }
+    archives_from_paths(paths, scan_opts, format)
+}
+
+/// Build archives from explicitly listed files, keeping their order. With a
+/// forced `format` every existing file is taken as that type regardless of its
+/// name. A listed directory is scanned like `--dir` (by name only, with the
+/// same `scan_opts`). Returns the recognized archives, the stubs found in
+/// listed directories and, for the paths that were not recognized, the path
+/// and why (unlike scanning, which drops such files silently).
+fn archives_from_paths(
+    paths: impl IntoIterator<Item = PathBuf>,
+    scan_opts: &ScanOptions,
+    format: Option<&ArchiveType>,
+) -> Result<Scanned> {
+    let custom_types = &scan_opts.custom_types;
+    let mut archives = Vec::new();
+    let mut stubs = Vec::new();
+    let mut rejected = Vec::new();
+
+    for listed in paths {
+        // Absolute, because extraction runs from the archive's own directory
+        let path = std::path::absolute(&listed)?;
+        if path.is_dir() {
+            let found = scan_report(&path, scan_opts)?;
+            if found.archives.is_empty() && found.stubs.is_empty() {
+                rejected.push((
+                    listed.to_string_lossy().into_owned(),
+                    "a directory with no archives in it".to_string(),
+                ));
+            }
+            archives.extend(found.archives);
+            stubs.extend(found.stubs);
+            continue;
+        }
+        let archive = match format {
+            Some(archive_type) => Archive::forced(path, archive_type.clone()),
+            None => Archive::detect(path, custom_types),
+        };
+        match archive {
+            Some(archive) if archive.path.is_file() => archives.push(archive),
+            _ => rejected.push((
+                listed.to_string_lossy().into_owned(),
+                rejection_reason(&std::path::absolute(&listed)?),
+            )),
+        }
+    }
+
+    // A directory and a file inside it can both be listed
+    let mut seen = std::collections::HashSet::new();
+    let mut seen_path =
+        |a: &Archive| seen.insert(a.path.canonicalize().unwrap_or_else(|_| a.path.clone()));
+    archives.retain(&mut seen_path);
+    stubs.retain(&mut seen_path);
+    Ok(Scanned {
+        archives,
+        stubs,
+        unrecognized: rejected,
+    })
+}
+
+/// List the archives a scan left out as smaller than `--min-file-size`.
+fn print_stubs(stubs: &[Archive], min_file_size: u64) {
+    if stubs.is_empty() {
+        return;
+    }
+    println!(
+        "{} Skipped {} stub file(s) smaller than {} (--min-file-size):",
+        style("⊘").yellow(),
+        style(stubs.len()).yellow(),
+        HumanBytes(min_file_size)
+    );
+    for stub in stubs {
+        println!("  {} {}", style("•").dim(), display_path(&stub.path));
+    }
+}
+
+/// Why an explicitly listed path can't be extracted.
+fn rejection_reason(path: &Path) -> String {
+    if !path.exists() {
+        return "no such file".to_string();
+    }
+    if !path.is_file() {
+        return "not a file".to_string();
+    }
+    match Archive::from_volume(path.to_path_buf()) {
+        Some((archive, number)) if number > 1 => format!(
+            "volume {} of a set, pass the first volume ({}) instead",
+            number,
+            display_name(&archive.base_name)
+        ),
+        _ => "unrecognized archive type, use --format 7z|zip|rar|tar.gz to force one".to_string(),
+    }
+}
+
+/// `un7z detect`: the type of `path` as listed files are detected (by name,
+/// custom type, or a split's header magic), None if it isn't an archive.
+fn detected_type(path: &Path, custom_types: &[CustomType]) -> Option<ArchiveType> {
+    Archive::detect(path.to_path_buf(), custom_types).map(|archive| archive.archive_type)
+}
+
+/// One `un7z detect` output line for `path`.
+fn detect_line(path: &Path, archive_type: Option<&ArchiveType>, json: bool) -> String {
+    if json {
+        serde_json::json!({
+            "path": path,
+            "type": archive_type.map(ArchiveType::as_str),
+        })
+        .to_string()
+    } else {
+        let name = archive_type.map_or("unknown", ArchiveType::as_str);
+        format!("{}\t{}", path.display(), name)
+    }
+}
+
+/// Print the detected type of each file; an error if any is unknown.
+fn run_detect(files: &[PathBuf], custom_types: &[CustomType], json: bool) -> Result<()> {
+    let mut unknown = 0;
+    for path in files {
+        let archive_type = detected_type(path, custom_types);
+        unknown += usize::from(archive_type.is_none());
+        println!("{}", detect_line(path, archive_type.as_ref(), json));
+    }
+    if unknown > 0 {
+        anyhow::bail!("{} of {} file(s) not recognized", unknown, files.len());
+    }
+    Ok(())
+}
+
+/// Parse a `--format` name into a built-in archive type.
+fn parse_format(name: &str) -> Result<ArchiveType> {
+    match name
+        .trim()
+        .trim_start_matches('.')
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "7z" => Ok(ArchiveType::SevenZip),
+        "zip" => Ok(ArchiveType::Zip),
+        "rar" => Ok(ArchiveType::Rar),
+        "tar.gz" | "tgz" => Ok(ArchiveType::TarGz),
+        _ => anyhow::bail!("Unknown format '{}' (use 7z, zip, rar or tar.gz)", name),
+    }
+}
+
+/// One detected archive, as reported by `--list-archives`.
+#[derive(Debug, Serialize)]
+struct ArchiveRecord {
+    path: PathBuf,
+    base_name: String,
+    #[serde(rename = "type")]
+    archive_type: String,
+    volumes: usize,
+    total_size: u64,
+}
+
+impl ArchiveRecord {
+    fn new(archive: &Archive) -> Self {
+        ArchiveRecord {
+            path: archive.path.clone(),
+            base_name: archive.base_name.clone(),
+            archive_type: archive.archive_type.as_str().to_string(),
+            volumes: archive.volumes().len(),
+            total_size: archive.total_size(),
+        }
+    }
+}
+
+/// Print detected archives as a table, or as a JSON array with `json`.
+fn print_archive_list(archives: &[Archive], json: bool) -> Result<()> {
+    let records: Vec<ArchiveRecord> = archives.iter().map(ArchiveRecord::new).collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    println!(
+        "\n{:<30} {:<7} {:>7} {:>10}  {}",
+        style("Name").bold(),
+        style("Type").bold(),
+        style("Volumes").bold(),
+        style("Size").bold(),
+        style("Path").bold()
+    );
+    for record in &records {
+        println!(
+            "{:<30} {:<7} {:>7} {:>10}  {}",
+            display_name(&record.base_name),
+            style(display_name(&record.archive_type)).cyan(),
+            record.volumes,
+            HumanBytes(record.total_size).to_string(),
+            style(display_path(&record.path)).dim()
+        );
+    }
+    Ok(())
+}
+
+/// `--probe`: one line of metadata per archive, or a JSON array with `json`.
+/// An archive that can't be listed is reported and the rest still probed.
+fn print_probes(archives: &[Archive], password: &Option<String>, json: bool) -> Result<()> {
+    let probes: Vec<Result<listing::Probe>> = archives
+        .iter()
+        .map(|a| listing::probe(a, password))
+        .collect();
+    if json {
+        let records: Vec<serde_json::Value> = archives
+            .iter()
+            .zip(&probes)
+            .map(|(archive, probe)| {
+                let mut record = serde_json::json!({
+                    "path": archive.path,
+                    "type": archive.archive_type.as_str(),
+                });
+                match probe {
+                    Ok(probe) => {
+                        if let serde_json::Value::Object(fields) = serde_json::to_value(probe)? {
+                            record.as_object_mut().unwrap().extend(fields);
+                        }
+                    }
+                    Err(e) => record["error"] = format!("{:#}", e).into(),
+                }
+                Ok(record)
+            })
+            .collect::<Result<_>>()?;
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    println!(
+        "\n{:<30} {:<7} {:>6} {:>10} {:<5} {:<9} {}",
+        style("Name").bold(),
+        style("Type").bold(),
+        style("Files").bold(),
+        style("Unpacked").bold(),
+        style("Solid").bold(),
+        style("Encrypted").bold(),
+        style("Methods").bold()
+    );
+    for (archive, probe) in archives.iter().zip(&probes) {
+        let name = display_name(&archive.base_name);
+        let archive_type = style(display_name(archive.archive_type.as_str())).cyan();
+        match probe {
+            Ok(probe) => {
+                let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+                println!(
+                    "{:<30} {:<7} {:>6} {:>10} {:<5} {:<9} {}",
+                    name,
+                    archive_type,
+                    probe.files,
+                    HumanBytes(probe.unpacked_size).to_string(),
+                    yes_no(probe.solid),
+                    yes_no(probe.encrypted),
+                    probe.methods.join(", ")
+                );
+            }
+            Err(e) => println!(
+                "{:<30} {:<7} {}",
+                name,
+                archive_type,
+                style(format!("{:#}", e)).red()
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Parse a duration like `45s`, `30m`, `24h`, `7d` or `2w`.
+fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let split = input.find(|c: char| !c.is_ascii_digit()).ok_or_else(|| {
+        anyhow::anyhow!("Missing unit in duration '{}' (use s, m, h, d or w)", input)
+    })?;
+    let (number, unit) = input.split_at(split);
+    let value: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration '{}'", input))?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => anyhow::bail!(
+            "Unknown unit '{}' in duration '{}' (use s, m, h, d or w)",
+            unit,
+            input
+        ),
+    };
+    value
+        .checked_mul(secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| anyhow::anyhow!("Duration '{}' is too large", input))
+}
+
+/// Parse a size like `500M` or `20G` (binary units; a bare number is bytes).
+fn parse_size(input: &str) -> Result<u64> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let value: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid size '{}'", input))?;
+    let shift = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => anyhow::bail!(
+            "Unknown unit '{}' in size '{}' (use K, M, G or T)",
+            unit,
+            input
+        ),
+    };
+    value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| anyhow::anyhow!("Size '{}' is too large", input))
+}
+
+/// Keep only archives whose primary volume was modified within `window` before `now`.
+fn filter_since(archives: Vec<Archive>, window: Duration, now: SystemTime) -> Vec<Archive> {
+    let cutoff = now.checked_sub(window).unwrap_or(SystemTime::UNIX_EPOCH);
+    archives
+        .into_iter()
+        .filter(|archive| {
+            fs::metadata(&archive.path)
+                .and_then(|m| m.modified())
+                .map(|mtime| mtime >= cutoff)
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// Split off archives whose primary volume is under `min_size` bytes: stubs
+/// and placeholders left by download managers. Returns (kept, stubs).
+fn split_stubs(archives: Vec<Archive>, min_size: u64) -> (Vec<Archive>, Vec<Archive>) {
+    archives
+        .into_iter()
+        .partition(|archive| fs::metadata(&archive.path).is_ok_and(|m| m.len() >= min_size))
+}
+
+/// Wait up to `timeout` for a line on `input` (reading the file `fd`), then
+/// read it; None if nothing arrived in time. Waiting with poll(2) means
+/// nothing is left reading after a timeout, so later prompts get what the
+/// user types. A line already in `input`'s buffer (say, pasted together with
+/// an earlier one) is read without waiting, since poll can't see it.
+fn read_line_within(
+    fd: std::os::fd::BorrowedFd<'_>,
+    input: &mut impl BufRead,
+    timeout: Option<Duration>,
+) -> Result<Option<String>> {
+    use std::os::fd::AsRawFd;
+    fn read(input: &mut impl BufRead) -> Result<Option<String>> {
+        let mut line = String::new();
+        input.read_line(&mut line)?;
+        Ok(Some(line))
+    }
+    // A timeout too long for the clock to represent is no timeout
+    let Some(deadline) = timeout.and_then(|timeout| Instant::now().checked_add(timeout)) else {
+        return read(input);
+    };
+    if has_buffered(fd, input)? {
+        return read(input);
+    }
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut poll_fd = libc::pollfd {
+            fd: fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = left.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: one valid pollfd, borrowed for the duration of the call
+        match unsafe { libc::poll(&mut poll_fd, 1, millis) } {
+            0 => return Ok(None),
+            n if n > 0 => return read(input),
+            _ => {
+                let error = std::io::Error::last_os_error();
+                if error.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(error).context("Waiting for the selection failed");
+                }
+            }
+        }
+    }
+}
+
+/// Whether `input` has buffered data, checked without blocking: with `fd`
+/// non-blocking, `fill_buf` returns the buffer as it is, or fails with
+/// `WouldBlock` when there is nothing to read.
+fn has_buffered(fd: std::os::fd::BorrowedFd<'_>, input: &mut impl BufRead) -> Result<bool> {
+    use std::os::fd::AsRawFd;
+    let fd = fd.as_raw_fd();
+    // SAFETY: fcntl(2) on a borrowed, open descriptor, touching only its flags
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("Cannot check for typed input");
+    }
+    let buffered = match input.fill_buf() {
+        Ok(buf) => Ok(!buf.is_empty()),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e).context("Cannot check for typed input"),
+    };
+    // SAFETY: as above, restoring the flags read before
+    unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+    buffered
+}
+
+/// The archives a prompt answer selects; `None` (no answer in time) falls
+/// back to `on_timeout`.
+fn selection_from_answer(
+    answer: Option<&str>,
+    count: usize,
+    on_timeout: OnPromptTimeout,
+) -> Result<Vec<usize>> {
+    let Some(input) = answer.map(str::trim) else {
+        return match on_timeout {
+            OnPromptTimeout::All => Ok((0..count).collect()),
+            OnPromptTimeout::Abort => {
+                anyhow::bail!("No selection before --prompt-timeout, nothing extracted")
+            }
+        };
+    };
+    if input.is_empty() || input.eq_ignore_ascii_case("all") {
+        Ok((0..count).collect())
+    } else {
+        parse_selection(input, count)
+    }
+}
+
+fn select_archives(
+    archives: &[Archive],
+    timeout: Option<Duration>,
+    on_timeout: OnPromptTimeout,
+) -> Result<Vec<usize>> {
+    use console::Term;
+    use std::os::fd::AsFd;
+
+    println!(
+        "\n{} {} {} {}\n",
+        style("Found").bold().cyan(),
+        style(archives.len()).bold().yellow(),
+        style("archives:").bold().cyan(),
+        style("(Select with space, confirm with Enter)").dim()
+    );
+
+    let term = Term::stdout();
+
+    for (i, archive) in archives.iter().enumerate() {
+        let name = display_name(&archive.base_name);
+        let ext = display_name(archive.archive_type.as_str());
+
+        println!(
+            "{:>3}. [{}] {} ({})",
+            style(i + 1).bold().dim(),
+            style(" ").white().on_black(),
+            style(name).bold().white(),
+            style(ext).cyan()
+        );
+    }
+
+    println!(
+        "\n{}",
+        style("Enter numbers (e.g., 1,3,5-7) or 'all':").bold()
+    );
+    print!("{} ", style(">").cyan());
+
+    let _ = term.flush();
+    let stdin = std::io::stdin();
+    let answer = read_line_within(stdin.as_fd(), &mut stdin.lock(), timeout)?;
+    if answer.is_none() && on_timeout == OnPromptTimeout::All {
+        println!(
+            "\n{} No answer in time, extracting all",
+            style("⏱").yellow()
+        );
+    }
+    selection_from_answer(answer.as_deref(), archives.len(), on_timeout)
+}
+
+/// Write the selected archives' paths (canonical, as in the success log) to
+/// `path`, one per line, replacing what was there.
+fn save_selection(path: &Path, archives: &[Archive], indices: &[usize]) -> Result<()> {
+    let content: String = indices
+        .iter()
+        .map(|&i| format!("{}\n", success_log_key(&archives[i]).display()))
+        .collect();
+    fs::write(path, content).with_context(|| format!("Cannot save selection to {}", path.display()))
+}
+
+/// The indices of the archives a saved selection names, in scan order, and the
+/// saved paths that no longer match any archive.
+fn load_selection(path: &Path, archives: &[Archive]) -> Result<(Vec<usize>, Vec<PathBuf>)> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Cannot read selection {}", path.display()))?;
+    let saved: Vec<PathBuf> = content
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(PathBuf::from)
+        .collect();
+    let keys: Vec<PathBuf> = archives.iter().map(success_log_key).collect();
+    let indices = (0..archives.len())
+        .filter(|&i| saved.contains(&keys[i]))
+        .collect();
+    let missing = saved.into_iter().filter(|p| !keys.contains(p)).collect();
+    Ok((indices, missing))
+}
+
+/// `--select`: the same input the prompt takes, but a number outside
+/// `1..=max` is an error instead of being left out.
+fn parse_select_option(input: &str, max: usize) -> Result<Vec<usize>> {
+    let input = input.trim();
+    if input.eq_ignore_ascii_case("all") {
+        return Ok((0..max).collect());
+    }
+    for bound in input.split([',', '-']) {
+        if let Ok(number) = bound.trim().parse::<usize>() {
+            if number == 0 || number > max {
+                anyhow::bail!(
+                    "--select: {} is out of range, {} archive(s) found",
+                    number,
+                    max
+                );
+            }
+        }
+    }
+    parse_selection(input, max)
+}
+
+fn parse_selection(input: &str, max: usize) -> Result<Vec<usize>> {
+    let mut selected = Vec::new();
+
+    for part in input.split(',') {
+        let part = part.trim();
+        if part.contains('-') {
+            let range: Vec<&str> = part.split('-').collect();
+            if range.len() == 2 {
+                let start: usize = range[0].parse().context("Invalid range start")?;
+                let end: usize = range[1].parse().context("Invalid range end")?;
+                for i in start..=end {
+                    if i > 0 && i <= max {
+                        selected.push(i - 1);
+                    }
+                }
+            }
+        } else {
+            let num: usize = part.parse().context("Invalid number")?;
+            if num > 0 && num <= max {
+                selected.push(num - 1);
+            }
+        }
+    }
+
+    selected.sort();
+    selected.dedup();
+    Ok(selected)
+}
+
+/// Render a command line for logs and echoes, quoting arguments that contain
+/// whitespace. Password switches (`-p<pwd>`) are shown as `-p***`; `-p-` and
+/// a bare `-p` carry no secret and are kept as they are.
+fn redacted_command_string(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if arg.starts_with("-p") && arg != "-p" && arg != "-p-" {
+                "-p***".to_string()
+            } else if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("{:?}", arg)
+            } else {
+                arg.into_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Log the backend invocation at debug level.
+fn log_command(cmd: &Command, dir: &Path) {
+    log::debug!(
+        "Running `{}` in {}",
+        redacted_command_string(cmd),
+        dir.display()
+    );
+}
+
+/// `cmd` set to run in `dir` (the archive's directory), with the archive path
+/// argument replaced by its bare file name. The directory is set on the
+/// command itself, so nothing changes for this process or other backends.
+fn rebase_command(cmd: &Command, archive_path: &Path, dir: &Path) -> Result<Command> {
+    let archive_name = archive_path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Cannot get archive filename"))?;
+    let mut rebased = Command::new(cmd.get_program());
+    let mut found_archive = false;
+    for arg in cmd.get_args() {
+        if arg == archive_path.as_os_str() {
+            rebased.arg(archive_name);
+            found_archive = true;
+        } else {
+            rebased.arg(arg);
+        }
+    }
+    if !found_archive {
+        anyhow::bail!("Archive path not found in command arguments");
+    }
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => rebased.env(key, value),
+            None => rebased.env_remove(key),
+        };
+    }
+    rebased.current_dir(dir);
+    Ok(rebased)
+}
+
+/// The directory a backend runs in: the one containing the archive.
+fn backend_dir(archive_path: &Path) -> Result<PathBuf> {
+    archive_path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Cannot get parent directory"))?
+        .canonicalize()
+        .context("Cannot canonicalize archive directory")
+}
+
+/// Run a command using PTY so it thinks it's in a real terminal
+/// This makes unrar/7zz display percentage progress
+/// With `show_output` false the backend still gets a terminal (so it shows
+/// progress and never blocks on output), but nothing it prints is forwarded.
+fn run_with_pty(
+    cmd: &mut Command,
+    archive_path: &Path,
+    on_progress: &mut dyn FnMut(u64),
+    profile: ProgressProfile,
+    guard: Option<&OutputGuard>,
+    show_output: bool,
+) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let archive_dir = backend_dir(archive_path)?;
+    let mut child_cmd = rebase_command(cmd, archive_path, &archive_dir)?;
+
+    log_command(cmd, &archive_dir);
+
+    // Create fork using from_ptmx
+    let fork = Fork::from_ptmx().context("Failed to create PTY")?;
+
+    // Handle child process: exec never returns on success, and the fork must
+    // not go on running our code otherwise
+    if let Ok(mut _slave) = fork.is_child() {
+        let err = child_cmd.exec();
+        eprintln!("exec failed: {}", err);
+        std::process::exit(127);
+    }
+
+    // Handle parent process
+    let mut master = match fork.is_parent() {
+        Ok(m) => m,
+        Err(_) => return Ok(()),
+    };
+    if let (Some(guard), Fork::Parent(pid, _)) = (guard, &fork) {
+        guard.set_pid(*pid as u32);
+    }
+
+    let parser = &mut ProgressParser::new(profile);
+    let tail = if show_output {
+        pump_pty_output(&mut master, &mut std::io::stdout(), parser, on_progress)
+    } else {
+        pump_pty_output(&mut master, &mut std::io::sink(), parser, on_progress)
+    };
+
+    // Wait for child process and check exit status
+    // On Unix, wait() returns the raw wait status; decode to get actual exit code or signal
+    let raw_status = fork.wait()?;
+    if raw_status == 0 {
+        Ok(())
+    } else {
+        Err(BackendError {
+            reason: decode_wait_status(raw_status),
+            output: tail.into_lines(),
+        }
+        .into())
+    }
+}
+
+/// Read the PTY until the backend closes it, copying everything to `out` and
+/// keeping the tail for error messages (stdout and stderr are one stream on a PTY).
+fn pump_pty_output(
+    master: &mut impl Read,
+    out: &mut dyn Write,
+    parser: &mut ProgressParser,
+    on_progress: &mut dyn FnMut(u64),
+) -> OutputTail {
+    let mut tail = OutputTail::default();
+    let mut buf = [0u8; 8192];
+    loop {
+        match master.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                let _ = out.write_all(&buf[..n]);
+                let _ = out.flush();
+                tail.push(&buf[..n]);
+                if let Some(percent) = parser.feed(&buf[..n]) {
+                    on_progress(percent);
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    tail
+}
+
+/// Our own 0-100% bar, for tar and `--no-pty-progress`.
+fn percent_bar(multi_progress: &MultiProgress) -> ProgressBar {
+    let bar = multi_progress.add(ProgressBar::new(100));
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("  {bar:40.cyan/blue} {pos:>3}%")
+            .unwrap()
+            .progress_chars("█▓░"),
+    );
+    bar
+}
+
+/// The last `NN%` a backend printed in `output`, as 7zz and unrar report progress.
+fn last_percent(output: &[u8]) -> Option<u64> {
+    let mut found = None;
+    for (i, _) in output.iter().enumerate().filter(|&(_, &b)| b == b'%') {
+        let digits = output[..i]
+            .iter()
+            .rev()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if (1..=3).contains(&digits) {
+            let number = std::str::from_utf8(&output[i - digits..i]).ok()?;
+            found = number.parse().ok().filter(|&p| p <= 100).or(found);
+        }
+    }
+    found
+}
+
+/// Where a backend puts its percent in the text it redraws, so that a `NN%`
+/// in a file name isn't taken for progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ProgressProfile {
+    /// 7-Zip 15 and later (`7zz`, p7zip 16.02): ` 45% 12 - name`
+    SevenZip,
+    /// unrar: `Extracting  name      45%`, the percent redrawn in place
+    Unrar,
+    /// Anything else: the last `NN%` seen
+    Generic,
+}
+
+impl ProgressProfile {
+    /// The profile for the 7-Zip `7zz` turned out to be, if it could tell.
+    fn for_7z(version: Option<&SevenZipVersion>) -> Self {
+        match version {
+            Some(version) if version.major >= 15 => ProgressProfile::SevenZip,
+            _ => ProgressProfile::Generic,
+        }
+    }
+
+    /// The percent one redrawn segment of output shows, if it shows one.
+    fn percent_in(self, segment: &[u8]) -> Option<u64> {
+        let text = std::str::from_utf8(segment).ok()?;
+        let digits = match self {
+            ProgressProfile::SevenZip => {
+                let (number, rest) = text.trim_start().split_once('%')?;
+                (rest.is_empty() || rest.starts_with(' ')).then_some(number)?
+            }
+            ProgressProfile::Unrar => {
+                let text = text.trim_end().strip_suffix('%')?;
+                text.rsplit(' ').next()?
+            }
+            ProgressProfile::Generic => return last_percent(segment),
+        };
+        if !(1..=3).contains(&digits.len()) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok().filter(|&p| p <= 100)
+    }
+}
+
+/// The 7-Zip build `7zz` runs, from its banner.
+#[derive(Debug, PartialEq, Eq)]
+struct SevenZipVersion {
+    major: u32,
+    minor: u32,
+    /// The p7zip port rather than 7-Zip's own Linux build.
+    p7zip: bool,
+}
+
+/// Read `7-Zip (z) 23.01 (x64) : ...` or `7-Zip [64] 16.02 : ...` (p7zip).
+fn parse_7z_version(banner: &str) -> Option<SevenZipVersion> {
+    let re = Regex::new(r"7-Zip(?: \[\d+\]| \(\w+\))? (\d+)\.(\d+)").unwrap();
+    let captures = re.captures(banner)?;
+    Some(SevenZipVersion {
+        major: captures[1].parse().ok()?,
+        minor: captures[2].parse().ok()?,
+        p7zip: banner.contains("p7zip"),
+    })
+}
+
+/// The progress profile for 7z and zip archives, from asking `7zz` for its
+/// version the first time it's needed.
+fn seven_zip_profile() -> ProgressProfile {
+    static PROFILE: std::sync::OnceLock<ProgressProfile> = std::sync::OnceLock::new();
+    *PROFILE.get_or_init(|| {
+        let banner = backend_output(Path::new("7zz"), &[]);
+        let version = banner.as_deref().and_then(parse_7z_version);
+        let profile = ProgressProfile::for_7z(version.as_ref());
+        log::debug!("7zz is {:?}, reading progress as {:?}", version, profile);
+        profile
+    })
+}
+
+/// Reads progress out of a backend's output as it arrives. Output is cut
+/// into segments at line breaks, carriage returns and backspaces (how the
+/// backends redraw), and the unfinished one is kept for the next chunk, so
+/// a percent split across two reads isn't misread.
+struct ProgressParser {
+    profile: ProgressProfile,
+    segment: Vec<u8>,
+    /// The unfinished segment's percent was reported already.
+    reported: bool,
+}
+
+impl ProgressParser {
+    fn new(profile: ProgressProfile) -> Self {
+        ProgressParser {
+            profile,
+            segment: Vec::new(),
+            reported: false,
+        }
+    }
+
+    /// The latest percent in the output so far, if `chunk` changed it.
+    fn feed(&mut self, chunk: &[u8]) -> Option<u64> {
+        if self.profile == ProgressProfile::Generic {
+            return last_percent(chunk);
+        }
+        let mut found = None;
+        for &byte in chunk {
+            if matches!(byte, b'\n' | b'\r' | 0x08) {
+                if !std::mem::take(&mut self.reported) {
+                    found = self.profile.percent_in(&self.segment).or(found);
+                }
+                self.segment.clear();
+            } else {
+                self.segment.push(byte);
+            }
+        }
+        // 7-Zip waits after printing ` 45% 12 - name`, before any redraw
+        if self.profile == ProgressProfile::SevenZip && !self.reported {
+            if let Some(percent) = self.profile.percent_in(&self.segment) {
+                self.reported = true;
+                found = Some(percent);
+            }
+        }
+        found
+    }
+}
+
+/// `--progress-fd`: machine-readable progress for frontends, one
+/// `<archive number> <percent>` line per update, flushed as written.
+struct ProgressFd {
+    fd: u32,
+    out: std::sync::Mutex<fs::File>,
+}
+
+impl ProgressFd {
+    /// Open a descriptor the parent process left open for us.
+    fn open(fd: u32) -> Result<Self> {
+        let out = OpenOptions::new()
+            .write(true)
+            .open(format!("/dev/fd/{}", fd))
+            .with_context(|| format!("File descriptor {} is not open for writing", fd))?;
+        Ok(ProgressFd {
+            fd,
+            out: std::sync::Mutex::new(out),
+        })
+    }
+
+    fn report(&self, number: usize, percent: u64) {
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        let line = format!("{} {}\n", number, percent);
+        if let Err(e) = out.write_all(line.as_bytes()).and_then(|()| out.flush()) {
+            log::warn!("Cannot write progress to fd {}: {}", self.fd, e);
+        }
+    }
+}
+
+/// How often `OutputGuard` measures the output.
+const OUTPUT_GUARD_INTERVAL: Duration = Duration::from_millis(250);
+
+/// `--max-output-size`: measures the output while the backend runs and kills
+/// the backend once it grows past the limit (a decompression bomb).
+struct OutputGuard {
+    path: PathBuf,
+    limit: u64,
+    pid: std::sync::Mutex<Option<u32>>,
+    tripped: std::sync::atomic::AtomicBool,
+}
+
+impl OutputGuard {
+    fn new(path: PathBuf, limit: u64) -> Self {
+        OutputGuard {
+            path,
+            limit,
+            pid: std::sync::Mutex::new(None),
+            tripped: Default::default(),
+        }
+    }
+
+    /// Called by the runner once the backend process exists.
+    fn set_pid(&self, pid: u32) {
+        *self.pid.lock().unwrap() = Some(pid);
+    }
+
+    fn tripped(&self) -> bool {
+        self.tripped.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Measure every `interval` until `stop` fires or the limit is crossed.
+    fn watch(&self, stop: std::sync::mpsc::Receiver<()>, interval: Duration) {
+        use std::sync::mpsc::RecvTimeoutError;
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+            let size = tree_size(&self.path);
+            if size <= self.limit {
+                continue;
+            }
+            log::debug!("{} is {} bytes, over the limit", self.path.display(), size);
+            self.tripped
+                .store(true, std::sync::atomic::Ordering::SeqCst);
+            if let Some(pid) = *self.pid.lock().unwrap() {
+                // SAFETY: kill(2) takes plain integers and touches no memory of ours
+                if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+                    let error = std::io::Error::last_os_error();
+                    log::warn!("Cannot stop the backend (pid {}): {}", pid, error);
+                }
+            }
+            return;
+        }
+    }
+}
+
+/// How many times larger the output is than the archive; `None` for an empty archive.
+fn compression_ratio(input_bytes: u64, output_bytes: u64) -> Option<f64> {
+    (input_bytes > 0).then(|| output_bytes as f64 / input_bytes as f64)
+}
+
+/// Print the extracted size against the archive set's size, e.g. `ratio 3.4x`.
+fn report_ratio(archive: &Archive, output_bytes: u64, json: bool) {
+    let input_bytes = archive.total_size();
+    let ratio = compression_ratio(input_bytes, output_bytes);
+    if json {
+        let line = serde_json::json!({
+            "archive": archive.path,
+            "input_bytes": input_bytes,
+            "output_bytes": output_bytes,
+            "ratio": ratio.map(|r| (r * 100.0).round() / 100.0),
+        });
+        println!("{}", line);
+        return;
+    }
+    let Some(ratio) = ratio else {
+        return;
+    };
+    say!(
+        "  {} {} from {}, ratio {}",
+        style("┖─").dim(),
+        HumanBytes(output_bytes),
+        HumanBytes(input_bytes),
+        style(format!("{:.1}x", ratio)).cyan()
+    );
+}
+
+/// Total size of the regular files under `path` (or of `path` itself).
+fn tree_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Number of output lines kept for a failed backend's error.
+const OUTPUT_TAIL_LINES: usize = 10;
+
+/// The last lines a backend printed. Carriage returns and backspaces are
+/// applied like a terminal would, so progress redraws don't fill the buffer.
+#[derive(Debug, Default)]
+struct OutputTail {
+    lines: std::collections::VecDeque<String>,
+    partial: Vec<u8>,
+    /// A `\r` was seen: the next text overwrites the current line.
+    carriage_return: bool,
+}
+
+impl OutputTail {
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            match byte {
+                b'\n' => self.end_line(),
+                b'\r' => self.carriage_return = true,
+                0x08 => {
+                    self.partial.pop();
+                }
+                _ => {
+                    if std::mem::take(&mut self.carriage_return) {
+                        self.partial.clear();
+                    }
+                    self.partial.push(byte);
+                }
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        self.carriage_return = false;
+        let text = String::from_utf8_lossy(&self.partial);
+        let line = console::strip_ansi_codes(&text).trim().to_string();
+        self.partial.clear();
+        if line.is_empty() {
+            return;
+        }
+        if self.lines.len() == OUTPUT_TAIL_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    fn into_lines(mut self) -> Vec<String> {
+        self.end_line();
+        self.lines.into()
+    }
+}
+
+/// A backend exited unsuccessfully. Shown with the most telling line of its
+/// output; `failed.log` gets all of `output`.
+#[derive(Debug)]
+struct BackendError {
+    reason: String,
+    output: Vec<String>,
+}
+
+impl BackendError {
+    /// The last `error:` line (7zz's `ERROR: Wrong password`), or else the last line.
+    fn summary_line(&self) -> Option<&str> {
+        self.output
+            .iter()
+            .rev()
+            .find(|line| line.to_lowercase().contains("error:"))
+            .or(self.output.last())
+            .map(String::as_str)
+    }
+}
+
+impl std::fmt::Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.summary_line() {
+            Some(line) => write!(f, "{}: {}", self.reason, line),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// List the entries of a tar.gz archive via `tar tzf`.
+fn list_tar_entries(archive_path: &Path) -> Result<Vec<String>> {
+    let output = Command::new("tar")
+        .arg("tzf")
+        .arg(archive_path)
+        .output()
+        .context("Failed to run tar")?;
+    if !output.status.success() {
+        let mut tail = OutputTail::default();
+        tail.push(&output.stderr);
+        let err = BackendError {
+            reason: exit_status_reason(output.status),
+            output: tail.into_lines(),
+        };
+        return Err(anyhow::Error::new(err).context("Cannot list tar archive"));
+    }
+    let listing = String::from_utf8_lossy(&output.stdout);
+    Ok(listing
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Count the entries of a tar.gz archive via `tar tzf`.
+fn count_tar_entries(archive_path: &Path) -> Result<u64> {
+    Ok(list_tar_entries(archive_path)?.len() as u64)
+}
+
+/// Whether every entry of a tar listing sits inside a top-level `base_name/`
+/// directory, so extracting into `base_name/` would nest it twice.
+fn entries_have_wrapper(entries: &[String], base_name: &str) -> bool {
+    let mut nested = false;
+    for entry in entries {
+        let entry = entry.trim_start_matches("./");
+        if entry.is_empty() {
+            continue;
+        }
+        match entry.split_once('/') {
+            Some((top, _)) if top == base_name => nested = true,
+            None if entry == base_name => {}
+            _ => return false,
+        }
+    }
+    nested
+}
+
+/// `entries_have_wrapper` for a tarball on disk; unreadable counts as no wrapper.
+fn tar_has_own_wrapper(archive_path: &Path, base_name: &str) -> bool {
+    match list_tar_entries(archive_path) {
+        Ok(entries) => entries_have_wrapper(&entries, base_name),
+        Err(e) => {
+            log::debug!("Cannot list {}: {}", archive_path.display(), e);
+            false
+        }
+    }
+}
+
+/// The name of a tar listing's only entry, if it is a single top-level file.
+fn lone_tar_member(entries: &[String]) -> Option<&str> {
+    let mut members = entries
+        .iter()
+        .map(|e| e.trim_start_matches("./"))
+        .filter(|e| !e.is_empty());
+    let member = members.next()?;
+    let is_file = !member.contains('/');
+    (is_file && members.next().is_none()).then_some(member)
+}
+
+/// `lone_tar_member` for a tarball on disk; unreadable counts as not single-file.
+fn tar_lone_member(archive_path: &Path) -> Option<String> {
+    match list_tar_entries(archive_path) {
+        Ok(entries) => lone_tar_member(&entries).map(String::from),
+        Err(e) => {
+            log::debug!("Cannot list {}: {}", archive_path.display(), e);
+            None
+        }
+    }
+}
+
+/// Percentage of a tar extraction given extracted vs total entry counts.
+fn tar_progress_percent(done: u64, total: u64) -> u64 {
+    if total == 0 {
+        return 100;
+    }
+    (done * 100 / total).min(100)
+}
+
+/// Run a verbose tar extraction with a progress bar driven by the number of
+/// extracted entries, since tar prints no percentage of its own.
+fn run_tar_with_progress(
+    cmd: &mut Command,
+    archive_path: &Path,
+    multi_progress: &MultiProgress,
+    on_progress: &mut dyn FnMut(u64),
+    guard: Option<&OutputGuard>,
+) -> Result<()> {
+    let total = count_tar_entries(archive_path)?;
+    log::debug!("{} has {} entries", archive_path.display(), total);
+    let archive_dir = backend_dir(archive_path)?;
+    let mut child_cmd = rebase_command(cmd, archive_path, &archive_dir)?;
+    log_command(cmd, &archive_dir);
+
+    let bar = percent_bar(multi_progress);
+
+    let mut child = child_cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to start tar")?;
+    if let Some(guard) = guard {
+        guard.set_pid(child.id());
+    }
+    // Collect stderr on the side, so errors don't garble the bar and end up in the error
+    let stderr = child.stderr.take().map(|mut stderr| {
+        std::thread::spawn(move || {
+            let mut tail = OutputTail::default();
+            let mut buf = [0u8; 4096];
+            while let Ok(n @ 1..) = stderr.read(&mut buf) {
+                tail.push(&buf[..n]);
+            }
+            tail.into_lines()
+        })
+    });
+    if let Some(stdout) = child.stdout.take() {
+        let mut done = 0;
+        for line in BufReader::new(stdout).split(b'\n') {
+            if line.is_err() {
+                break;
+            }
+            done += 1;
+            let percent = tar_progress_percent(done, total);
+            if percent != bar.position() {
+                on_progress(percent);
+            }
+            bar.set_position(percent);
+        }
+    }
+    let status = child.wait()?;
+    bar.finish_and_clear();
+    let output = stderr.and_then(|t| t.join().ok()).unwrap_or_default();
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(BackendError {
+            reason: exit_status_reason(status),
+            output,
+        }
+        .into())
+    }
+}
+
+/// Describe a failed child's exit status like `decode_wait_status` does.
+fn exit_status_reason(status: std::process::ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+    decode_wait_status(status.into_raw())
+}
+
+/// Decode Unix wait status into a human-readable string (actual exit code or signal).
+fn decode_wait_status(raw: i32) -> String {
+    #[cfg(unix)]
+    {
+        const WIFEXITED_MASK: i32 = 0x7F;
+        const WEXITSTATUS_SHIFT: i32 = 8;
+        if (raw & WIFEXITED_MASK) == 0 {
+            let code = (raw >> WEXITSTATUS_SHIFT) & 0xFF;
+            let hint = exit_code_hint(code);
+            if hint.is_empty() {
+                format!("Command failed with exit code {}", code)
+            } else {
+                format!("Command failed with exit code {} ({})", code, hint)
+            }
+        } else {
+            let sig = raw & 0x7F;
+            format!(
+                "Command killed by signal {} (raw wait status: {})",
+                sig, raw
+            )
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        format!("Command failed with status {}", raw)
+    }
+}
+
+/// Short hint for common unrar/7zz exit codes to help diagnose failed.log.
+fn exit_code_hint(code: i32) -> &'static str {
+    match code {
+        0 => "success",
+        1 => "warning (non-fatal)",
+        2 => "fatal error",
+        3 => "checksum error / damaged data",
+        6 => "file open error (missing part?)",
+        10 => "no files matching",
+        11 => "wrong password",
+        50 => "unrar 50 (often: missing next volume .part02.rar etc)",
+        255 => "user break",
+        _ => "",
+    }
+}
+
+/// Append a detailed failure entry to failed.log for later inspection.
+fn log_failed_archive(archive: &Archive, err: &anyhow::Error) {
+    let log_path = "failed.log";
+    let Ok(mut file) = OpenOptions::new().append(true).create(true).open(log_path) else {
+        return;
+    };
+    let ts = Local::now().format("%Y-%m-%d %H:%M:%S");
+    // Full error chain (anyhow {:#} shows causes)
+    let err_text = format!("{:#}", err);
+    let entry = format!(
+        "---\n\
+         time: {}\n\
+         path: {}\n\
+         base_name: {}\n\
+         type: {}\n\
+         error: {}\n",
+        ts,
+        display_path(&archive.path),
+        display_name(&archive.base_name),
+        archive.archive_type.as_str(),
+        err_text.replace('\n', " "),
+    );
+    let mut entry = entry;
+    if let Some(backend) = err.chain().find_map(|e| e.downcast_ref::<BackendError>()) {
+        if !backend.output.is_empty() {
+            entry.push_str("output:\n");
+            for line in &backend.output {
+                entry.push_str(&format!("  | {}\n", display_name(line)));
+            }
+        }
+    }
+    let _ = writeln!(file, "{}", entry);
+}
+
+const MARKER_FILE: &str = ".un7z-complete";
+
+/// Completion marker written into `extract_dir` after a successful extraction.
+/// Records the primary volume's size/mtime so a changed archive is re-extracted.
+#[derive(Debug, PartialEq, Eq)]
+struct Marker {
+    size: u64,
+    mtime: u64,
+    files: usize,
+}
+
+impl Marker {
+    fn for_archive(archive: &Archive, extract_dir: &Path) -> Result<Self> {
+        let (size, mtime) = volume_stamp(&archive.path)?;
+        Ok(Marker {
+            size,
+            mtime,
+            files: count_extracted_files(extract_dir),
+        })
+    }
+
+    fn write(&self, extract_dir: &Path) -> Result<()> {
+        let content = format!(
+            "size={}\nmtime={}\nfiles={}\n",
+            self.size, self.mtime, self.files
+        );
+        fs::write(extract_dir.join(MARKER_FILE), content)
+            .context("Failed to write completion marker")
+    }
+
+    fn read(extract_dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(extract_dir.join(MARKER_FILE)).ok()?;
+        let (mut size, mut mtime, mut files) = (None, None, None);
+        for line in content.lines() {
+            match line.split_once('=') {
+                Some(("size", v)) => size = v.trim().parse().ok(),
+                Some(("mtime", v)) => mtime = v.trim().parse().ok(),
+                Some(("files", v)) => files = v.trim().parse().ok(),
+                _ => {}
+            }
+        }
+        Some(Marker {
+            size: size?,
+            mtime: mtime?,
+            files: files?,
+        })
+    }
+
+    /// A marker is valid if the archive is unchanged and no extracted files went missing.
+    fn is_valid_for(&self, archive: &Archive, extract_dir: &Path) -> bool {
+        let Ok((size, mtime)) = volume_stamp(&archive.path) else {
+            return false;
+        };
+        self.size == size && self.mtime == mtime && self.files == count_extracted_files(extract_dir)
+    }
+}
+
+/// Size and mtime (seconds since epoch) of an archive volume.
+fn volume_stamp(path: &Path) -> Result<(u64, u64)> {
+    let meta = fs::metadata(path).context("Cannot stat archive")?;
+    let mtime = meta
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Ok((meta.len(), mtime))
+}
+
+/// Count extracted files, not including our own marker.
+fn count_extracted_files(extract_dir: &Path) -> usize {
+    WalkDir::new(extract_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && e.file_name() != MARKER_FILE)
+        .count()
+}
+
+/// Check if the directory contains actual files (not just empty stubs)
+fn has_valid_files(dir: &Path) -> bool {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .any(|e| e.file_type().is_file() && e.metadata().map(|m| m.len() > 0).unwrap_or(false))
+}
+
+/// `--clean-empty`: remove extraction targets that hold no real files (what an
+/// interrupted run leaves behind). Only the directories un7z would extract the
+/// given archives into are considered. Returns the directories removed.
+fn clean_empty_dirs(archives: &[Archive], layout: &OutputLayout) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for archive in archives {
+        let dir = archive.extract_dir(layout)?;
+        let is_dir = fs::symlink_metadata(&dir).is_ok_and(|m| m.is_dir());
+        if !is_dir || has_valid_files(&dir) || removed.contains(&dir) {
+            continue;
+        }
+        fs::remove_dir_all(&dir).with_context(|| format!("Cannot remove {}", dir.display()))?;
+        log::debug!(
+            "Removed empty {} (for {})",
+            dir.display(),
+            archive.path.display()
+        );
+        removed.push(dir);
+    }
+    Ok(removed)
+}
+
+/// State of an archive's extraction target before we extract into it.
+#[derive(Debug, PartialEq, Eq)]
+enum ExistingOutput {
+    Missing,
+    Complete,
+    Incomplete,
+}
+
+/// With `use_marker`, only a valid completion marker counts as complete;
+/// otherwise any non-empty file does.
+fn existing_output(archive: &Archive, extract_dir: &Path, use_marker: bool) -> ExistingOutput {
+    if !extract_dir.exists() {
+        return ExistingOutput::Missing;
+    }
+    let complete = if use_marker {
+        Marker::read(extract_dir).is_some_and(|m| m.is_valid_for(archive, extract_dir))
+    } else {
+        has_valid_files(extract_dir)
+    };
+    if complete {
+        ExistingOutput::Complete
+    } else {
+        ExistingOutput::Incomplete
+    }
+}
+
+/// What `extract_archive` did with an archive.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    /// Extracted into the given (resolved) directory.
+    Extracted(PathBuf),
+    /// Integrity test passed.
+    Tested,
+    /// Headers and CRC tables look sound (`--quick-test`); data was not read.
+    QuickChecked,
+    /// The first few files were extracted into the given directory (`--preview`).
+    Previewed(PathBuf),
+    /// Already extracted, nothing done.
+    Skipped,
+    /// The output looks incomplete and was left alone (`--report-incomplete-only`).
+    Incomplete(PathBuf),
+    /// Not started: the `--deadline` had passed, or the archive would run past it.
+    OutOfTime,
+}
+
+/// The `archive_finished` event for an archive's result.
+fn finished_event<'a>(archive: &'a Archive, result: &'a Result<Outcome>) -> Event<'a> {
+    let (outcome, dir) = match result {
+        Ok(Outcome::Extracted(dir)) => ("extracted", Some(dir.as_path())),
+        Ok(Outcome::Tested) => ("tested", None),
+        Ok(Outcome::QuickChecked) => ("quick_checked", None),
+        Ok(Outcome::Previewed(dir)) => ("previewed", Some(dir.as_path())),
+        Ok(Outcome::Skipped) => ("skipped", None),
+        Ok(Outcome::Incomplete(dir)) => ("incomplete", Some(dir.as_path())),
+        Ok(Outcome::OutOfTime) => ("out_of_time", None),
+        Err(_) => ("failed", None),
+    };
+    Event::ArchiveFinished {
+        archive: &archive.path,
+        outcome,
+        dir,
+        error: result.as_ref().err().map(|e| format!("{:#}", e)),
+    }
+}
+
+/// Append a source→destination line to the map log.
+fn append_map_log(log_path: &Path, archive: &Archive, extract_dir: &Path) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(log_path)
+        .with_context(|| format!("Cannot open map log {}", log_path.display()))?;
+    writeln!(
+        file,
+        "{}\t{}",
+        archive.path.display(),
+        extract_dir.display()
+    )?;
+    Ok(())
+}
+
+/// How an archive is identified in the success log: its canonical path.
+fn success_log_key(archive: &Archive) -> PathBuf {
+    fs::canonicalize(&archive.path).unwrap_or_else(|_| archive.path.clone())
+}
+
+/// Append a successfully extracted archive to the success log.
+fn append_success_log(log_path: &Path, archive: &Archive) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(log_path)
+        .with_context(|| format!("Cannot open success log {}", log_path.display()))?;
+    // One write per line, so parallel runs don't interleave
+    let line = format!("{}\n", success_log_key(archive).display());
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Archives recorded in the success log; a missing log records none.
+fn read_success_log(log_path: &Path) -> Result<std::collections::HashSet<PathBuf>> {
+    match fs::read_to_string(log_path) {
+        Ok(content) => Ok(content
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(PathBuf::from)
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+        Err(e) => Err(e).with_context(|| format!("Cannot read success log {}", log_path.display())),
+    }
+}
+
+/// Split the selection into archives not yet in the success log and a count of
+/// those that are, whatever became of their output since.
+fn partition_logged(
+    archives: &[Archive],
+    indices: Vec<usize>,
+    logged: &std::collections::HashSet<PathBuf>,
+) -> (Vec<usize>, usize) {
+    let (done, todo): (Vec<usize>, Vec<usize>) = indices
+        .into_iter()
+        .partition(|&i| logged.contains(&success_log_key(&archives[i])));
+    (todo, done.len())
+}
+
+/// Returns why an archive looks like it is still downloading, if it does.
+///
+/// A set is in progress when any file next to it is one of its volumes with an
+/// in-progress suffix, or when its volumes (all but the last) differ in size.
+fn download_in_progress(archive: &Archive, suffixes: &[String]) -> Option<String> {
+    let volumes = archive.volumes();
+
+    let primary_name = archive.path.file_name()?.to_str()?;
+    let pattern = archive.volume_pattern();
+    if let Ok(entries) = fs::read_dir(archive.dir()) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            for suffix in suffixes {
+                if let Some(stem) = name.strip_suffix(suffix.as_str()) {
+                    if stem == primary_name || pattern.as_ref().is_some_and(|p| p.is_match(stem)) {
+                        return Some(format!("{} is still downloading", name));
+                    }
+                }
+            }
+        }
+    }
+
+    if volumes.len() > 1 {
+        let sizes: Vec<u64> = volumes
+            .iter()
+            .map(|v| fs::metadata(v).map(|m| m.len()).unwrap_or(0))
+            .collect();
+        let first = sizes[0];
+        let (last, middle) = sizes[1..].split_last()?;
+        if middle.iter().any(|&size| size != first) || *last > first {
+            return Some("volume sizes are inconsistent".to_string());
+        }
+    }
+
+    None
+}
+
+/// What to do with an extraction target that already has files in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum ExistsAction {
+    /// Leave it and skip the archive
+    Skip,
+    /// Delete it and extract afresh
+    Overwrite,
+    /// Extract into `name (2)` instead
+    Rename,
+    /// Extract into it, keeping files the archive doesn't have
+    Merge,
+}
+
+/// Map a prompt answer to an action; no answer or anything unrecognized skips.
+fn parse_exists_action(answer: Option<&str>) -> ExistsAction {
+    match answer.map(|a| a.trim().to_ascii_lowercase()).as_deref() {
+        Some("o") | Some("overwrite") => ExistsAction::Overwrite,
+        Some("r") | Some("rename") => ExistsAction::Rename,
+        _ => ExistsAction::Skip,
+    }
+}
+
+/// Held while a prompt is on the terminal, so archives extracted in parallel
+/// (`--jobs-per-disk`) ask one at a time and each answer reaches its own prompt.
+static PROMPT_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+fn prompt_lock() -> std::sync::MutexGuard<'static, ()> {
+    PROMPT_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Ask on the terminal what to do with an existing target; non-TTY always skips.
+fn prompt_exists_action(extract_dir: &Path) -> ExistsAction {
+    if !std::io::stdin().is_terminal() {
+        return ExistsAction::Skip;
+    }
+    let _prompt = prompt_lock();
+    print!(
+        "  {} {} already has files: [s]kip, [o]verwrite, [r]ename? ",
+        style("?").cyan(),
+        style(display_path(extract_dir)).bold()
+    );
+    let _ = std::io::stdout().flush();
+    let mut input = String::new();
+    match std::io::stdin().read_line(&mut input) {
+        Ok(n) if n > 0 => parse_exists_action(Some(&input)),
+        _ => ExistsAction::Skip,
+    }
+}
+
+/// Ask for a password on the terminal without echoing it. None when stdin is
+/// not a terminal or nothing was entered.
+fn prompt_password(name: &str) -> Option<String> {
+    if !std::io::stdin().is_terminal() {
+        return None;
+    }
+    let _prompt = prompt_lock();
+    print!(
+        "  {} {} is encrypted, password: ",
+        style("?").cyan(),
+        style(name).bold()
+    );
+    let _ = std::io::stdout().flush();
+    let stty = |arg| Command::new("stty").arg(arg).status();
+    let _ = stty("-echo");
+    let mut input = String::new();
+    let read = std::io::stdin().read_line(&mut input);
+    let _ = stty("echo");
+    println!();
+    let password = input.trim_end_matches(['\r', '\n']);
+    match read {
+        Ok(_) if !password.is_empty() => Some(password.to_string()),
+        _ => None,
+    }
+}
+
+/// Whether to ask before deleting output that only looks incomplete (the
+/// heuristic can be wrong): only if it has contents, we can ask, and `--yes`
+/// wasn't given. Otherwise it is deleted and re-extracted as before.
+fn needs_reextract_confirmation(extract_dir: &Path, assume_yes: bool, interactive: bool) -> bool {
+    !assume_yes && interactive && fs::read_dir(extract_dir).is_ok_and(|mut e| e.next().is_some())
+}
+
+/// Map a yes/no answer; anything but yes is no.
+fn parse_yes(input: Option<&str>) -> bool {
+    let answer = input.unwrap_or("").trim().to_lowercase();
+    answer == "y" || answer == "yes"
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no.
+fn confirm(question: &str) -> bool {
+    let _prompt = prompt_lock();
+    print!("  {} {} [y/N] ", style("?").cyan(), question);
+    let _ = std::io::stdout().flush();
+    let mut input = String::new();
+    match std::io::stdin().read_line(&mut input) {
+        Ok(n) if n > 0 => parse_yes(Some(&input)),
+        _ => false,
+    }
+}
+
+/// First free sibling name of the form `dir (2)`, `dir (3)`, ...
+fn unique_dir(dir: &Path) -> PathBuf {
+    unique_name(dir, false)
+}
+
+/// `unique_dir`, keeping a file's extension last (`movie (2).mkv`) when `is_file`.
+fn unique_name(path: &Path, is_file: bool) -> PathBuf {
+    (2..)
+        .map(|n| tagged_name(path, &n.to_string(), is_file))
+        .find(|candidate| !candidate.exists())
+        .expect("unbounded range always yields a free name")
+}
+
+/// `path` with ` (tag)` added to its name, before the extension when `is_file`.
+fn tagged_name(path: &Path, tag: &str, is_file: bool) -> PathBuf {
+    let extension = path.extension().filter(|_| is_file);
+    let stem = match extension {
+        Some(_) => path.file_stem(),
+        None => path.file_name(),
+    };
+    let mut name = stem.unwrap_or_default().to_os_string();
+    name.push(format!(" ({})", tag));
+    if let Some(extension) = extension {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Whether `--overwrite-newer` replaces a file modified at `existing` (None
+/// when there is none) with the archive's copy modified at `incoming`.
+fn replaces_existing(incoming: SystemTime, existing: Option<SystemTime>) -> bool {
+    existing.is_none_or(|existing| existing < incoming)
+}
+
+/// Move files from `staging` into `output` where `replaces_existing` says so,
+/// keeping the rest. Returns how many files were placed and how many kept.
+fn merge_newer(staging: &Path, output: &Path) -> Result<(usize, usize)> {
+    let (mut placed, mut kept) = (0, 0);
+    for entry in WalkDir::new(staging).min_depth(1) {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(staging)?;
+        let target = output.join(relative);
+        let existing = fs::symlink_metadata(&target).ok();
+        if existing.as_ref().is_some_and(|meta| meta.is_dir()) {
+            kept += 1;
+            continue;
+        }
+        let incoming = entry.metadata()?.modified()?;
+        let existing = existing.map(|meta| meta.modified()).transpose()?;
+        if !replaces_existing(incoming, existing) {
+            kept += 1;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(entry.path(), &target)
+            .with_context(|| format!("Cannot move {} into place", target.display()))?;
+        placed += 1;
+    }
+    Ok((placed, kept))
+}
+
+/// Short FNV-1a hash of a file, or of a tree's relative paths and file contents.
+fn content_hash(path: &Path) -> Result<String> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |bytes: &[u8]| {
+        for &b in bytes {
+            hash = (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    for entry in WalkDir::new(path).sort_by_file_name() {
+        let entry = entry?;
+        feed(
+            entry
+                .path()
+                .strip_prefix(path)?
+                .as_os_str()
+                .as_encoded_bytes(),
+        );
+        feed(&[0]);
+        if entry.file_type().is_file() {
+            let mut file = fs::File::open(entry.path())?;
+            let mut buf = [0u8; 64 * 1024];
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                feed(&buf[..n]);
+            }
+        }
+    }
+    Ok(format!("{:016x}", hash)[..8].to_string())
+}
+
+/// Where `incoming` goes when it is to be placed at `target`: `target` itself
+/// when free, otherwise as `collision` says. None keeps what is there, which
+/// for `Hash` also means the same content is already in place. `Overwrite`
+/// removes the existing entry.
+fn resolve_collision(
+    target: &Path,
+    incoming: &Path,
+    collision: Collision,
+) -> Result<Option<PathBuf>> {
+    if fs::symlink_metadata(target).is_err() {
+        return Ok(Some(target.to_path_buf()));
+    }
+    let is_file = incoming.is_file();
+    Ok(match collision {
+        Collision::Skip => None,
+        Collision::Overwrite => {
+            if target.is_dir() && !target.is_symlink() {
+                retry_fs(|| fs::remove_dir_all(target))?;
+            } else {
+                retry_fs(|| fs::remove_file(target))?;
+            }
+            Some(target.to_path_buf())
+        }
+        Collision::Counter => Some(unique_name(target, is_file)),
+        Collision::Hash => {
+            let hashed = tagged_name(target, &content_hash(incoming)?, is_file);
+            (!hashed.exists()).then_some(hashed)
+        }
+    })
+}
+
+/// Split selected indices into those still to extract and the count already done,
+/// using the same completeness check as `extract_archive`.
+fn partition_missing(
+    archives: &[Archive],
+    indices: Vec<usize>,
+    opts: &ExtractOptions,
+) -> (Vec<usize>, usize) {
+    let (done, missing): (Vec<usize>, Vec<usize>) = indices.into_iter().partition(|&i| {
+        let layout = &opts.settings_for(&archives[i]).layout;
+        // Output extracted here can't be told apart from what was already there
+        !layout.here
+            && archives[i]
+                .extract_dir(layout)
+                .map(|dir| {
+                    existing_output(&archives[i], &dir, opts.marker) == ExistingOutput::Complete
+                })
+                .unwrap_or(false)
+    });
+    (missing, done.len())
+}
+
+/// Running totals of the extraction loop.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct BatchStats {
+    success: usize,
+    failed: usize,
+    skipped: usize,
+    /// Passed `--quick-test` only; counted apart from full successes.
+    quick_checked: usize,
+    /// Archives left untouched because the batch stopped early.
+    not_attempted: usize,
+    /// Archives not started because of `--deadline`.
+    out_of_time: usize,
+    /// Output directories that look incomplete (`--report-incomplete-only`).
+    incomplete: Vec<PathBuf>,
+}
+
+/// Extraction directories that more than one selected archive maps to (the
+/// later one would overwrite the earlier), with those archives' indices.
+fn colliding_targets(
+    archives: &[Archive],
+    indices: &[usize],
+    extract_dir: impl Fn(&Archive) -> Result<PathBuf>,
+) -> Result<Vec<(PathBuf, Vec<usize>)>> {
+    let mut targets: std::collections::BTreeMap<PathBuf, Vec<usize>> = Default::default();
+    for &i in indices {
+        targets
+            .entry(extract_dir(&archives[i])?)
+            .or_default()
+            .push(i);
+    }
+    Ok(targets
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .collect())
+}
+
+impl BatchStats {
+    fn record(&mut self, result: &Result<Outcome>) {
+        match result {
+            Ok(Outcome::Skipped) => self.skipped += 1,
+            Ok(Outcome::QuickChecked) => self.quick_checked += 1,
+            Ok(Outcome::Incomplete(dir)) => self.incomplete.push(dir.clone()),
+            Ok(Outcome::OutOfTime) => self.out_of_time += 1,
+            Ok(_) => self.success += 1,
+            Err(_) => self.failed += 1,
+        }
+    }
+}
+
+/// The end-of-run summary for `selected` archives (`skipped` already counts the
+/// incomplete ones), one printed line each.
+fn summary_lines(selected: usize, stats: &mut BatchStats) -> Vec<String> {
+    let mut lines = vec![
+        format!("\n{}", style("═".repeat(50)).dim()),
+        format!(
+            "{} {} | {} {} | {} {} | {} {}",
+            style("Total:").bold(),
+            style(selected).yellow(),
+            style("Success:").green(),
+            style(stats.success).green(),
+            style("Failed:").red(),
+            style(stats.failed).red(),
+            style("Skipped:").yellow(),
+            style(stats.skipped).yellow()
+        ),
+    ];
+
+    if stats.quick_checked > 0 {
+        lines.push(format!(
+            "\n{} {} passed a quick check only (headers/CRC tables, not a full test)",
+            style("ℹ").cyan(),
+            style(stats.quick_checked).cyan()
+        ));
+    }
+
+    if !stats.incomplete.is_empty() {
+        lines.push(format!(
+            "\n{} {} output director(ies) look incomplete, left as they are:",
+            style("⚠").yellow(),
+            style(stats.incomplete.len()).yellow()
+        ));
+        stats.incomplete.sort();
+        for dir in &stats.incomplete {
+            lines.push(format!("  {} {}", style("•").dim(), display_path(dir)));
+        }
+    }
+
+    if stats.not_attempted > 0 {
+        lines.push(format!(
+            "\n{} Stopped after the first failure, {} not attempted",
+            style("⚠").yellow(),
+            style(stats.not_attempted).yellow()
+        ));
+    }
+
+    if stats.out_of_time > 0 {
+        lines.push(format!(
+            "\n{} Deadline reached, {} not started",
+            style("⏱").yellow(),
+            style(stats.out_of_time).yellow()
+        ));
+    }
+
+    if stats.failed > 0 {
+        lines.push(format!(
+            "\n{} See {} for details",
+            style("⚠").yellow(),
+            style("failed.log").yellow()
+        ));
+    }
+    lines
+}
+
+/// The `--compact` status line: `[3/50] extracting name (43%)`.
+fn compact_status(
+    position: usize,
+    total: usize,
+    verb: &str,
+    name: &str,
+    percent: Option<u64>,
+) -> String {
+    let mut line = format!("[{}/{}] {} {}", position, total, verb, display_name(name));
+    if let Some(percent) = percent {
+        line.push_str(&format!(" ({}%)", percent));
+    }
+    line
+}
+
+/// Title and body of the `--notify` desktop notification for a finished batch.
+fn notification_text(selected: usize, stats: &BatchStats) -> (String, String) {
+    let title = if stats.failed > 0 {
+        format!("un7z: {} failed", stats.failed)
+    } else {
+        "un7z: done".to_string()
+    };
+    let mut body = format!(
+        "{} archive(s): {} extracted, {} failed, {} skipped",
+        selected, stats.success, stats.failed, stats.skipped
+    );
+    if !stats.incomplete.is_empty() {
+        body.push_str(&format!(", {} incomplete", stats.incomplete.len()));
+    }
+    if stats.not_attempted > 0 {
+        body.push_str(&format!(", {} not attempted", stats.not_attempted));
+    }
+    if stats.out_of_time > 0 {
+        body.push_str(&format!(", {} not started (deadline)", stats.out_of_time));
+    }
+    (title, body)
+}
+
+/// Show a desktop notification with `notify-send`, or `osascript` on macOS.
+/// Without either (or a desktop session) nothing happens.
+fn send_notification(title: &str, body: &str) {
+    let mut cmd = if cfg!(target_os = "macos") {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let script = format!(
+            "display notification {} with title {}",
+            quote(body),
+            quote(title)
+        );
+        let mut cmd = Command::new("osascript");
+        cmd.arg("-e").arg(script);
+        cmd
+    } else {
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["--app-name", "un7z", "--", title, body]);
+        cmd
+    };
+    let result = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match result {
+        Ok(status) if !status.success() => log::debug!("Notification exited with {}", status),
+        Ok(_) => {}
+        Err(e) => log::debug!("Cannot send notification: {}", e),
+    }
+}
+
+/// `--show-report`: rebuild a run's stats from its `--events` file. Returns the
+/// number of archives the run finished and their tally.
+fn stats_from_events(reader: impl BufRead) -> Result<(usize, BatchStats)> {
+    let mut stats = BatchStats::default();
+    let mut finished = 0;
+    for record in events::read_finished(reader)? {
+        let result = match (record.outcome.as_str(), record.dir) {
+            ("extracted", Some(dir)) => Ok(Outcome::Extracted(dir)),
+            ("previewed", Some(dir)) => Ok(Outcome::Previewed(dir)),
+            ("incomplete", Some(dir)) => Ok(Outcome::Incomplete(dir)),
+            ("tested", _) => Ok(Outcome::Tested),
+            ("quick_checked", _) => Ok(Outcome::QuickChecked),
+            ("skipped", _) => Ok(Outcome::Skipped),
+            ("out_of_time", _) => Ok(Outcome::OutOfTime),
+            ("failed", _) => Err(anyhow::anyhow!(record.error.unwrap_or_default())),
+            (outcome, _) => anyhow::bail!(
+                "Unknown outcome '{}' for {} in report",
+                outcome,
+                record.archive.display()
+            ),
+        };
+        stats.record(&result);
+        finished += 1;
+    }
+    stats.skipped += stats.incomplete.len();
+    Ok((finished, stats))
+}
+
+/// Run `process(position, index)` over the selected archives in order and tally
+/// the outcomes. With `fail_fast`, stop at the first failure.
+fn run_batch<F>(indices: &[usize], fail_fast: bool, mut process: F) -> BatchStats
+where
+    F: FnMut(usize, usize) -> Result<Outcome>,
+{
+    let mut stats = BatchStats::default();
+    for (n, &i) in indices.iter().enumerate() {
+        let result = process(n, i);
+        stats.record(&result);
+        if fail_fast && result.is_err() {
+            stats.not_attempted = indices.len() - n - 1;
+            break;
+        }
+    }
+    stats
+}
+
+/// Device id (`st_dev`) of `path`, or of its nearest existing ancestor when
+/// the extraction directory doesn't exist yet.
+fn device_of(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    path.ancestors()
+        .find_map(|p| fs::metadata(p).ok())
+        .map(|m| m.dev())
+}
+
+/// Split the selected positions into one queue per device, keeping the
+/// selection order inside each queue and ordering queues by first appearance.
+fn group_by_device<F>(count: usize, device: F) -> Vec<Vec<usize>>
+where
+    F: Fn(usize) -> Option<u64>,
+{
+    let mut groups: Vec<(Option<u64>, Vec<usize>)> = Vec::new();
+    for n in 0..count {
+        let dev = device(n);
+        match groups.iter_mut().find(|(d, _)| *d == dev) {
+            Some((_, members)) => members.push(n),
+            None => groups.push((dev, vec![n])),
+        }
+    }
+    groups.into_iter().map(|(_, members)| members).collect()
+}
+
+/// `run_batch` for `--jobs-per-disk`: every device queue gets `jobs` workers of
+/// its own, so a slow disk is never hit by more than `jobs` archives at once
+/// while other disks keep going. With `fail_fast`, no new archive starts after
+/// the first failure.
+fn run_batch_by_device<F>(
+    indices: &[usize],
+    groups: &[Vec<usize>],
+    jobs: usize,
+    fail_fast: bool,
+    process: F,
+) -> BatchStats
+where
+    F: Fn(usize, usize) -> Result<Outcome> + Sync,
+{
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    let stats = Mutex::new(BatchStats::default());
+    let stop = AtomicBool::new(false);
+    let attempted = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for group in groups {
+            let next = std::sync::Arc::new(AtomicUsize::new(0));
+            for _ in 0..jobs.min(group.len()) {
+                let (next, stats, stop, attempted) = (next.clone(), &stats, &stop, &attempted);
+                let process = &process;
+                scope.spawn(move || {
+                    while !stop.load(Ordering::SeqCst) {
+                        let Some(&n) = group.get(next.fetch_add(1, Ordering::SeqCst)) else {
+                            break;
+                        };
+                        attempted.fetch_add(1, Ordering::SeqCst);
+                        let result = process(n, indices[n]);
+                        if fail_fast && result.is_err() {
+                            stop.store(true, Ordering::SeqCst);
+                        }
+                        stats.lock().unwrap().record(&result);
+                    }
+                });
+            }
+        }
+    });
+
+    let mut stats = stats.into_inner().unwrap();
+    if fail_fast {
+        stats.not_attempted = indices.len() - attempted.into_inner();
+    }
+    stats
+}
+
+/// Whether `--deadline` lets an archive start at `now`: not once it has
+/// passed, nor when the archive is `expected` to take longer than what's left.
+fn deadline_allows(deadline: Instant, now: Instant, expected: Option<Duration>) -> bool {
+    match deadline.checked_duration_since(now) {
+        Some(left) if !left.is_zero() => expected.is_none_or(|expected| expected <= left),
+        _ => false,
+    }
+}
+
+/// How long `bytes` should take at the throughput observed so far.
+fn expected_duration(bytes: u64, bytes_done: u64, work_time: Duration) -> Option<Duration> {
+    (bytes_done > 0 && !work_time.is_zero())
+        .then(|| work_time.mul_f64(bytes as f64 / bytes_done as f64))
+}
+
+/// Estimated time left for the batch, from the throughput observed so far.
+fn batch_eta(bytes_done: u64, bytes_total: u64, elapsed: Duration) -> Option<Duration> {
+    if bytes_done == 0 || elapsed.is_zero() {
+        return None;
+    }
+    let remaining = bytes_total.saturating_sub(bytes_done);
+    Some(elapsed.mul_f64(remaining as f64 / bytes_done as f64))
+}
+
+/// Attempts and first delay for `retry_fs`; the delay doubles after each try.
+const FS_RETRY_ATTEMPTS: u32 = 5;
+const FS_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Run a filesystem operation, retrying with backoff while it fails because
+/// something (antivirus, an indexer, NFS) briefly holds the file. Other
+/// errors are returned right away.
+fn retry_fs<T, F>(op: F) -> std::io::Result<T>
+where
+    F: FnMut() -> std::io::Result<T>,
+{
+    retry_fs_with(FS_RETRY_ATTEMPTS, FS_RETRY_DELAY, op)
+}
+
+fn retry_fs_with<T, F>(attempts: u32, first_delay: Duration, mut op: F) -> std::io::Result<T>
+where
+    F: FnMut() -> std::io::Result<T>,
+{
+    let mut delay = first_delay;
+    for attempt in 1.. {
+        match op() {
+            Err(e) if attempt < attempts && is_transient_lock(&e) => {
+                log::debug!("{} (attempt {}), retrying in {:?}", e, attempt, delay);
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    unreachable!()
+}
+
+/// Whether an error looks like a file being held open by another process.
+/// Windows reports a sharing violation as permission denied; elsewhere that
+/// (a read-only directory, say) doesn't go away by waiting.
+fn is_transient_lock(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::ResourceBusy => true,
+        ErrorKind::PermissionDenied => cfg!(windows),
+        _ => false,
+    }
+}
+
+/// Move a directory, falling back to copy + remove when it has to cross filesystems.
+fn move_dir(src: &Path, dst: &Path) -> Result<()> {
+    move_dir_with(src, dst, |from, to| retry_fs(|| fs::rename(from, to)))
+}
+
+fn move_dir_with<F>(src: &Path, dst: &Path, rename: F) -> Result<()>
+where
+    F: Fn(&Path, &Path) -> std::io::Result<()>,
+{
+    match rename(src, dst) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_dir_all(src, dst)?;
+            retry_fs(|| fs::remove_dir_all(src))?;
+            Ok(())
+        }
+        Err(e) => Err(e).with_context(|| format!("Cannot move to {}", dst.display())),
+    }
+}
+
+/// Recursively copy a directory tree, recreating symlinks rather than following them.
+fn copy_dir_all(src: &Path, dst: &Path) -> Result<()> {
+    for entry in WalkDir::new(src) {
+        let entry = entry?;
+        let target = dst.join(entry.path().strip_prefix(src)?);
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("Cannot copy to {}", target.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Move finished output into `move_to`, resolving a collision with `collision`,
+/// or by asking when that isn't set and `interactive`. Returns where the
+/// output ended up.
+fn relocate_output(
+    extract_dir: &Path,
+    move_to: &Path,
+    collision: Option<Collision>,
+    interactive: bool,
+) -> Result<PathBuf> {
+    let name = extract_dir
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Extract directory has no name"))?;
+    fs::create_dir_all(move_to).with_context(|| format!("Cannot create {}", move_to.display()))?;
+    let target = move_to.join(name);
+
+    let collision = match collision {
+        Some(collision) => collision,
+        None if interactive && target.exists() => match prompt_exists_action(&target) {
+            // Never offered by the prompt
+            ExistsAction::Skip | ExistsAction::Merge => Collision::Skip,
+            ExistsAction::Overwrite => Collision::Overwrite,
+            ExistsAction::Rename => Collision::Counter,
+        },
+        None => Collision::Skip,
+    };
+    let Some(target) = resolve_collision(&target, extract_dir, collision)? else {
+        say!(
+            "  {} {} already exists, output left in place",
+            style("┖─").dim(),
+            display_path(&target)
+        );
+        return Ok(extract_dir.to_path_buf());
+    };
+
+    move_dir(extract_dir, &target)?;
+    say!("  {} Moved to {}", style("┖─").dim(), display_path(&target));
+    Ok(target)
+}
+
+/// Per-run settings for `extract_archive`, resolved from the command line.
+struct ExtractOptions {
+    test: bool,
+    quick_test: bool,
+    checksum_archive: bool,
+    json: bool,
+    max_output_size: Option<u64>,
+    max_files: Option<usize>,
+    verify_extraction: bool,
+    sandbox: Option<Sandbox>,
+    no_pty_progress: bool,
+    prefetch: bool,
+    repair: bool,
+    preview: Option<usize>,
+    password: Option<String>,
+    on_encrypted: OnEncrypted,
+    session_password: SessionPassword,
+    keyring_use: bool,
+    keyring_store: bool,
+    /// Per-archive settings from `--spec`, over the ones above.
+    spec: spec::Spec,
+    force: bool,
+    no_cleanup: bool,
+    report_incomplete_only: bool,
+    touch: bool,
+    no_special: bool,
+    normalize_unicode: Option<normalize::UnicodeForm>,
+    no_subdir: bool,
+    marker: bool,
+    layout: OutputLayout,
+    /// Archives downloaded from URLs and, without `--output`, the root they
+    /// extract under instead of their temporary download directory.
+    downloaded: Vec<PathBuf>,
+    download_root: Option<PathBuf>,
+    in_progress_suffixes: Vec<String>,
+    interactive_overwrite: bool,
+    overwrite_newer: bool,
+    dest_exists_action: Option<ExistsAction>,
+    assume_yes: bool,
+    move_to: Option<PathBuf>,
+    collision: Option<Collision>,
+    repack: Option<RepackFormat>,
+    repack_remove: bool,
+    /// Extract into exactly this directory instead of `extract_dir`.
+    extract_into: Option<PathBuf>,
+    success_log: Option<PathBuf>,
+    hooks: Hooks,
+    events: Option<EventLog>,
+    progress_fd: Option<ProgressFd>,
+}
+
+/// Commands run after each archive, see `run_hook`.
+#[derive(Debug, Default)]
+struct Hooks {
+    on_success: Option<String>,
+    on_failure: Option<String>,
+    timeout: Duration,
+}
+
+/// The settings one archive is extracted with.
+#[derive(Debug)]
+struct ArchiveSettings<'a> {
+    test: bool,
+    force: bool,
+    touch: bool,
+    password: &'a Option<String>,
+    layout: Cow<'a, OutputLayout>,
+}
+
+impl ExtractOptions {
+    /// The settings for `archive`: its `--spec` entry's where it has them,
+    /// the command line's otherwise.
+    fn settings_for(&self, archive: &Archive) -> ArchiveSettings<'_> {
+        let entry = self.spec.entry(&archive.path);
+        let password = match entry {
+            Some(entry) if entry.password.is_some() => &entry.password,
+            _ => &self.password,
+        };
+        let download_root = self
+            .download_root
+            .clone()
+            .filter(|_| self.downloaded.contains(&archive.path));
+        let layout = match entry
+            .and_then(|entry| entry.output.clone())
+            .or(download_root)
+        {
+            Some(root) => Cow::Owned(OutputLayout {
+                root: Some(root),
+                ..self.layout.clone()
+            }),
+            None => Cow::Borrowed(&self.layout),
+        };
+        ArchiveSettings {
+            test: entry.and_then(|entry| entry.test).unwrap_or(self.test),
+            force: entry.and_then(|entry| entry.force).unwrap_or(self.force),
+            touch: entry.and_then(|entry| entry.touch).unwrap_or(self.touch),
+            password,
+            layout,
+        }
+    }
+
+    /// Without `--output`, extract the `downloaded` archives under `root`
+    /// rather than in their temporary directories. Local files listed beside
+    /// them still extract next to themselves.
+    fn extract_downloads_into(&mut self, downloaded: Vec<PathBuf>, root: PathBuf) {
+        if self.layout.root.is_none() {
+            self.downloaded = downloaded;
+            self.download_root = Some(root);
+        }
+    }
+
+    fn from_args(args: &Args) -> Result<Self> {
+        Ok(ExtractOptions {
+            test: args.test,
+            quick_test: args.quick_test,
+            checksum_archive: args.checksum_archive,
+            json: args.json,
+            max_output_size: args.max_output_size,
+            max_files: args.max_files,
+            verify_extraction: args.verify_extraction,
+            sandbox: Sandbox::resolve(args.sandbox),
+            // Parallel jobs would interleave the backends' raw terminal output
+            no_pty_progress: args.no_pty_progress || args.compact || args.jobs_per_disk.is_some(),
+            prefetch: args.prefetch,
+            repair: args.repair,
+            preview: args.preview,
+            password: args.password.clone(),
+            on_encrypted: args.on_encrypted,
+            session_password: SessionPassword::default(),
+            keyring_use: args.keyring_use,
+            keyring_store: args.keyring_store,
+            spec: spec::Spec::default(),
+            force: args.extract_into.is_some(),
+            no_cleanup: args.no_cleanup,
+            report_incomplete_only: args.report_incomplete_only,
+            touch: args.touch,
+            no_special: args.no_special,
+            normalize_unicode: args.normalize_unicode,
+            no_subdir: args.no_subdir,
+            marker: args.marker,
+            layout: OutputLayout::from_args(args)?,
+            downloaded: Vec::new(),
+            download_root: None,
+            in_progress_suffixes: args.in_progress_suffixes.clone(),
+            interactive_overwrite: args.interactive_overwrite,
+            overwrite_newer: args.overwrite_newer,
+            dest_exists_action: args.dest_exists_action,
+            assume_yes: args.yes,
+            move_to: args.move_to.clone(),
+            collision: args.collision,
+            repack: args.repack,
+            repack_remove: args.repack_remove,
+            extract_into: args.extract_into.clone(),
+            success_log: args.success_log.clone(),
+            hooks: Hooks {
+                on_success: args.on_success.clone(),
+                on_failure: args.on_failure.clone(),
+                timeout: args.hook_timeout,
+            },
+            events: None,
+            progress_fd: args.progress_fd.map(ProgressFd::open).transpose()?,
+        })
+    }
+}
+
+/// `--checksum-archive`: whether the archive's volumes match their checksum
+/// sidecars (no sidecar counts as a match, with a warning).
+fn checksums_ok(archive: &Archive, opts: &ExtractOptions) -> Result<bool> {
+    let base_name = &display_name(&archive.base_name);
+    let report = checksum::verify_archive(archive)?;
+    log::debug!("{}: {:?}", archive.path.display(), report);
+    if opts.json {
+        println!("{}", report.to_json(&archive.path));
+    }
+    if !report.mismatched.is_empty() {
+        if !opts.json {
+            for line in report.format_mismatches(base_name) {
+                say!("{}", line);
+            }
+        }
+        return Ok(false);
+    }
+    if report.verified == 0 {
+        say!(
+            "{} {} has no checksum sidecar, not verified",
+            style("⚠").yellow(),
+            style(base_name).yellow()
+        );
+    }
+    Ok(true)
+}
+
+fn extract_archive(
+    archive: &Archive,
+    multi_progress: &MultiProgress,
+    opts: &ExtractOptions,
+    report_progress: &mut dyn FnMut(u64),
+) -> Result<Outcome> {
+    let base_name = &display_name(&archive.base_name);
+    let settings = opts.settings_for(archive);
+    let (test, password, force, marker) = (
+        settings.test,
+        settings.password,
+        settings.force,
+        opts.marker,
+    );
+    let mut extract_dir = match &opts.extract_into {
+        Some(dir) => dir.clone(),
+        None => archive.extract_dir(&settings.layout)?,
+    };
+    // --extract-here shares the directory with other files, so existing ones
+    // are handled file by file by the backend instead of as a whole
+    let here = settings.layout.here && opts.extract_into.is_none();
+
+    if let Some(reason) = download_in_progress(archive, &opts.in_progress_suffixes) {
+        log::debug!("Skipping {}: {}", archive.path.display(), reason);
+        say!("{} {}", style("⊘").yellow(), style(base_name).yellow(),);
+        say!("  {} In progress ({}), skipped", style("┖─").dim(), reason);
+        return Ok(Outcome::Skipped);
+    }
+
+    // Checked once the archive is known to run, so skipped ones aren't rehashed
+    let checksums_match = || {
+        opts.checksum_archive
+            .then(|| checksums_ok(archive, opts))
+            .transpose()
+    };
+    if (opts.quick_test || opts.preview.is_some()) && checksums_match()? == Some(false) {
+        return Ok(Outcome::Skipped);
+    }
+
+    if opts.quick_test {
+        return quick_test_archive(archive, password);
+    }
+
+    if let Some(count) = opts.preview {
+        let password = effective_password(opts.on_encrypted, password);
+        return preview_archive(archive, count, &password, &extract_dir);
+    }
+
+    // --no-subdir: a single-file tarball puts its file next to where the folder would be
+    let is_tar = archive.archive_type == ArchiveType::TarGz;
+    let mut lone_file = match extract_dir.parent() {
+        Some(parent)
+            if opts.no_subdir && is_tar && !test && opts.extract_into.is_none() && !here =>
+        {
+            tar_lone_member(&archive.path).map(|member| parent.join(member))
+        }
+        _ => None,
+    };
+    // A taken name is resolved after extraction, from a staging folder beside it
+    let mut staged_lone = None;
+    if let Some(file) = lone_file.clone() {
+        if !force && fs::symlink_metadata(&file).is_ok() {
+            if opts.collision.unwrap_or_default() == Collision::Skip {
+                say!("{} {}", style("⊘").yellow(), style(base_name).yellow());
+                say!(
+                    "  {} {} already exists, skipping",
+                    style("┖─").dim(),
+                    display_path(&file)
+                );
+                return Ok(Outcome::Skipped);
+            }
+            let staging = file.with_file_name(format!(".{}.un7z-staging", base_name));
+            lone_file = file.file_name().map(|name| staging.join(name));
+            staged_lone = Some((staging, file));
+        }
+    }
+
+    // --overwrite-newer extracts over existing output instead of skipping or replacing it
+    let update = opts.overwrite_newer && !test && lone_file.is_none() && extract_dir.exists();
+    let merge = opts.dest_exists_action == Some(ExistsAction::Merge)
+        && !test
+        && lone_file.is_none()
+        && extract_dir.exists();
+
+    // Check if already extracted (but skip this check if force is enabled)
+    if !force && !update && !merge && !here && lone_file.is_none() {
+        let existing = existing_output(archive, &extract_dir, marker);
+        log::debug!("{} is {:?}", extract_dir.display(), existing);
+        // Asking covers incomplete output too, so nothing is deleted unconfirmed
+        let ask = opts.interactive_overwrite || opts.dest_exists_action.is_some();
+        match existing {
+            ExistingOutput::Complete if ask => {
+                let action = opts
+                    .dest_exists_action
+                    .unwrap_or_else(|| prompt_exists_action(&extract_dir));
+                match action {
+                    ExistsAction::Skip => {
+                        say!("{} {}", style("⊘").yellow(), style(base_name).yellow());
+                        say!("  {} Kept existing output, skipping", style("┖─").dim());
+                        return Ok(Outcome::Skipped);
+                    }
+                    ExistsAction::Overwrite => retry_fs(|| fs::remove_dir_all(&extract_dir))?,
+                    ExistsAction::Rename => extract_dir = unique_dir(&extract_dir),
+                    ExistsAction::Merge => {}
+                }
+            }
+            ExistingOutput::Complete => {
+                say!("{} {}", style("⊘").yellow(), style(base_name).yellow(),);
+                if marker {
+                    say!("  {} Completion marker found, skipping", style("┖─").dim());
+                } else {
+                    say!(
+                        "  {} Already exists with valid files, skipping",
+                        style("┖─").dim()
+                    );
+                }
+                return Ok(Outcome::Skipped);
+            }
+            ExistingOutput::Incomplete if opts.report_incomplete_only => {
+                say!("{} {}", style("⚠").yellow(), style(base_name).yellow(),);
+                say!(
+                    "  {} Exists but appears incomplete, left for the report",
+                    style("┖─").dim()
+                );
+                return Ok(Outcome::Incomplete(extract_dir));
+            }
+            ExistingOutput::Incomplete if ask => {
+                say!("{} {}", style("⚠").yellow(), style(base_name).yellow(),);
+                say!("  {} Exists but appears incomplete", style("┖─").dim());
+                let action = opts
+                    .dest_exists_action
+                    .unwrap_or_else(|| prompt_exists_action(&extract_dir));
+                match action {
+                    ExistsAction::Skip => {
+                        say!("  {} Kept existing output, skipping", style("┖─").dim());
+                        return Ok(Outcome::Skipped);
+                    }
+                    ExistsAction::Overwrite => retry_fs(|| fs::remove_dir_all(&extract_dir))?,
+                    ExistsAction::Rename => extract_dir = unique_dir(&extract_dir),
+                    ExistsAction::Merge => {}
+                }
+            }
+            ExistingOutput::Incomplete => {
+                say!("{} {}", style("⚠").yellow(), style(base_name).yellow(),);
+                if marker {
+                    say!(
+                        "  {} Exists without a valid completion marker, re-extracting",
+                        style("┖─").dim()
+                    );
+                } else {
+                    say!(
+                        "  {} Exists but appears incomplete, re-extracting",
+                        style("┖─").dim()
+                    );
+                }
+                if opts.no_cleanup {
+                    say!(
+                        "  {} Keeping what is there (--no-cleanup), extracting over it",
+                        style("┖─").dim()
+                    );
+                } else {
+                    let interactive = std::io::stdin().is_terminal();
+                    if needs_reextract_confirmation(&extract_dir, opts.assume_yes, interactive)
+                        && !confirm(&format!(
+                            "Delete {} and re-extract?",
+                            display_path(&extract_dir)
+                        ))
+                    {
+                        say!("  {} Kept existing output, skipping", style("┖─").dim());
+                        return Ok(Outcome::Skipped);
+                    }
+                    retry_fs(|| fs::remove_dir_all(&extract_dir))?;
+                }
+            }
+            ExistingOutput::Missing => {}
+        }
+    }
+    if checksums_match()? == Some(false) {
+        return Ok(Outcome::Skipped);
+    }
+
+    // Tar (and custom tools) require the target directory to exist before extraction,
+    // except a tarball that already wraps everything in a `base_name/` folder: that
+    // one goes into the parent, so the output isn't nested twice
+    let mut tar_dir = extract_dir.clone();
+    if let Some(parent) = lone_file.as_ref().and_then(|file| file.parent()) {
+        fs::create_dir_all(parent)?;
+        tar_dir = parent.to_path_buf();
+    } else if !test
+        && is_tar
+        && opts.extract_into.is_none()
+        && !here
+        && tar_has_own_wrapper(&archive.path, &archive.base_name)
+    {
+        if let Some(parent) = extract_dir.parent() {
+            fs::create_dir_all(parent)?;
+            tar_dir = parent.to_path_buf();
+        }
+    } else if !test
+        && matches!(
+            archive.archive_type,
+            ArchiveType::TarGz | ArchiveType::Custom(_)
+        )
+    {
         fs::create_dir_all(&extract_dir)?;
     }
 
-    // Print what we're about to do
-    if test {
-        println!(
-            "{} {}",
-            style("⟳").cyan(),
-            style(format!("Testing: {}", base_name)).cyan()
+    // Neither backend can prompt through our PTY, so decide up front what an
+    // encrypted archive without a password means (--on-encrypted)
+    let chosen;
+    let mut typed = false;
+    let password = if password.is_none() && archive_is_encrypted(archive) {
+        let stored = keyring_password(opts, archive);
+        match opts.on_encrypted {
+            _ if stored.is_some() => {
+                say!(
+                    "  {} Using the password from the keyring",
+                    style("┖─").dim()
+                );
+                chosen = stored;
+            }
+            OnEncrypted::Skip => {
+                say!("{} {}", style("⊘").yellow(), style(base_name).yellow());
+                say!(
+                    "  {} Encrypted and no password given, skipping",
+                    style("┖─").dim()
+                );
+                return Ok(Outcome::Skipped);
+            }
+            OnEncrypted::Prompt => {
+                (chosen, typed) = opts.session_password.get_or_prompt(
+                    |password| listing::password_works(archive, password),
+                    || prompt_password(base_name),
+                );
+                if chosen.is_some() && !typed {
+                    say!("  {} Using the password entered earlier", style("┖─").dim());
+                }
+            }
+            OnEncrypted::Empty => chosen = Some(String::new()),
+            OnEncrypted::Fail => chosen = None,
+        }
+        if chosen.is_none() {
+            say!("{} {}", style("✗").red(), style(base_name).red());
+            say!(
+                "  {} Encrypted, pass --password to extract",
+                style("┖─").dim()
+            );
+            anyhow::bail!("Encrypted archive and no password given");
+        }
+        &chosen
+    } else {
+        chosen = effective_password(opts.on_encrypted, password);
+        &chosen
+    };
+
+    if archive.archive_type == ArchiveType::Zip {
+        warn_weak_zip_encryption(archive);
+    }
+
+    if let Some(max_files) = opts.max_files.filter(|_| !test) {
+        if !check_file_count(archive, password, max_files, opts.assume_yes) {
+            return Ok(Outcome::Skipped);
+        }
+    }
+
+    // Print what we're about to do
+    if test {
+        say!(
+            "{} {}",
+            style("⟳").cyan(),
+            style(format!("Testing: {}", base_name)).cyan()
+        );
+    } else {
+        say!(
+            "{} {}",
+            style("⟳").cyan(),
+            style(format!("Extracting: {}", base_name)).cyan()
+        );
+    }
+    if update {
+        say!(
+            "  {} {} exists, replacing only older files",
+            style("┖─").dim(),
+            display_path(&extract_dir)
+        );
+    } else if merge {
+        say!(
+            "  {} {} exists, merging into it",
+            style("┖─").dim(),
+            display_path(&extract_dir)
+        );
+    }
+
+    // Run command with PTY for real progress display
+    // (tar prints no progress of its own, so it gets an entry-count bar instead)
+    let show_pty = !opts.no_pty_progress;
+    let pty_bar = (!show_pty && !is_tar).then(|| percent_bar(multi_progress));
+    let mut last_percent = None;
+    let on_progress = &mut |percent| {
+        if last_percent.replace(percent) != Some(percent) {
+            report_progress(percent);
+            if let Some(bar) = &pty_bar {
+                bar.set_position(percent);
+            }
+        }
+    };
+    let confine = |cmd: Command, target: &Archive, writable: Option<&Path>| -> Result<Command> {
+        let Some(sandbox) = opts.sandbox else {
+            return Ok(cmd);
+        };
+        // The sandbox can only open up a directory that exists
+        if let Some(dir) = writable {
+            fs::create_dir_all(dir)?;
+        }
+        // The volumes, and the links to them for a mixed set, may be under /tmp
+        let mut readable: Vec<&Path> = [&archive.path, &target.path]
+            .into_iter()
+            .filter_map(|path| path.parent())
+            .collect();
+        readable.dedup();
+        Ok(sandbox.wrap(&cmd, &readable, writable))
+    };
+    // Without a backend switch for --overwrite-newer, extract beside the output and merge
+    let update_args = archive.update_args().filter(|_| update);
+    let overwrite_args = match update_args {
+        Some(args) => args,
+        None if merge => archive.merge_args(),
+        None if here && !test => {
+            let action = opts.dest_exists_action.unwrap_or(ExistsAction::Skip);
+            archive.here_args(action).with_context(|| {
+                format!(
+                    "--extract-here cannot {} existing files with this backend, \
+                     choose another --dest-exists-action",
+                    format!("{:?}", action).to_lowercase()
+                )
+            })?
+        }
+        None => &[],
+    };
+    let update_staging = (update && update_args.is_none())
+        .then(|| extract_dir.with_file_name(format!(".{}.un7z-update", base_name)));
+    if let Some(staging) = &update_staging {
+        if staging.exists() {
+            retry_fs(|| fs::remove_dir_all(staging))?;
+        }
+        fs::create_dir_all(staging)?;
+    }
+    let out_dir = update_staging.as_deref().unwrap_or(&extract_dir);
+    // Watch where the backend writes, which is the staging directory for an update
+    let guard = match opts.max_output_size {
+        Some(limit) if !test => {
+            let output = lone_file.clone().unwrap_or_else(|| out_dir.to_path_buf());
+            Some(OutputGuard::new(output, limit))
+        }
+        _ => None,
+    };
+    let guard = guard.as_ref();
+    let profile = archive.progress_profile();
+    let mut run = |target: &Archive| {
+        if test {
+            let cmd = target.extract_command(true, password, &extract_dir)?;
+            let mut cmd = confine(cmd, target, None)?;
+            run_with_pty(
+                &mut cmd,
+                &target.path,
+                on_progress,
+                profile,
+                guard,
+                show_pty,
+            )
+        } else if is_tar {
+            let mut cmd = target.extract_command(false, password, &tar_dir)?;
+            cmd.args(overwrite_args);
+            let mut cmd = confine(cmd, target, Some(&tar_dir))?;
+            run_tar_with_progress(&mut cmd, &target.path, multi_progress, on_progress, guard)
+        } else {
+            let mut cmd = target.extract_command(false, password, out_dir)?;
+            cmd.args(overwrite_args);
+            let mut cmd = confine(cmd, target, Some(out_dir))?;
+            run_with_pty(
+                &mut cmd,
+                &target.path,
+                on_progress,
+                profile,
+                guard,
+                show_pty,
+            )
+        }
+    };
+    // 7zz follows a split only when all its volumes are named alike
+    let linked = VolumeLinks::for_mixed(archive)?;
+    let target = linked.as_ref().map_or(archive, |(_, linked)| linked);
+    let prefetch = if opts.prefetch {
+        Prefetch::start(archive.volumes())
+    } else {
+        None
+    };
+    let result = std::thread::scope(|scope| {
+        let (stop, stopped) = std::sync::mpsc::channel();
+        if let Some(guard) = guard {
+            scope.spawn(move || guard.watch(stopped, OUTPUT_GUARD_INTERVAL));
+        }
+        let result = match run(target) {
+            Err(e) if opts.repair => repair_and_retry(archive, e, repair_archive, &mut run),
+            result => result,
+        };
+        let _ = stop.send(());
+        result
+    });
+    let result = match guard {
+        Some(guard) if guard.tripped() => Err(anyhow::anyhow!(
+            "Output grew past --max-output-size ({}), extraction stopped",
+            HumanBytes(guard.limit)
+        )),
+        _ => result,
+    };
+    if let Some(bar) = pty_bar {
+        bar.finish_and_clear();
+    }
+    if let Some(prefetch) = prefetch {
+        let read = prefetch.finish();
+        log::debug!("{} volume(s) prefetched", read.len());
+    }
+
+    let result = match (result, &update_staging) {
+        (Ok(()), Some(staging)) => merge_newer(staging, &extract_dir).map(|(placed, kept)| {
+            log::debug!("{} file(s) placed, {} newer one(s) kept", placed, kept);
+            if kept > 0 {
+                say!(
+                    "  {} Kept {} file(s) newer than the archive's",
+                    style("┖─").dim(),
+                    kept
+                );
+            }
+        }),
+        (result, _) => result,
+    };
+    if let Some(staging) = update_staging.as_ref().filter(|dir| dir.exists()) {
+        retry_fs(|| fs::remove_dir_all(staging))?;
+    }
+
+    // The output of a single-file tarball is checked by tar itself, and an
+    // updated one keeps newer files that differ from the archive on purpose
+    let verify = opts.verify_extraction && !test && lone_file.is_none() && !update;
+    let result = match result {
+        Ok(()) if verify && !matches!(archive.archive_type, ArchiveType::Custom(_)) => {
+            verify_extraction(
+                archive,
+                password,
+                if is_tar { &tar_dir } else { &extract_dir },
+            )
+        }
+        result => result,
+    };
+
+    if let (Ok(()), true, Some(password)) = (&result, typed, password) {
+        opts.session_password.remember(password);
+        if opts.keyring_store {
+            let entry = keyring::Entry::for_base_name(&archive.base_name);
+            match keyring::set(&entry, password) {
+                Ok(()) => say!("  {} Saved the password to the keyring", style("┖─").dim()),
+                Err(e) => eprintln!("{} {:#}", style("⚠").yellow(), e),
+            }
+        }
+    }
+
+    // Handle result
+    match &result {
+        Ok(()) => {
+            say!("{} {}", style("✓").green(), style(base_name).green());
+            if !test && !here {
+                let output = lone_file.as_ref().unwrap_or(&extract_dir);
+                report_ratio(archive, tree_size(output), opts.json);
+            }
+        }
+        Err(e) => {
+            say!("{} {}", style("✗").red(), style(base_name).red());
+            say!("  {} Error: {}", style("┖─").dim(), e);
+        }
+    }
+
+    if let Err(e) = result {
+        let partial = lone_file.as_ref().unwrap_or(&extract_dir);
+        if opts.no_cleanup && partial.exists() && !test {
+            say!(
+                "  {} Partial output kept in {}",
+                style("┖─").dim(),
+                display_path(partial)
+            );
+        } else if let Some((staging, _)) = staged_lone.as_ref().filter(|(dir, _)| dir.exists()) {
+            retry_fs(|| fs::remove_dir_all(staging))?;
+        } else if let Some(file) = lone_file.as_ref().filter(|file| file.exists()) {
+            retry_fs(|| fs::remove_file(file))?;
+        } else if update || merge {
+            say!(
+                "  {} Existing output in {} left as it is",
+                style("┖─").dim(),
+                display_path(&extract_dir)
+            );
+        } else if here {
+            say!(
+                "  {} Files already extracted into {} left in place",
+                style("┖─").dim(),
+                display_path(&extract_dir)
+            );
+        } else if extract_dir.exists() {
+            retry_fs(|| fs::remove_dir_all(&extract_dir))?;
+        }
+        if !test {
+            let on_failure = opts.hooks.on_failure.as_deref();
+            run_hook(
+                "on-failure",
+                on_failure,
+                &extract_dir,
+                archive,
+                opts.hooks.timeout,
+            );
+        }
+        return Err(e);
+    }
+
+    if test {
+        return Ok(Outcome::Tested);
+    }
+
+    // The output is the lone file itself from here on
+    let marker = marker && lone_file.is_none();
+    let mut extract_dir = lone_file.unwrap_or(extract_dir);
+    if let Some((staging, target)) = staged_lone {
+        let collision = opts.collision.unwrap_or_default();
+        match resolve_collision(&target, &extract_dir, collision)? {
+            Some(placed) => {
+                retry_fs(|| fs::rename(&extract_dir, &placed))?;
+                extract_dir = placed;
+            }
+            None => {
+                let hashed = tagged_name(&target, &content_hash(&extract_dir)?, true);
+                say!(
+                    "  {} Same content already in {}",
+                    style("┖─").dim(),
+                    display_path(&hashed)
+                );
+                extract_dir = hashed;
+            }
+        }
+        retry_fs(|| fs::remove_dir_all(&staging))?;
+    }
+
+    if opts.no_special {
+        let removed = remove_special_files(&extract_dir)?;
+        if !removed.is_empty() {
+            say!(
+                "  {} Removed {} unsafe entr(ies) (--no-special):",
+                style("⚠").yellow(),
+                removed.len()
+            );
+            for (path, kind) in &removed {
+                say!("    {} {} ({})", style("•").dim(), display_path(path), kind);
+            }
+        }
+    }
+
+    if let Some(form) = opts.normalize_unicode {
+        let renamed = normalize::normalize_tree(&extract_dir, form)?;
+        if !renamed.is_empty() {
+            say!(
+                "  {} Normalized {} name(s) to {}",
+                style("┖─").dim(),
+                renamed.len(),
+                form.as_str()
+            );
+        }
+        for rename in &renamed {
+            if rename.collided {
+                say!(
+                    "    {} {} collides after normalization, renamed to {}",
+                    style("⚠").yellow(),
+                    display_path(&rename.from),
+                    display_path(&rename.to)
+                );
+            }
+            if rename.from == extract_dir {
+                extract_dir = rename.to.clone();
+            }
+        }
+    }
+
+    // Touching the whole directory would reach files the archive never had
+    if settings.touch && !here {
+        let mtime = fs::metadata(&archive.path)?.modified()?;
+        let touched = touch_tree(&extract_dir, mtime)?;
+        log::debug!(
+            "Set mtime of {} file(s) in {}",
+            touched,
+            extract_dir.display()
+        );
+    }
+
+    if let Some(format) = opts.repack {
+        let packed = repack::repack(&extract_dir, format, opts.repack_remove)?;
+        say!(
+            "  {} Repacked into {}",
+            style("┖─").dim(),
+            display_path(&packed)
+        );
+        if opts.repack_remove {
+            extract_dir = packed;
+        }
+    }
+
+    if marker && extract_dir.is_dir() {
+        Marker::for_archive(archive, &extract_dir)?.write(&extract_dir)?;
+    }
+
+    let extract_dir = match &opts.move_to {
+        Some(move_to) => relocate_output(
+            &extract_dir,
+            move_to,
+            opts.collision,
+            opts.interactive_overwrite,
+        )?,
+        None => extract_dir,
+    };
+
+    let on_success = opts.hooks.on_success.as_deref();
+    run_hook(
+        "on-success",
+        on_success,
+        &extract_dir,
+        archive,
+        opts.hooks.timeout,
+    );
+
+    if let Some(success_log) = &opts.success_log {
+        if let Err(e) = append_success_log(success_log, archive) {
+            eprintln!("{} {:#}", style("⚠").yellow(), e);
+        }
+    }
+
+    Ok(Outcome::Extracted(extract_dir))
+}
+
+/// `--prefetch`: reads the volumes after the first into the OS cache on a
+/// background thread, so the backend finds them there instead of waiting on
+/// slow storage when it moves on. Stopped once extraction ends.
+struct Prefetch {
+    stop: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    handle: std::thread::JoinHandle<Vec<PathBuf>>,
+}
+
+impl Prefetch {
+    /// Start reading ahead; None for a single-volume archive.
+    fn start(volumes: Vec<PathBuf>) -> Option<Self> {
+        if upcoming_volumes(&volumes).is_empty() {
+            return None;
+        }
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = stop.clone();
+        let handle =
+            std::thread::spawn(move || prefetch_volumes(upcoming_volumes(&volumes), &flag));
+        Some(Prefetch { stop, handle })
+    }
+
+    /// Stop reading and return the volumes that were read completely.
+    fn finish(self) -> Vec<PathBuf> {
+        self.stop.store(true, std::sync::atomic::Ordering::SeqCst);
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+/// The volumes worth prefetching: all but the first, which the backend
+/// opens right away.
+fn upcoming_volumes(volumes: &[PathBuf]) -> &[PathBuf] {
+    volumes.get(1..).unwrap_or_default()
+}
+
+/// Read `volumes` in order and throw the data away, until done or `stop` is
+/// set. Returns the volumes read to the end.
+fn prefetch_volumes(volumes: &[PathBuf], stop: &std::sync::atomic::AtomicBool) -> Vec<PathBuf> {
+    use std::sync::atomic::Ordering;
+    let mut buf = vec![0u8; 1 << 20];
+    let mut done = Vec::new();
+    for volume in volumes {
+        let Ok(mut file) = fs::File::open(volume) else {
+            continue;
+        };
+        loop {
+            if stop.load(Ordering::SeqCst) {
+                return done;
+            }
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    log::debug!("Prefetch of {} stopped: {}", volume.display(), e);
+                    break;
+                }
+            }
+        }
+        log::debug!("Prefetched {}", volume.display());
+        done.push(volume.clone());
+    }
+    done
+}
+
+/// A repaired copy of an archive, in a temporary directory that is removed
+/// on drop so the copy is never picked up as an archive of its own.
+struct Repaired {
+    dir: PathBuf,
+    archive: Archive,
+}
+
+impl Drop for Repaired {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            log::warn!("Cannot remove {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+/// Run `rar r` on a damaged archive, writing the repaired copy to a
+/// temporary directory.
+fn repair_archive(archive: &Archive) -> Result<Repaired> {
+    let name = archive
+        .path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Archive has no file name"))?;
+    // From here on, returning early removes the directory again
+    let mut repaired = Repaired {
+        dir: create_temp_dir(&std::env::temp_dir(), "un7z-repair")?,
+        archive: Archive {
+            path: PathBuf::new(),
+            base_name: archive.base_name.clone(),
+            archive_type: ArchiveType::Rar,
+            type_suffix: archive.type_suffix,
+        },
+    };
+    let mut cmd = archive.repair_command(&repaired.dir).ok_or_else(|| {
+        anyhow::anyhow!("No repair command for {}", archive.archive_type.as_str())
+    })?;
+    let profile = archive.progress_profile();
+    run_with_pty(&mut cmd, &archive.path, &mut |_| {}, profile, None, true)?;
+
+    // RAR 5 writes `fixed.<name>`, older versions `rebuilt.<name>`
+    repaired.archive.path = ["fixed.", "rebuilt."]
+        .iter()
+        .map(|prefix| {
+            let mut file_name = OsString::from(prefix);
+            file_name.push(name);
+            repaired.dir.join(file_name)
+        })
+        .find(|path| path.is_file())
+        .ok_or_else(|| anyhow::anyhow!("rar reported success but wrote no repaired copy"))?;
+    Ok(repaired)
+}
+
+/// `--repair`: after `err`, try `repair` and run `retry` on the repaired copy,
+/// which is deleted afterwards. Only RAR archives carry recovery records;
+/// anything else keeps its error. A failed repair reports the original error,
+/// not the repair's.
+fn repair_and_retry<R, F>(archive: &Archive, err: anyhow::Error, repair: R, retry: F) -> Result<()>
+where
+    R: FnOnce(&Archive) -> Result<Repaired>,
+    F: FnOnce(&Archive) -> Result<()>,
+{
+    if archive.archive_type != ArchiveType::Rar {
+        say!(
+            "  {} Repair is only possible for RAR archives, not .{}",
+            style("┖─").dim(),
+            archive.archive_type.as_str()
+        );
+        return Err(err);
+    }
+
+    say!(
+        "  {} {}",
+        style("┖─").dim(),
+        style("Trying to repair from the recovery record").cyan()
+    );
+    let repaired = match repair(archive) {
+        Ok(repaired) => repaired,
+        Err(repair_err) => {
+            say!("  {} Repair failed: {:#}", style("┖─").dim(), repair_err);
+            return Err(err);
+        }
+    };
+    say!(
+        "  {} Repaired copy {}, retrying",
+        style("┖─").dim(),
+        style(display_path(&repaired.archive.path)).cyan()
+    );
+    retry(&repaired.archive)?;
+    say!(
+        "  {} {}",
+        style("┖─").dim(),
+        style("Succeeded after repair").green()
+    );
+    Ok(())
+}
+
+/// `--quick-test`: check headers only and report it as a quick check.
+fn quick_test_archive(archive: &Archive, password: &Option<String>) -> Result<Outcome> {
+    let base_name = display_name(&archive.base_name);
+    say!(
+        "{} {}",
+        style("⟳").cyan(),
+        style(format!("Quick check: {}", base_name)).cyan()
+    );
+    let result = archive.quick_test_command(password).and_then(|mut cmd| {
+        log_command(&cmd, archive.dir());
+        let output = cmd
+            .stdin(Stdio::null())
+            .output()
+            .context("Failed to run backend")?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let mut tail = OutputTail::default();
+        tail.push(&output.stdout);
+        tail.push(&output.stderr);
+        let reason = exit_status_reason(output.status);
+        Err(BackendError {
+            reason,
+            output: tail.into_lines(),
+        }
+        .into())
+    });
+    match result {
+        Ok(()) => {
+            say!("{} {}", style("✓").green(), style(&base_name).green());
+            say!(
+                "  {} Headers OK (quick check, data not tested)",
+                style("┖─").dim()
+            );
+            Ok(Outcome::QuickChecked)
+        }
+        Err(e) => {
+            say!("{} {}", style("✗").red(), style(&base_name).red());
+            say!("  {} Error: {}", style("┖─").dim(), e);
+            Err(e)
+        }
+    }
+}
+
+/// `--preview N`: extract only the first `count` files into `base_name.preview/`
+/// next to where the archive would be extracted, replacing an older preview.
+fn preview_archive(
+    archive: &Archive,
+    count: usize,
+    password: &Option<String>,
+    extract_dir: &Path,
+) -> Result<Outcome> {
+    let base_name = display_name(&archive.base_name);
+    say!(
+        "{} {}",
+        style("⟳").cyan(),
+        style(format!("Preview: {}", base_name)).cyan()
+    );
+    let mut preview_name = extract_dir.file_name().unwrap_or_default().to_os_string();
+    preview_name.push(".preview");
+    let preview_dir = extract_dir.with_file_name(preview_name);
+
+    let result = listing::list_entries(archive, password).and_then(|entries| {
+        let names = listing::first_files(&entries, count);
+        if names.is_empty() {
+            anyhow::bail!("Archive lists no files");
+        }
+        if preview_dir.exists() {
+            fs::remove_dir_all(&preview_dir)?;
+        }
+        fs::create_dir_all(&preview_dir)?;
+        let mut cmd = listing::extract_only_command(archive, password, &preview_dir, &names)?;
+        log_command(&cmd, archive.dir());
+        let output = cmd
+            .stdin(Stdio::null())
+            .output()
+            .context("Failed to run backend")?;
+        if !output.status.success() {
+            let mut tail = OutputTail::default();
+            tail.push(&output.stdout);
+            tail.push(&output.stderr);
+            let reason = exit_status_reason(output.status);
+            return Err(BackendError {
+                reason,
+                output: tail.into_lines(),
+            }
+            .into());
+        }
+        Ok(names.len())
+    });
+    match result {
+        Ok(files) => {
+            say!("{} {}", style("✓").green(), style(&base_name).green());
+            say!(
+                "  {} {} file(s) in {}",
+                style("┖─").dim(),
+                files,
+                style(display_path(&preview_dir)).cyan()
+            );
+            Ok(Outcome::Previewed(preview_dir))
+        }
+        Err(e) => {
+            say!("{} {}", style("✗").red(), style(&base_name).red());
+            say!("  {} Error: {}", style("┖─").dim(), e);
+            Err(e)
+        }
+    }
+}
+
+/// `--touch`: set the mtime of every regular file under `dir` to `mtime`.
+/// Symlinks are left alone. Returns the number of files updated.
+fn touch_tree(dir: &Path, mtime: SystemTime) -> Result<usize> {
+    let mut touched = 0;
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let file = fs::File::options()
+            .write(true)
+            .open(entry.path())
+            .or_else(|_| fs::File::open(entry.path()))?;
+        file.set_modified(mtime)
+            .with_context(|| format!("Cannot set mtime of {}", display_path(entry.path())))?;
+        touched += 1;
+    }
+    Ok(touched)
+}
+
+/// `path` with `.` and `..` resolved without touching the filesystem.
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normal.pop();
+            }
+            other => normal.push(other),
+        }
+    }
+    normal
+}
+
+/// Why an extracted entry under `root` isn't safe to keep: a device node,
+/// FIFO or socket, or a symlink whose target lies outside `root`.
+fn special_kind(path: &Path, file_type: fs::FileType, root: &Path) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_symlink() {
+        let target = fs::read_link(path).ok()?;
+        let resolved = normalize_lexically(&path.parent()?.join(target));
+        (!resolved.starts_with(root)).then_some("symlink out of the output")
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        Some("device node")
+    } else if file_type.is_fifo() {
+        Some("FIFO")
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else {
+        None
+    }
+}
+
+/// `--no-special`: delete the entries `special_kind` flags under `output` (a
+/// directory, or a lone extracted file) and return them with the reason.
+fn remove_special_files(output: &Path) -> Result<Vec<(PathBuf, &'static str)>> {
+    let output = normalize_lexically(&std::path::absolute(output)?);
+    let root = if output.is_dir() && !output.is_symlink() {
+        output.clone()
+    } else {
+        output.parent().map(Path::to_path_buf).unwrap_or_default()
+    };
+    let found: Vec<(PathBuf, &'static str)> = WalkDir::new(&output)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            Some((
+                e.path().to_path_buf(),
+                special_kind(e.path(), e.file_type(), &root)?,
+            ))
+        })
+        .collect();
+    for (path, _) in &found {
+        retry_fs(|| fs::remove_file(path))
+            .with_context(|| format!("Cannot remove {}", display_path(path)))?;
+    }
+    Ok(found)
+}
+
+/// Build a hook command from its template, substituting `{dir}` (the output
+/// directory) and `{name}` (the archive's base name).
+fn hook_command(template: &str, dir: &Path, archive: &Archive) -> Result<Command> {
+    expand_template(
+        template,
+        &[("dir", dir), ("name", Path::new(&archive.base_name))],
+    )
+}
+
+/// Run `cmd`, killing it if it is still running after `timeout`.
+fn run_with_timeout(cmd: &mut Command, timeout: Duration) -> Result<std::process::ExitStatus> {
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to run {:?}", cmd.get_program()))?;
+    // None when too far off for the clock, which never comes anyway
+    let deadline = std::time::Instant::now().checked_add(timeout);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline) {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("timed out after {}s", timeout.as_secs_f32());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Run a `--on-success`/`--on-failure` hook if one is configured. A failing
+/// hook is reported but never changes the archive's outcome.
+fn run_hook(label: &str, template: Option<&str>, dir: &Path, archive: &Archive, timeout: Duration) {
+    let Some(template) = template else {
+        return;
+    };
+    let status = hook_command(template, dir, archive).and_then(|mut cmd| {
+        log::debug!("Running {} hook `{}`", label, redacted_command_string(&cmd));
+        run_with_timeout(&mut cmd, timeout)
+    });
+    match status {
+        Ok(status) if status.success() => log::info!("{} hook exited with {}", label, status),
+        Ok(status) => {
+            log::warn!("{} hook exited with {}", label, status);
+            let reason = exit_status_reason(status);
+            say!("  {} {} hook failed: {}", style("┖─").dim(), label, reason);
+        }
+        Err(e) => {
+            log::warn!("{} hook failed: {}", label, e);
+            say!("  {} {} hook failed: {}", style("┖─").dim(), label, e);
+        }
+    }
+}
+
+/// `--test-part`: integrity-test one volume of a multi-volume set.
+fn test_single_part(path: &Path, password: &Option<String>) -> Result<()> {
+    let (archive, number) = Archive::from_volume(path.to_path_buf())
+        .ok_or_else(|| anyhow::anyhow!("{} is not an archive volume", display_path(path)))?;
+    println!(
+        "\n{} {} {}",
+        style("Testing").bold(),
+        style(display_path(&archive.path)).cyan(),
+        style(format!("(volume {})", number)).dim()
+    );
+    let mut cmd = archive.part_test_command(number, password)?;
+    let profile = archive.progress_profile();
+    run_with_pty(&mut cmd, &archive.path, &mut |_| {}, profile, None, true)?;
+    println!("{} {}", style("✓").green(), style("Volume OK").green());
+    Ok(())
+}
+
+/// The level the command line asks for: `--log-level`, else debug for
+/// `--verbose` (backend commands, detection and skip reasons).
+fn log_filter(args: &Args) -> Option<log::LevelFilter> {
+    args.log_level
+        .or(args.verbose.then_some(log::LevelFilter::Debug))
+}
+
+/// Set up logging: --log-level wins, then --verbose (debug), then RUST_LOG, else warnings.
+fn init_logging(args: &Args) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    if let Some(level) = log_filter(args) {
+        builder.filter_level(level);
+    }
+    let _ = builder.try_init();
+}
+
+/// `--sandbox`: a tool that runs the backend confined (Linux only).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sandbox {
+    Bwrap,
+    Firejail,
+}
+
+impl Sandbox {
+    /// In order of preference.
+    const ALL: [Sandbox; 2] = [Sandbox::Bwrap, Sandbox::Firejail];
+
+    fn program(self) -> &'static str {
+        match self {
+            Sandbox::Bwrap => "bwrap",
+            Sandbox::Firejail => "firejail",
+        }
+    }
+
+    /// The first sandbox tool installed on `search_path`.
+    fn detect(search_path: &std::ffi::OsStr) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|s| find_in_path(s.program(), search_path).is_some())
+    }
+
+    /// The tool to use for `--sandbox`; without one, warn and run unconfined.
+    fn resolve(requested: bool) -> Option<Self> {
+        if !requested {
+            return None;
+        }
+        let found = if cfg!(target_os = "linux") {
+            Self::detect(&std::env::var_os("PATH").unwrap_or_default())
+        } else {
+            None
+        };
+        if found.is_none() {
+            let why = if cfg!(target_os = "linux") {
+                "needs bwrap or firejail on PATH"
+            } else {
+                "is only supported on Linux"
+            };
+            println!(
+                "{} --sandbox {}, running backends unconfined",
+                style("⚠").yellow(),
+                why
+            );
+        }
+        found
+    }
+
+    /// `cmd` run inside the sandbox: read-only filesystem, private /tmp, no
+    /// network, and only `writable` (the extraction target) open for writes.
+    /// `readable` (the archive's directories) stay visible even under /tmp.
+    fn wrap(self, cmd: &Command, readable: &[&Path], writable: Option<&Path>) -> Command {
+        let mut wrapped = Command::new(self.program());
+        match self {
+            Sandbox::Bwrap => {
+                wrapped.args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"]);
+                wrapped.args(["--tmpfs", "/tmp"]);
+                for dir in readable {
+                    wrapped.arg("--ro-bind").arg(dir).arg(dir);
+                }
+                if let Some(dir) = writable {
+                    wrapped.arg("--bind").arg(dir).arg(dir);
+                }
+                wrapped.args(["--unshare-all", "--die-with-parent", "--new-session"]);
+            }
+            Sandbox::Firejail => {
+                wrapped.args(["--quiet", "--noprofile", "--net=none", "--private-tmp"]);
+                wrapped.arg("--read-only=/");
+                // Firejail keeps what is under /tmp only when whitelisted
+                let in_tmp = readable.iter().copied().chain(writable);
+                for dir in in_tmp.filter(|dir| dir.starts_with("/tmp")) {
+                    let mut whitelist = OsString::from("--whitelist=");
+                    whitelist.push(dir);
+                    wrapped.arg(whitelist);
+                }
+                if let Some(dir) = writable {
+                    let mut rw = OsString::from("--read-write=");
+                    rw.push(dir);
+                    wrapped.arg(rw);
+                }
+            }
+        }
+        wrapped
+            .arg("--")
+            .arg(cmd.get_program())
+            .args(cmd.get_args());
+        if let Some(dir) = cmd.get_current_dir() {
+            wrapped.current_dir(dir);
+        }
+        for (key, value) in cmd.get_envs() {
+            match value {
+                Some(value) => wrapped.env(key, value),
+                None => wrapped.env_remove(key),
+            };
+        }
+        wrapped
+    }
+}
+
+/// Backends `--doctor` checks, with the arguments that make each print its version.
+const BACKENDS: [(&str, &[&str]); 6] = [
+    ("7zz", &[]),
+    ("unrar", &[]),
+    ("tar", &["--version"]),
+    ("gzip", &["--version"]),
+    ("xz", &["--version"]),
+    ("zstd", &["--version"]),
+];
+
+/// What `--doctor` found out about one backend.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct BackendProbe {
+    name: &'static str,
+    /// Resolved executable, None if not on the search path.
+    path: Option<PathBuf>,
+    /// First line the tool printed for its version, if it ran.
+    version: Option<String>,
+}
+
+/// First executable called `name` in the directories of `search_path`.
+fn find_in_path(name: &str, search_path: &std::ffi::OsStr) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+    std::env::split_paths(search_path)
+        .map(|dir| dir.join(name))
+        .find(|path| {
+            fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+/// What a backend prints (stdout, then stderr) when run with `args`, or None
+/// when it can't be run.
+fn backend_output(program: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    Some(
+        String::from_utf8_lossy(&output.stdout).into_owned()
+            + &String::from_utf8_lossy(&output.stderr),
+    )
+}
+
+/// Locate each backend on `search_path` and ask it for its version.
+fn probe_backends(search_path: &std::ffi::OsStr) -> Vec<BackendProbe> {
+    BACKENDS
+        .iter()
+        .map(|&(name, version_args)| {
+            let path = find_in_path(name, search_path);
+            let version = path.as_ref().and_then(|path| {
+                backend_output(path, version_args)?
+                    .lines()
+                    .map(str::trim)
+                    .find(|l| !l.is_empty())
+                    .map(String::from)
+            });
+            BackendProbe {
+                name,
+                path,
+                version,
+            }
+        })
+        .collect()
+}
+
+/// Version of the `--version-json` layout, raised when fields change meaning.
+const VERSION_JSON_FORMAT: u32 = 1;
+
+/// `--version-json`: what `--doctor` reports, as one JSON object.
+fn version_json(probes: &[BackendProbe]) -> serde_json::Value {
+    serde_json::json!({
+        "format": VERSION_JSON_FORMAT,
+        "version": env!("CARGO_PKG_VERSION"),
+        "backends": probes,
+        "platform": {
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+            "pty": Path::new("/dev/ptmx").exists(),
+        },
+    })
+}
+
+/// `--doctor`: report the backends found, their versions, and PTY support.
+fn print_doctor_report() {
+    println!("\n{}", style("Backends").bold());
+    let search_path = std::env::var_os("PATH").unwrap_or_default();
+    for probe in probe_backends(&search_path) {
+        match &probe.path {
+            Some(path) => println!(
+                "  {} {:<6} {} {}",
+                style("✓").green(),
+                probe.name,
+                style(display_path(path)).cyan(),
+                style(
+                    probe
+                        .version
+                        .as_deref()
+                        .map(display_name)
+                        .unwrap_or_default()
+                )
+                .dim()
+            ),
+            None => println!(
+                "  {} {:<6} {}",
+                style("✗").red(),
+                probe.name,
+                style("not found").red()
+            ),
+        }
+    }
+
+    println!("\n{}", style("Platform").bold());
+    println!(
+        "  {} {}/{}",
+        style("•").dim(),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let ptmx = Path::new("/dev/ptmx").exists();
+    println!(
+        "  {} PTY (/dev/ptmx): {}",
+        style("•").dim(),
+        if ptmx {
+            style("available").green()
+        } else {
+            style("missing").red()
+        }
+    );
+    let tty = if std::io::stdout().is_terminal() {
+        "yes"
+    } else {
+        "no"
+    };
+    println!("  {} stdout is a terminal: {}", style("•").dim(), tty);
+}
+
+/// Write the completion script for `shell` (`--generate-completions`).
+fn write_completions(shell: clap_complete::Shell, out: &mut dyn Write) {
+    clap_complete::generate(shell, &mut Args::command(), "un7z", out);
+}
+
+/// A new, empty directory under the system temp directory.
+fn create_temp_target() -> Result<PathBuf> {
+    create_temp_dir(&std::env::temp_dir(), "un7z")
+}
+
+/// A new directory `{prefix}-...` under `root`, created by us: a name that
+/// is already taken (maybe by someone else, maybe as a symlink) is never used.
+pub(crate) fn create_temp_dir(root: &Path, prefix: &str) -> Result<PathBuf> {
+    let stamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    for attempt in 0..100 {
+        let dir = root.join(format!(
+            "{}-{}-{}-{}",
+            prefix,
+            std::process::id(),
+            stamp,
+            attempt
+        ));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Cannot create {}", dir.display())),
+        }
+    }
+    anyhow::bail!("Cannot find a free temporary directory name")
+}
+
+/// The arguments for the `--extract-to-temp` (or `--pipe-tar`) child: ours,
+/// with the flag swapped for `--extract-into dir`.
+fn extract_to_temp_args(args: impl IntoIterator<Item = OsString>, dir: &Path) -> Vec<OsString> {
+    let mut child: Vec<OsString> = args
+        .into_iter()
+        .filter(|arg| arg != "--extract-to-temp" && arg != "--pipe-tar")
+        .collect();
+    child.push("--extract-into".into());
+    child.push(dir.into());
+    child
+}
+
+/// `--extract-to-temp`: run the extraction as a child whose stdout goes to our
+/// stderr, so the temporary directory's path is all that reaches stdout. The
+/// directory is the caller's to delete, unless extraction failed.
+fn extract_to_temp() -> Result<()> {
+    let dir = create_temp_target()?;
+    if let Err(e) = extract_in_child(&dir) {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(e);
+    }
+    println!("{}", dir.display());
+    Ok(())
+}
+
+/// Run this invocation again as a child extracting into `dir`, its stdout
+/// sent to our stderr.
+fn extract_in_child(dir: &Path) -> Result<()> {
+    use std::os::fd::AsFd;
+
+    let stdout = std::io::stderr().as_fd().try_clone_to_owned()?;
+    let status = Command::new(std::env::current_exe().context("Cannot find the un7z binary")?)
+        .args(extract_to_temp_args(std::env::args_os().skip(1), dir))
+        .stdout(Stdio::from(stdout))
+        .status()
+        .context("Failed to start the extraction")?;
+    if !status.success() {
+        anyhow::bail!("Extraction into a temporary directory failed");
+    }
+    Ok(())
+}
+
+/// `tar` writing the contents of `dir` (not `dir` itself) to stdout.
+fn pipe_tar_command(dir: &Path) -> Command {
+    let mut cmd = Command::new("tar");
+    cmd.arg("-cf").arg("-").arg("-C").arg(dir).arg(".");
+    cmd
+}
+
+/// `--pipe-tar`: extract into a temporary directory as `--extract-to-temp`
+/// does, then stream it to stdout as a tar. The directory is removed
+/// afterwards, whether or not that worked.
+fn pipe_tar() -> Result<()> {
+    stream_tar(&create_temp_target()?, extract_in_child, Stdio::inherit())
+}
+
+/// Extract into `dir` with `extract`, tar it to `out` and delete `dir`.
+fn stream_tar(dir: &Path, extract: impl FnOnce(&Path) -> Result<()>, out: Stdio) -> Result<()> {
+    let result = extract(dir).and_then(|()| {
+        let mut cmd = pipe_tar_command(dir);
+        log_command(&cmd, dir);
+        let status = cmd.stdout(out).status().context("Failed to start tar")?;
+        if !status.success() {
+            anyhow::bail!("Packing the extracted files failed (tar {})", status);
+        }
+        Ok(())
+    });
+    if let Err(e) = retry_fs(|| fs::remove_dir_all(dir)) {
+        log::warn!("Cannot remove {}: {}", dir.display(), e);
+    }
+    result
+}
+
+/// A URL that could not be downloaded, and why.
+type FailedDownload = (String, anyhow::Error);
+
+/// Download the URLs among `files`, at most `max_concurrent` at a time, all
+/// before extraction starts. Returns `files` with each downloaded URL replaced
+/// by its path and each failed one left out, the downloads (kept until the run
+/// ends), and the failed URLs.
+fn download_urls(
+    files: &[PathBuf],
+    max_concurrent: usize,
+) -> (Vec<PathBuf>, Vec<download::Download>, Vec<FailedDownload>) {
+    let urls: Vec<String> = files
+        .iter()
+        .filter(|file| download::is_url(file))
+        .map(|file| file.to_string_lossy().into_owned())
+        .collect();
+    let progress = MultiProgress::new();
+    let results = download::fetch_all(&urls, max_concurrent, |url| {
+        download::Download::fetch(url, &progress)
+    });
+    let mut downloads = Vec::new();
+    let mut fetched = Vec::new();
+    let mut failed = Vec::new();
+    for (url, result) in urls.into_iter().zip(results) {
+        match result {
+            Ok(download) => {
+                println!(
+                    "{} Downloaded {}",
+                    style("✓").green(),
+                    style(display_name(&url)).cyan()
+                );
+                fetched.push(Some(download.path.clone()));
+                downloads.push(download);
+            }
+            Err(e) => {
+                eprintln!("{} {:#}", style("✗").red(), e);
+                fetched.push(None);
+                failed.push((url, e));
+            }
+        }
+    }
+    (replace_urls(files, fetched), downloads, failed)
+}
+
+/// `files` with the URLs among them replaced, in turn, by `fetched`: the
+/// downloaded path, or None to leave a failed URL out.
+fn replace_urls(files: &[PathBuf], fetched: Vec<Option<PathBuf>>) -> Vec<PathBuf> {
+    let mut fetched = fetched.into_iter();
+    files
+        .iter()
+        .filter_map(|file| {
+            if download::is_url(file) {
+                fetched.next().flatten()
+            } else {
+                Some(file.clone())
+            }
+        })
+        .collect()
+}
+
+/// Append a failed download to failed.log, like `log_failed_archive`.
+fn log_failed_download(url: &str, err: &anyhow::Error) {
+    let Ok(mut file) = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open("failed.log")
+    else {
+        return;
+    };
+    let ts = Local::now().format("%Y-%m-%d %H:%M:%S");
+    let err_text = format!("{:#}", err);
+    let _ = writeln!(
+        file,
+        "---\ntime: {}\nurl: {}\nerror: {}\n",
+        ts,
+        display_name(url),
+        err_text.replace('\n', " ")
+    );
+}
+
+fn main() -> Result<()> {
+    let mut args = Args::parse();
+    if let Some(shell) = args.generate_completions {
+        write_completions(shell, &mut std::io::stdout());
+        return Ok(());
+    }
+    init_logging(&args);
+    if args.version_json {
+        let search_path = std::env::var_os("PATH").unwrap_or_default();
+        println!("{}", version_json(&probe_backends(&search_path)));
+        return Ok(());
+    }
+    if let Some(Cmd::Detect { files, json }) = &args.command {
+        return run_detect(files, &args.custom_types, *json || args.json);
+    }
+    if args.extract_to_temp {
+        return extract_to_temp();
+    }
+    if args.pipe_tar {
+        return pipe_tar();
+    }
+    if args.no_color {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+
+    // Print banner (kept off stdout when it carries JSON)
+    if !args.json {
+        println!(
+            "\n{} {} {}",
+            style("un7z").bold().cyan(),
+            style(format!("v{}", env!("CARGO_PKG_VERSION"))).dim(),
+            style("- Modern Batch Extraction").cyan()
+        );
+    }
+
+    if args.doctor {
+        print_doctor_report();
+        return Ok(());
+    }
+
+    if let Some(report) = &args.show_report {
+        let file = fs::File::open(report)
+            .with_context(|| format!("Cannot open report {}", report.display()))?;
+        let (finished, mut stats) = stats_from_events(BufReader::new(file))?;
+        for line in summary_lines(finished, &mut stats) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    if args.list_types {
+        print_types(&args.custom_types);
+        return Ok(());
+    }
+
+    if let Some(part) = &args.test_part {
+        return test_single_part(part, &args.password);
+    }
+
+    // --dir only matters when nothing is listed
+    let scan_dirs = if args.files.is_empty() && !args.stdin {
+        dir_pattern::expand(&args.dir)?
+    } else {
+        Vec::new()
+    };
+
+    if args.join || args.concat {
+        let splits = if args.concat {
+            bare_splits(&args.files, &scan_dirs)
+        } else {
+            joinable_splits(&args.files, &scan_dirs, &args.custom_types)
+        };
+        for first in &splits {
+            let target = join_split(first, args.delete_parts)?;
+            println!(
+                "{} Joined {}",
+                style("✓").green(),
+                style(display_path(&target)).cyan()
+            );
+        }
+        println!("\n{} split file(s) joined", style(splits.len()).yellow());
+        return Ok(());
+    }
+
+    let events = args.events.as_deref().map(EventLog::create).transpose()?;
+
+    // Downloads live until the run ends
+    let (files, downloads, failed_downloads) =
+        download_urls(&args.files, args.max_concurrent_downloads.get());
+    for (url, e) in &failed_downloads {
+        log_failed_download(url, e);
+    }
+    if args.fail_fast && !failed_downloads.is_empty() {
+        anyhow::bail!(
+            "{} download(s) failed (--fail-fast)",
+            failed_downloads.len()
+        );
+    }
+    args.files = files;
+
+    let spec = match &args.spec {
+        Some(path) => spec::Spec::load(path)?,
+        None => spec::Spec::default(),
+    };
+    let scan_opts = ScanOptions {
+        custom_types: args.custom_types.clone(),
+        root_only: args.archive_root_only,
+        min_file_size: args.min_file_size,
+    };
+    // A URL whose download failed was still listed, so don't fall back to a scan
+    let listed =
+        args.stdin || !args.files.is_empty() || args.spec.is_some() || !failed_downloads.is_empty();
+    let mut archives = if listed {
+        let format = args.format.as_ref();
+        let listed = if args.stdin {
+            archives_from_reader(std::io::stdin().lock(), &scan_opts, format)?
+        } else if args.spec.is_some() {
+            archives_from_paths(spec.paths(), &scan_opts, format)?
+        } else {
+            archives_from_paths(args.files.clone(), &scan_opts, format)?
+        };
+        print_stubs(&listed.stubs, args.min_file_size);
+        for (line, reason) in &listed.unrecognized {
+            println!(
+                "{} {} {}",
+                style("⚠").yellow(),
+                style(display_name(line)).yellow(),
+                style(format!("({}, skipped)", reason)).dim()
+            );
+        }
+        listed.archives
+    } else {
+        // Scan for archives
+        let spinner_style = ProgressStyle::default_spinner()
+            .template("{spinner:.cyan} {msg}")
+            .unwrap();
+
+        let scan_spinner = ProgressBar::new(1);
+        scan_spinner.set_style(spinner_style);
+        scan_spinner.set_message("Scanning for archives...");
+        scan_spinner.enable_steady_tick(std::time::Duration::from_millis(80));
+
+        let mut archives = Vec::new();
+        let mut stubs = Vec::new();
+        let mut unrecognized = Vec::new();
+        for dir in &scan_dirs {
+            let cache_path = if args.cache {
+                let dir = std::path::absolute(dir)?;
+                let path = cache::cache_file_for(&dir.canonicalize().unwrap_or(dir));
+                if path.is_none() {
+                    log::warn!("No cache directory available, scanning without --cache");
+                }
+                path
+            } else {
+                None
+            };
+            // A cached scan doesn't read the files again, so it can't list the rest
+            let scanned = match &cache_path {
+                Some(cache_path) if !args.show_unrecognized => {
+                    cache::cached_scan(dir, cache_path, &scan_opts)?
+                }
+                _ => scan_report(dir, &scan_opts)?,
+            };
+            archives.extend(scanned.archives);
+            stubs.extend(scanned.stubs);
+            if args.show_unrecognized {
+                unrecognized.extend(scanned.unrecognized);
+            }
+        }
+        // Overlapping roots (`dl` and `dl/*`) find the same archives twice
+        let mut seen = std::collections::HashSet::new();
+        archives.retain(|a: &Archive| seen.insert(a.path.clone()));
+        stubs.retain(|a: &Archive| seen.insert(a.path.clone()));
+
+        scan_spinner.finish_with_message(format!(
+            "{} Found {} archive(s)",
+            style("✓").green(),
+            style(archives.len()).yellow()
+        ));
+        print_stubs(&stubs, args.min_file_size);
+        if !unrecognized.is_empty() {
+            println!(
+                "{} {} file(s) look like archives but weren't recognized:",
+                style("⚠").yellow(),
+                style(unrecognized.len()).yellow()
+            );
+            for (path, reason) in &unrecognized {
+                println!(
+                    "  {} {} {}",
+                    style("•").dim(),
+                    display_path(Path::new(path)),
+                    style(format!("({})", reason)).dim()
+                );
+            }
+        }
+        archives
+    };
+
+    if let Some(window) = args.since {
+        archives = filter_since(archives, window, SystemTime::now());
+    }
+    for (dir, base_name) in disambiguate_mixed_types(&mut archives) {
+        println!(
+            "{} {} in {} is more than one archive type, extracting each into {}.<type>",
+            style("⚠").yellow(),
+            style(display_name(&base_name)).yellow(),
+            display_path(&dir),
+            display_name(&base_name)
+        );
+    }
+    if let Some(events) = &events {
+        events.emit(&Event::ScanComplete {
+            archives: archives.len(),
+        });
+    }
+
+    if args.list_archives {
+        return print_archive_list(&archives, args.json);
+    }
+    if args.probe {
+        return print_probes(&archives, &args.password, args.json);
+    }
+
+    if args.clean_empty {
+        let removed = clean_empty_dirs(&archives, &OutputLayout::from_args(&args)?)?;
+        for dir in &removed {
+            println!(
+                "{} Removed {}",
+                style("✓").green(),
+                style(display_path(dir)).cyan()
+            );
+        }
+        println!(
+            "\n{} empty director(ies) removed",
+            style(removed.len()).yellow()
+        );
+        return Ok(());
+    }
+
+    if args.extract_into.is_some() && archives.len() != 1 {
+        anyhow::bail!("--extract-to-temp takes exactly one archive");
+    }
+    if archives.is_empty() {
+        if !failed_downloads.is_empty() {
+            anyhow::bail!(
+                "{} download(s) failed, nothing to extract",
+                failed_downloads.len()
+            );
+        }
+        println!("\n{}", style("No archives found.").yellow().dim());
+        return Ok(());
+    }
+
+    // Select archives (a piped list is already the selection)
+    let indices = if let Some(input) = &args.select {
+        parse_select_option(input, archives.len())?
+    } else if args.all || listed {
+        (0..archives.len()).collect()
+    } else if let Some(path) = &args.load_selection {
+        let (indices, missing) = load_selection(path, &archives)?;
+        for gone in &missing {
+            println!(
+                "{} {} {}",
+                style("⚠").yellow(),
+                style(display_path(gone)).yellow(),
+                style("(saved in the selection, no longer found)").dim()
+            );
+        }
+        indices
+    } else {
+        select_archives(&archives, args.prompt_timeout, args.on_prompt_timeout)?
+    };
+    if let Some(path) = &args.save_selection {
+        save_selection(path, &archives, &indices)?;
+    }
+
+    if indices.is_empty() {
+        println!("\n{}", style("No archives selected.").yellow().dim());
+        return Ok(());
+    }
+
+    // Extract
+    let multi_progress = MultiProgress::new();
+    let mut opts = ExtractOptions {
+        events,
+        spec,
+        ..ExtractOptions::from_args(&args)?
+    };
+    if !downloads.is_empty() {
+        let cwd = std::env::current_dir().context("Cannot resolve current directory")?;
+        opts.extract_downloads_into(downloads.iter().map(|d| d.path.clone()).collect(), cwd);
+    }
+
+    // Tests write nothing, so only extraction needs distinct targets; extracting
+    // here shares the directory on purpose
+    let writes_output = !opts.test && !opts.quick_test && !opts.layout.here;
+    let collisions = if writes_output {
+        colliding_targets(&archives, &indices, |archive| {
+            archive.extract_dir(&opts.settings_for(archive).layout)
+        })?
+    } else {
+        Vec::new()
+    };
+    if !collisions.is_empty() {
+        for (target, members) in &collisions {
+            println!(
+                "{} {} would receive:",
+                style("✗").red(),
+                style(display_path(target)).red()
+            );
+            for &i in members {
+                println!(
+                    "  {} {}",
+                    style("┖─").dim(),
+                    display_path(&archives[i].path)
+                );
+            }
+        }
+        anyhow::bail!(
+            "{} extraction target(s) shared by several selected archives; \
+             select only one of each or use --group-by-type",
+            collisions.len()
+        );
+    }
+
+    let selected = indices.len();
+    let (indices, already_done) = if args.only_missing {
+        partition_missing(&archives, indices, &opts)
+    } else {
+        (indices, 0)
+    };
+    if args.only_missing {
+        println!(
+            "\n{} {} already done, {} to extract",
+            style("→").bold().cyan(),
+            style(already_done).yellow(),
+            style(indices.len()).bold().yellow()
+        );
+    }
+    let (indices, already_done) = match &args.success_log {
+        Some(log_path) => {
+            let (indices, logged) =
+                partition_logged(&archives, indices, &read_success_log(log_path)?);
+            if logged > 0 {
+                println!(
+                    "\n{} {} already in {}, skipped",
+                    style("→").bold().cyan(),
+                    style(logged).yellow(),
+                    display_path(log_path)
+                );
+            }
+            (indices, already_done + logged)
+        }
+        None => (indices, already_done),
+    };
+    let (indices, already_done, checkpoint) = match &args.checkpoint {
+        Some(path) => {
+            let checkpoint = checkpoint::Checkpoint::load(path)?;
+            let (indices, done) = checkpoint.partition(&archives, indices);
+            if done > 0 {
+                println!(
+                    "\n{} {} already done in {}, resuming with the rest",
+                    style("→").bold().cyan(),
+                    style(done).yellow(),
+                    display_path(path)
+                );
+            }
+            (indices, already_done + done, Some(checkpoint))
+        }
+        None => (indices, already_done, None),
+    };
+
+    println!(
+        "\n{} {} {}",
+        style("→").bold().cyan(),
+        style("Extracting").bold(),
+        style(indices.len()).bold().yellow()
+    );
+
+    // Throughput tracking for the batch ETA (skipped archives don't count)
+    let sizes: Vec<u64> = indices.iter().map(|&i| archives[i].total_size()).collect();
+    let throughput = std::sync::Mutex::new((sizes.iter().sum::<u64>(), 0, Duration::ZERO));
+    let finished = std::sync::atomic::AtomicUsize::new(0);
+
+    // --compact: the backends' own bars are drawn nowhere, one spinner line instead
+    COMPACT.store(args.compact, std::sync::atomic::Ordering::Relaxed);
+    let hidden = MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden());
+    let archive_progress = if args.compact {
+        &hidden
+    } else {
+        &multi_progress
+    };
+    let compact_bar = args.compact.then(|| {
+        let bar = multi_progress.add(ProgressBar::new_spinner());
+        bar.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.cyan} {msg}")
+                .unwrap(),
+        );
+        bar.enable_steady_tick(Duration::from_millis(120));
+        bar
+    });
+    let verb = if opts.quick_test {
+        "checking"
+    } else if opts.test {
+        "testing"
+    } else {
+        "extracting"
+    };
+
+    // Ask 7zz for its version up front, not while the first archive runs
+    let uses_7zz = |&i: &usize| {
+        matches!(
+            archives[i].archive_type,
+            ArchiveType::SevenZip | ArchiveType::Zip
+        )
+    };
+    if indices.iter().any(uses_7zz) {
+        seven_zip_profile();
+    }
+
+    // A budget too large for the clock can't run out
+    let deadline = args
+        .deadline
+        .and_then(|budget| Instant::now().checked_add(budget));
+    let process = |n: usize, i: usize| {
+        let archive = &archives[i];
+        let started = Instant::now();
+        if let Some(deadline) = deadline {
+            let (_, bytes_done, work_time) = *throughput.lock().unwrap();
+            let expected = expected_duration(sizes[n], bytes_done, work_time);
+            if !deadline_allows(deadline, started, expected) {
+                log::debug!(
+                    "Not starting {} (expected {:?})",
+                    archive.path.display(),
+                    expected
+                );
+                return Ok(Outcome::OutOfTime);
+            }
+        }
+        if let Some(events) = &opts.events {
+            events.emit(&Event::ArchiveStarted {
+                archive: &archive.path,
+                name: &archive.base_name,
+            });
+        }
+
+        let status =
+            |percent| compact_status(n + 1, indices.len(), verb, &archive.base_name, percent);
+        if let Some(bar) = &compact_bar {
+            bar.set_message(status(None));
+        }
+        let result = extract_archive(archive, archive_progress, &opts, &mut |percent| {
+            if let Some(bar) = &compact_bar {
+                bar.set_message(status(Some(percent)));
+            }
+            if let Some(events) = &opts.events {
+                events.emit(&Event::ArchiveProgress {
+                    archive: &archive.path,
+                    percent,
+                });
+            }
+            if let Some(progress_fd) = &opts.progress_fd {
+                progress_fd.report(i + 1, percent);
+            }
+        });
+        if let Some(events) = &opts.events {
+            events.emit(&finished_event(archive, &result));
+        }
+        if let (Some(checkpoint), Some(status)) = (&checkpoint, checkpoint::Status::of(&result)) {
+            if let Err(e) = checkpoint.record(archive, status) {
+                eprintln!("{} {:#}", style("⚠").yellow(), e);
+            }
+        }
+        let (bytes_total, bytes_done, work_time) = {
+            let mut throughput = throughput.lock().unwrap();
+            let (bytes_total, bytes_done, work_time) = &mut *throughput;
+            if matches!(result, Ok(Outcome::Skipped)) {
+                *bytes_total -= sizes[n];
+            } else {
+                *bytes_done += sizes[n];
+                *work_time += started.elapsed();
+            }
+            *throughput
+        };
+
+        match &result {
+            Ok(Outcome::Extracted(extract_dir)) => {
+                if let Some(map_log) = &args.map_log {
+                    if let Err(e) = append_map_log(map_log, archive, extract_dir) {
+                        eprintln!("{} {}", style("⚠").yellow(), e);
+                    }
+                }
+            }
+            Ok(Outcome::Tested)
+            | Ok(Outcome::QuickChecked)
+            | Ok(Outcome::Previewed(_))
+            | Ok(Outcome::Skipped)
+            | Ok(Outcome::Incomplete(_))
+            | Ok(Outcome::OutOfTime) => {}
+            Err(e) => {
+                eprintln!(
+                    "\n{} {}: {}",
+                    style("✗").red(),
+                    style(display_name(&archive.base_name)).red(),
+                    e
+                );
+                log_failed_archive(archive, e);
+            }
+        }
+
+        let left = indices.len() - finished.fetch_add(1, std::sync::atomic::Ordering::SeqCst) - 1;
+        if left > 0 && !(args.fail_fast && result.is_err()) {
+            if let Some(eta) = batch_eta(bytes_done, bytes_total, work_time) {
+                say!(
+                    "  {} {}",
+                    style("┖─").dim(),
+                    style(format!("{} left, batch ETA {}", left, HumanDuration(eta))).dim()
+                );
+            }
+        }
+        result
+    };
+    let mut stats = match args.jobs_per_disk {
+        Some(jobs) => {
+            let jobs = jobs.get();
+            let groups = group_by_device(indices.len(), |n| {
+                let archive = &archives[indices[n]];
+                let dir = archive
+                    .extract_dir(&opts.settings_for(archive).layout)
+                    .ok()?;
+                device_of(&dir)
+            });
+            log::debug!("{} device queue(s), {} job(s) each", groups.len(), jobs);
+            run_batch_by_device(&indices, &groups, jobs, args.fail_fast, process)
+        }
+        None => run_batch(&indices, args.fail_fast, process),
+    };
+    if let Some(bar) = compact_bar {
+        bar.finish_and_clear();
+    }
+    stats.skipped += already_done + stats.incomplete.len();
+    // Failed downloads count as failed archives of the batch
+    stats.failed += failed_downloads.len();
+    let selected = selected + failed_downloads.len();
+
+    for line in summary_lines(selected, &mut stats) {
+        println!("{}", line);
+    }
+    if args.notify {
+        let (title, body) = notification_text(selected, &stats);
+        send_notification(&title, &body);
+    }
+
+    if opts.extract_into.is_some() && stats.failed > 0 {
+        anyhow::bail!("Extraction failed");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_new_and_extract_dir() {
+        // Archive in subdirectory: extract_dir should be parent/base_name
+        let archive = Archive::new(PathBuf::from("a/b/c/archive.7z.001")).unwrap();
+        assert_eq!(archive.base_name, "archive");
+        assert_eq!(archive.archive_type, ArchiveType::SevenZip);
+        let extract_dir = archive.extract_dir(&OutputLayout::default()).unwrap();
+        assert_eq!(extract_dir, PathBuf::from("a/b/c/archive"));
+
+        // Tar.gz in root
+        let archive2 = Archive::new(PathBuf::from("foo.tar.gz")).unwrap();
+        assert_eq!(archive2.base_name, "foo");
+        assert_eq!(archive2.archive_type, ArchiveType::TarGz);
+        let extract_dir2 = archive2.extract_dir(&OutputLayout::default()).unwrap();
+        assert_eq!(extract_dir2, PathBuf::from("foo"));
+
+        // Unrecognized extensions return None
+        assert!(Archive::new(PathBuf::from("other.txt")).is_none());
+    }
+
+    #[test]
+    fn test_split_type_from_sibling_volumes() {
+        let temp = tempfile::tempdir().unwrap();
+        let first = temp.path().join("data.001");
+        // No usable magic: only the siblings' names tell the type
+        fs::write(&first, "not a header").unwrap();
+        fs::write(temp.path().join("data.7z.002"), "b").unwrap();
+        fs::write(temp.path().join("data.7z.003"), "c").unwrap();
+        fs::write(temp.path().join("other.zip.002"), "x").unwrap();
+
+        let archive = Archive::detect(first.clone(), &[]).unwrap();
+        assert_eq!(archive.archive_type, ArchiveType::SevenZip);
+        assert_eq!(archive.base_name, "data");
+        let volumes = archive.volumes();
+        assert_eq!(
+            volumes,
+            [
+                first.clone(),
+                temp.path().join("data.7z.002"),
+                temp.path().join("data.7z.003")
+            ]
+        );
+
+        let (links, linked) = VolumeLinks::for_mixed(&archive).unwrap().unwrap();
+        assert_eq!(linked.path.file_name().unwrap(), "data.7z.001");
+        assert_eq!(fs::read_to_string(&linked.path).unwrap(), "not a header");
+        let linked_volumes: Vec<_> = linked.volumes().iter().map(fs::read_to_string).collect();
+        assert_eq!(linked_volumes.len(), 3);
+        let dir = links.dir.clone();
+        drop(links);
+        assert!(!dir.exists());
+        assert!(first.exists());
+
+        // Consistently named sets are used as they are
+        let regular = Archive::detect(temp.path().join("other.zip.002"), &[]);
+        assert!(regular.is_none());
+        fs::rename(&first, temp.path().join("data.7z.001")).unwrap();
+        let archive = Archive::new(temp.path().join("data.7z.001")).unwrap();
+        assert!(VolumeLinks::for_mixed(&archive).unwrap().is_none());
+        assert!(sibling_split_type(&temp.path().join("lone.001")).is_none());
+    }
+
+    #[test]
+    fn test_scan_lists_unrecognized_archive_like_files() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in [
+            "set.7z.001",
+            "set.7z.002",
+            "lost.7z.003",
+            "show.part02.rar",
+            "show.part03.rar",
+            "single.rar",
+            "old.r00",
+            "backup.tar.xz",
+            "notes.txt",
+            "video.mkv",
+        ] {
+            fs::write(temp.path().join(name), "dummy").unwrap();
+        }
+
+        let Scanned {
+            archives,
+            unrecognized,
+            ..
+        } = scan_report(temp.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(archives.len(), 1);
+        let names: Vec<_> = unrecognized
+            .iter()
+            .map(|(path, _)| Path::new(path).file_name().unwrap().to_str().unwrap())
+            .collect();
+        // set.7z.002 belongs to a set that was found
+        assert_eq!(
+            names,
+            [
+                "backup.tar.xz",
+                "lost.7z.003",
+                "old.r00",
+                "show.part02.rar",
+                "show.part03.rar",
+                "single.rar"
+            ]
+        );
+        let reason = |name: &str| unrecognized_reason(name).map(|(reason, _)| reason);
+        assert_eq!(
+            reason("lost.7z.003").unwrap(),
+            "volume 3, its set's first volume is missing"
+        );
+        assert!(reason("single.rar").unwrap().contains("--format rar"));
+        assert!(reason("backup.tar.xz").unwrap().contains("--custom-type"));
+        assert!(reason("dump.002").unwrap().contains(".001 is missing"));
+        assert_eq!(reason("notes.txt"), None);
+        assert_eq!(reason(".zip"), None);
+    }
+
+    #[test]
+    fn test_scan_archives_root_only() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp.path().join("movie/extras")).unwrap();
+        fs::write(temp.path().join("top.7z.001"), "dummy").unwrap();
+        fs::write(temp.path().join("movie/inner.part01.rar"), "dummy").unwrap();
+        fs::write(temp.path().join("movie/extras/deep.tgz"), "dummy").unwrap();
+
+        let all = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(all.len(), 3);
+
+        let opts = ScanOptions {
+            root_only: true,
+            ..Default::default()
+        };
+        let root = scan_archives(temp.path(), &opts).unwrap();
+        let names: Vec<_> = root.iter().map(|a| a.base_name.as_str()).collect();
+        assert_eq!(names, ["top"]);
+    }
+
+    #[test]
+    fn test_scan_archives_finds_subfolder_archives() {
+        let temp = tempfile::tempdir().unwrap();
+        let temp_path = temp.path();
+
+        // Create temp/subdir/file.7z.001
+        let subdir = temp_path.join("subdir");
+        fs::create_dir_all(&subdir).unwrap();
+        let archive_path = subdir.join("file.7z.001");
+        fs::write(&archive_path, "dummy").unwrap();
+
+        let archives = scan_archives(temp_path, &ScanOptions::default()).unwrap();
+        assert_eq!(archives.len(), 1);
+        assert_eq!(archives[0].base_name, "file");
+        assert!(archives[0].path.ends_with("file.7z.001"));
+        assert!(archives[0].path.parent().unwrap().ends_with("subdir"));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(
+            parse_duration("24h").unwrap(),
+            Duration::from_secs(24 * 3600)
+        );
+        assert_eq!(
+            parse_duration("7d").unwrap(),
+            Duration::from_secs(7 * 86400)
+        );
+        assert_eq!(
+            parse_duration("2w").unwrap(),
+            Duration::from_secs(14 * 86400)
+        );
+        assert!(parse_duration("24").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn test_stub_files_skipped_by_default() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("stub.7z.001"), "").unwrap();
+        fs::write(temp.path().join("real.7z.001"), [0u8; 64]).unwrap();
+        // Only the first volume counts
+        fs::write(temp.path().join("real.7z.002"), "").unwrap();
+        let min_file_size = Args::parse_from(["un7z"]).min_file_size;
+        assert_eq!(min_file_size, 32);
+        let opts = ScanOptions {
+            min_file_size,
+            ..Default::default()
+        };
+        let scanned = scan_report(temp.path(), &opts).unwrap();
+        assert_eq!(scanned.archives.len(), 1);
+        assert_eq!(scanned.archives[0].base_name, "real");
+        assert_eq!(scanned.stubs.len(), 1);
+        assert_eq!(scanned.stubs[0].base_name, "stub");
+        // Every scan leaves them out, a cached one too
+        assert_eq!(scan_archives(temp.path(), &opts).unwrap().len(), 1);
+        let cache_path = temp.path().join("cache/scan.json");
+        let cached = cache::cached_scan(temp.path(), &cache_path, &opts).unwrap();
+        assert_eq!((cached.archives.len(), cached.stubs.len()), (1, 1));
+
+        let min_file_size = Args::parse_from(["un7z", "--min-file-size", "0"]).min_file_size;
+        let opts = ScanOptions {
+            min_file_size,
+            ..Default::default()
+        };
+        assert_eq!(scan_archives(temp.path(), &opts).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_filter_since_uses_primary_volume_mtime() {
+        let temp = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        let ages = [
+            ("fresh.7z.001", 1),
+            ("day.7z.001", 20),
+            ("old.7z.001", 24 * 10),
+        ];
+        for (name, age_hours) in ages {
+            let path = temp.path().join(name);
+            fs::write(&path, "dummy").unwrap();
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(now - Duration::from_secs(age_hours * 3600))
+                .unwrap();
+        }
+
+        let archives = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        let recent = filter_since(archives, parse_duration("24h").unwrap(), now);
+        let names: Vec<&str> = recent.iter().map(|a| a.base_name.as_str()).collect();
+        assert_eq!(names, vec!["day", "fresh"]);
+    }
+
+    #[test]
+    fn test_marker_roundtrip_and_skip_decision() {
+        let temp = tempfile::tempdir().unwrap();
+        let archive_path = temp.path().join("data.7z.001");
+        fs::write(&archive_path, "dummy").unwrap();
+        let archive = Archive::new(archive_path.clone()).unwrap();
+        let extract_dir = archive.extract_dir(&OutputLayout::default()).unwrap();
+        assert_eq!(
+            existing_output(&archive, &extract_dir, true),
+            ExistingOutput::Missing
+        );
+
+        // Files without a marker: the heuristic skips, marker mode re-extracts
+        fs::create_dir_all(&extract_dir).unwrap();
+        fs::write(extract_dir.join("file.txt"), "content").unwrap();
+        assert_eq!(
+            existing_output(&archive, &extract_dir, false),
+            ExistingOutput::Complete
+        );
+        assert_eq!(
+            existing_output(&archive, &extract_dir, true),
+            ExistingOutput::Incomplete
+        );
+
+        let marker = Marker::for_archive(&archive, &extract_dir).unwrap();
+        assert_eq!(marker.files, 1);
+        marker.write(&extract_dir).unwrap();
+        assert_eq!(Marker::read(&extract_dir), Some(marker));
+        assert_eq!(
+            existing_output(&archive, &extract_dir, true),
+            ExistingOutput::Complete
+        );
+
+        // A changed archive invalidates the marker
+        fs::write(&archive_path, "dummy but longer").unwrap();
+        assert_eq!(
+            existing_output(&archive, &extract_dir, true),
+            ExistingOutput::Incomplete
+        );
+    }
+
+    #[test]
+    fn test_append_map_log_writes_tab_separated_line() {
+        let temp = tempfile::tempdir().unwrap();
+        let log_path = temp.path().join("map.log");
+        let archive = Archive::new(temp.path().join("movies/film.part01.rar")).unwrap();
+        let extract_dir = archive.extract_dir(&OutputLayout::default()).unwrap();
+
+        append_map_log(&log_path, &archive, &extract_dir).unwrap();
+        append_map_log(&log_path, &archive, &extract_dir).unwrap();
+
+        let content = fs::read_to_string(&log_path).unwrap();
+        let expected = format!(
+            "{}\t{}\n",
+            temp.path().join("movies/film.part01.rar").display(),
+            temp.path().join("movies/film").display()
+        );
+        assert_eq!(content, expected.repeat(2));
+    }
+
+    #[test]
+    fn test_success_log_appends_and_skips_logged() {
+        let temp = tempfile::tempdir().unwrap();
+        let log_path = temp.path().join("state/success.log");
+        fs::create_dir(temp.path().join("state")).unwrap();
+        fs::write(temp.path().join("data.tar.gz"), "").unwrap();
+        fs::write(temp.path().join("other.tgz"), "").unwrap();
+        let archives = vec![
+            Archive::new(temp.path().join("data.tar.gz")).unwrap(),
+            Archive::new(temp.path().join("other.tgz")).unwrap(),
+        ];
+
+        // No log yet: nothing is skipped
+        let logged = read_success_log(&log_path).unwrap();
+        assert!(logged.is_empty());
+        assert_eq!(
+            partition_logged(&archives, vec![0, 1], &logged),
+            (vec![0, 1], 0)
+        );
+
+        append_success_log(&log_path, &archives[0]).unwrap();
+        let content = fs::read_to_string(&log_path).unwrap();
+        let canonical = temp.path().canonicalize().unwrap().join("data.tar.gz");
+        assert_eq!(content, format!("{}\n", canonical.display()));
+
+        // Logged archives are skipped even with no output left on disk
+        let logged = read_success_log(&log_path).unwrap();
+        assert!(!temp.path().join("data").exists());
+        assert_eq!(
+            partition_logged(&archives, vec![0, 1], &logged),
+            (vec![1], 1)
+        );
+    }
+
+    #[test]
+    fn test_extract_appends_success_log() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("file.txt"), "hello").unwrap();
+        let tarball = temp.path().join("data.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(temp.path())
+            .arg("file.txt")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let log_path = temp.path().join("success.log");
+        let args = Args::parse_from([
+            OsString::from("un7z"),
+            OsString::from("--success-log"),
+            log_path.clone().into(),
+        ]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        let archive = Archive::new(tarball).unwrap();
+        let outcome = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap();
+        assert!(matches!(outcome, Outcome::Extracted(_)));
+        assert!(read_success_log(&log_path)
+            .unwrap()
+            .contains(&success_log_key(&archive)));
+    }
+
+    #[test]
+    fn test_extract_dir_grouped_by_type() {
+        let names = [
+            ("d/a.7z.001", "7z"),
+            ("d/b.zip.001", "zip"),
+            ("d/c.part01.rar", "rar"),
+            ("d/e.tgz", "tar.gz"),
+        ];
+        let grouped = OutputLayout {
+            root: None,
+            group_by_type: true,
+            scan_root: None,
+            here: false,
+        };
+        let grouped_out = OutputLayout {
+            root: Some(PathBuf::from("/out")),
+            group_by_type: true,
+            scan_root: None,
+            here: false,
+        };
+        let out_only = OutputLayout {
+            root: Some(PathBuf::from("/out")),
+            group_by_type: false,
+            scan_root: None,
+            here: false,
+        };
+        for (name, type_dir) in names {
+            let archive = Archive::new(PathBuf::from(name)).unwrap();
+            let base = &archive.base_name;
+            assert_eq!(
+                archive.extract_dir(&grouped).unwrap(),
+                PathBuf::from("d").join(type_dir).join(base)
+            );
+            assert_eq!(
+                archive.extract_dir(&grouped_out).unwrap(),
+                PathBuf::from("/out").join(type_dir).join(base)
+            );
+            assert_eq!(
+                archive.extract_dir(&out_only).unwrap(),
+                PathBuf::from("/out").join(base)
+            );
+        }
+    }
+
+    #[test]
+    fn test_extract_dir_preserves_tree() {
+        let layout = OutputLayout {
+            root: Some(PathBuf::from("/out")),
+            group_by_type: false,
+            scan_root: Some(PathBuf::from("/data")),
+            here: false,
+        };
+        let cases = [
+            ("/data/a/b/x.7z.001", "/out/a/b/x"),
+            ("/data/a/y.part01.rar", "/out/a/y"),
+            ("/data/top.tgz", "/out/top"),
+            // Outside the scan root: straight under the output root
+            ("/elsewhere/z.7z.001", "/out/z"),
+        ];
+        for (path, expected) in cases {
+            let archive = Archive::new(PathBuf::from(path)).unwrap();
+            assert_eq!(
+                archive.extract_dir(&layout).unwrap(),
+                PathBuf::from(expected)
+            );
+        }
+
+        let grouped = OutputLayout {
+            group_by_type: true,
+            ..layout.clone()
+        };
+        let archive = Archive::new(PathBuf::from("/data/a/b/x.7z.001")).unwrap();
+        assert_eq!(
+            archive.extract_dir(&grouped).unwrap(),
+            PathBuf::from("/out/7z/a/b/x")
+        );
+
+        // Without a root the scan root changes nothing
+        let beside = OutputLayout {
+            root: None,
+            ..layout
+        };
+        assert_eq!(
+            archive.extract_dir(&beside).unwrap(),
+            PathBuf::from("/data/a/b/x")
+        );
+
+        // Relative --dir and archive paths resolve against the current directory
+        let args = Args::parse_from(["un7z", "-d", "data", "-o", "/out", "--preserve-tree"]);
+        let layout = OutputLayout::from_args(&args).unwrap();
+        let archive = Archive::new(PathBuf::from("data/a/b/x.7z.001")).unwrap();
+        assert_eq!(
+            archive.extract_dir(&layout).unwrap(),
+            PathBuf::from("/out/a/b/x")
+        );
+        assert!(Args::try_parse_from(["un7z", "--preserve-tree"]).is_err());
+    }
+
+    #[test]
+    fn test_output_root_from_cwd_or_archive_dir() {
+        let archive = Archive::new(PathBuf::from("/data/in/movie.7z.001")).unwrap();
+
+        let beside = OutputLayout::from_args(&Args::parse_from(["un7z"])).unwrap();
+        assert_eq!(beside.root, None);
+        assert_eq!(
+            archive.extract_dir(&beside).unwrap(),
+            PathBuf::from("/data/in/movie")
+        );
+
+        // --cwd pins an absolute root, so the PTY's chdir to the archive doesn't move it
+        let cwd = std::env::current_dir().unwrap();
+        let here = OutputLayout::from_args(&Args::parse_from(["un7z", "--cwd"])).unwrap();
+        assert_eq!(here.root.as_deref(), Some(cwd.as_path()));
+        let dir = archive.extract_dir(&here).unwrap();
+        assert!(dir.is_absolute());
+        assert_eq!(dir, cwd.join("movie"));
+
+        assert!(Args::try_parse_from(["un7z", "--cwd", "-o", "/out"]).is_err());
+    }
+
+    #[test]
+    fn test_mixed_types_with_same_base_get_distinct_dirs() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir(temp.path().join("other")).unwrap();
+        for name in [
+            "data.7z.001",
+            "data.part01.rar",
+            "solo.7z.001",
+            "other/data.tgz",
+        ] {
+            fs::write(temp.path().join(name), "x").unwrap();
+        }
+        let mut archives = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        let root = temp.path().canonicalize().unwrap();
+
+        let mixed = disambiguate_mixed_types(&mut archives);
+        assert_eq!(mixed, vec![(root.clone(), "data".to_string())]);
+
+        let layout = OutputLayout::default();
+        let dirs: Vec<PathBuf> = archives
+            .iter()
+            .map(|a| a.extract_dir(&layout).unwrap())
+            .collect();
+        // A different directory doesn't count as a conflict
+        assert!(dirs.contains(&root.join("other/data")));
+        assert!(dirs.contains(&root.join("data.7z")));
+        assert!(dirs.contains(&root.join("data.rar")));
+        assert!(dirs.contains(&root.join("solo")));
+        let indices: Vec<usize> = (0..archives.len()).collect();
+        assert!(
+            colliding_targets(&archives, &indices, |a| a.extract_dir(&layout))
+                .unwrap()
+                .is_empty()
+        );
+
+        // Volumes are still found by the real base name
+        let rar = archives
+            .iter()
+            .find(|a| a.archive_type == ArchiveType::Rar)
+            .unwrap();
+        assert_eq!(rar.volumes(), vec![rar.path.clone()]);
+    }
+
+    #[test]
+    fn test_download_in_progress_suffixes() {
+        let temp = tempfile::tempdir().unwrap();
+        let suffixes: Vec<String> = DEFAULT_IN_PROGRESS_SUFFIXES.map(String::from).to_vec();
+        for name in ["data.7z.001", "data.7z.002", "other.7z.001"] {
+            fs::write(temp.path().join(name), "x").unwrap();
+        }
+        let data = Archive::new(temp.path().join("data.7z.001")).unwrap();
+        let other = Archive::new(temp.path().join("other.7z.001")).unwrap();
+        assert_eq!(download_in_progress(&data, &suffixes), None);
+
+        // A later volume still being written by the browser
+        fs::write(temp.path().join("data.7z.003.crdownload"), "x").unwrap();
+        assert!(download_in_progress(&data, &suffixes).is_some());
+        assert_eq!(download_in_progress(&other, &suffixes), None);
+
+        // A download manager control file next to a finished-looking name
+        fs::write(temp.path().join("other.7z.001.aria2"), "x").unwrap();
+        assert!(download_in_progress(&other, &suffixes).is_some());
+        assert_eq!(
+            download_in_progress(&other, &[".crdownload".to_string()]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_download_in_progress_volume_sizes() {
+        let temp = tempfile::tempdir().unwrap();
+        let write = |name: &str, len: usize| {
+            fs::write(temp.path().join(name), vec![0u8; len]).unwrap();
+        };
+        // Equal volumes with a shorter last one: complete
+        write("full.part01.rar", 100);
+        write("full.part02.rar", 100);
+        write("full.part03.rar", 40);
+        // A middle volume shorter than the first: still downloading
+        write("short.7z.001", 100);
+        write("short.7z.002", 60);
+        write("short.7z.003", 40);
+
+        let full = Archive::new(temp.path().join("full.part01.rar")).unwrap();
+        assert_eq!(full.volumes().len(), 3);
+        assert_eq!(download_in_progress(&full, &[]), None);
+
+        let short = Archive::new(temp.path().join("short.7z.001")).unwrap();
+        assert_eq!(short.volumes().len(), 3);
+        assert!(download_in_progress(&short, &[]).is_some());
+    }
+
+    #[test]
+    fn test_custom_type_detection_and_template() {
+        let lzh = CustomType::parse(".lzh = lha xw={outdir} {archive}").unwrap();
+        assert_eq!(lzh.extension, "lzh");
+        assert!(CustomType::parse("lzh").is_err());
+        assert!(CustomType::parse("=cmd").is_err());
+
+        let custom_types = vec![lzh.clone()];
+        let archive = Archive::detect(PathBuf::from("/dl/old files.lzh"), &custom_types).unwrap();
+        assert_eq!(archive.base_name, "old files");
+        assert_eq!(archive.archive_type, ArchiveType::Custom(lzh));
+        assert_eq!(archive.archive_type.as_str(), "lzh");
+
+        // Built-in types still win, unknown extensions are still ignored
+        let builtin = Archive::detect(PathBuf::from("/dl/a.7z.001"), &custom_types).unwrap();
+        assert_eq!(builtin.archive_type, ArchiveType::SevenZip);
+        assert!(Archive::detect(PathBuf::from("/dl/a.arj"), &custom_types).is_none());
+        assert!(Archive::detect(PathBuf::from("/dl/.lzh"), &custom_types).is_none());
+
+        let cmd = archive
+            .extract_command(false, &None, Path::new("/out/old files"))
+            .unwrap();
+        assert_eq!(cmd.get_program(), "lha");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["xw=/out/old files", "/dl/old files.lzh"]);
+        assert!(archive
+            .extract_command(true, &None, Path::new("/out"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_tar_progress_percent() {
+        assert_eq!(tar_progress_percent(0, 8), 0);
+        assert_eq!(tar_progress_percent(2, 8), 25);
+        assert_eq!(tar_progress_percent(7, 8), 87);
+        assert_eq!(tar_progress_percent(8, 8), 100);
+        // Never past 100 even if tar reports more lines than the listing had
+        assert_eq!(tar_progress_percent(9, 8), 100);
+        assert_eq!(tar_progress_percent(0, 0), 100);
+    }
+
+    #[test]
+    fn test_archives_from_reader() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in ["b.7z.001", "b.7z.002", "a.tgz", "notes.txt"] {
+            fs::write(temp.path().join(name), "x").unwrap();
+        }
+        let input = [
+            "b.7z.001",
+            "b.7z.002",
+            "a.tgz",
+            "notes.txt",
+            "missing.7z.001",
+        ]
+        .map(|name| temp.path().join(name).display().to_string())
+        .join("\n\n");
+
+        let scan_opts = ScanOptions::default();
+        let Scanned {
+            archives,
+            unrecognized: rejected,
+            ..
+        } = archives_from_reader(input.as_bytes(), &scan_opts, None).unwrap();
+        let names: Vec<&str> = archives.iter().map(|a| a.base_name.as_str()).collect();
+        assert_eq!(names, vec!["b", "a"]);
+        assert_eq!(rejected.len(), 3);
+        assert!(rejected[0].0.ends_with("b.7z.002"));
+        assert!(rejected[1].0.ends_with("notes.txt"));
+        assert!(rejected[2].0.ends_with("missing.7z.001"));
+        assert_eq!(
+            rejected[0].1,
+            "volume 2 of a set, pass the first volume (b) instead"
+        );
+        assert_eq!(rejected[2].1, "no such file");
+    }
+
+    #[test]
+    fn test_listed_directory_is_scanned() {
+        let temp = tempfile::tempdir().unwrap();
+        let downloads = temp.path().join("downloads");
+        fs::create_dir_all(downloads.join("nested")).unwrap();
+        fs::write(downloads.join("a.tgz"), [0; 64]).unwrap();
+        fs::write(downloads.join("nested/b.7z.001"), [0; 64]).unwrap();
+        fs::write(downloads.join("stub.7z.001"), "").unwrap();
+        fs::write(downloads.join("notes.txt"), "").unwrap();
+        // Named like an archive, but a directory (say, already extracted into)
+        let empty = temp.path().join("movie.7z.001");
+        fs::create_dir(&empty).unwrap();
+
+        let listed = [downloads.clone(), empty.clone(), downloads.join("a.tgz")];
+        // Scanned with the same options as --dir, so stubs are left out
+        let scan_opts = ScanOptions {
+            min_file_size: 32,
+            ..Default::default()
+        };
+        let scanned = archives_from_paths(listed, &scan_opts, None).unwrap();
+        let mut names: Vec<_> = scanned
+            .archives
+            .iter()
+            .map(|a| a.base_name.as_str())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(scanned.stubs.len(), 1);
+        assert_eq!(scanned.stubs[0].base_name, "stub");
+        assert_eq!(
+            scanned.unrecognized,
+            vec![(
+                empty.display().to_string(),
+                "a directory with no archives in it".to_string()
+            )]
+        );
+
+        // --archive-root-only keeps a listed directory's subdirectories out too
+        let root_only = ScanOptions {
+            root_only: true,
+            ..Default::default()
+        };
+        let scanned = archives_from_paths([downloads.clone()], &root_only, None).unwrap();
+        let mut names: Vec<_> = scanned
+            .archives
+            .iter()
+            .map(|a| a.base_name.as_str())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a", "stub"]);
+    }
+
+    #[test]
+    fn test_failed_downloads_are_left_out() {
+        let files = [
+            "https://example.com/a.tgz",
+            "/dl/local.7z.001",
+            "https://example.com/missing.tgz",
+            "https://example.com/c.zip",
+        ]
+        .map(PathBuf::from);
+        let fetched = vec![
+            Some(PathBuf::from("/tmp/un7z-download-1/a.tgz")),
+            None,
+            Some(PathBuf::from("/tmp/un7z-download-2/c.zip")),
+        ];
+        assert_eq!(
+            replace_urls(&files, fetched),
+            [
+                "/tmp/un7z-download-1/a.tgz",
+                "/dl/local.7z.001",
+                "/tmp/un7z-download-2/c.zip"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_forced_format_applies_to_listed_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let file = temp.path().join("data.bin");
+        fs::write(&file, "x").unwrap();
+
+        let scan_opts = ScanOptions::default();
+        let Scanned {
+            archives,
+            unrecognized: rejected,
+            ..
+        } = archives_from_paths([file.clone()], &scan_opts, None).unwrap();
+        assert!(archives.is_empty());
+        assert_eq!(rejected.len(), 1);
+        let (listed, reason) = &rejected[0];
+        assert_eq!(listed, &file.display().to_string());
+        assert!(
+            reason.starts_with("unrecognized archive type"),
+            "{}",
+            reason
+        );
+        assert!(reason.contains("--format"), "{}", reason);
+
+        let format = parse_format("7z").unwrap();
+        let Scanned {
+            archives,
+            unrecognized: rejected,
+            ..
+        } = archives_from_paths([file.clone()], &scan_opts, Some(&format)).unwrap();
+        assert!(rejected.is_empty());
+        let archive = &archives[0];
+        assert_eq!(archive.archive_type, ArchiveType::SevenZip);
+        assert_eq!(archive.base_name, "data");
+        assert_eq!(archive.volumes(), vec![file.clone()]);
+        let out = archive.extract_dir(&OutputLayout::default()).unwrap();
+        assert_eq!(out, temp.path().join("data"));
+        let cmd = archive.extract_command(false, &None, &out).unwrap();
+        assert_eq!(cmd.get_program(), "7zz");
+        assert!(cmd.get_args().any(|a| a == file.as_os_str()));
+
+        // Directory scans still go by name
+        assert!(scan_archives(temp.path(), &ScanOptions::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_clean_empty_removes_only_empty_dirs() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in [
+            "empty.7z.001",
+            "nested.7z.001",
+            "full.part01.rar",
+            "absent.tgz",
+        ] {
+            fs::write(temp.path().join(name), "x").unwrap();
+        }
+        fs::create_dir(temp.path().join("empty")).unwrap();
+        fs::create_dir_all(temp.path().join("nested/sub")).unwrap();
+        fs::write(temp.path().join("nested/sub/stub"), "").unwrap();
+        fs::create_dir(temp.path().join("full")).unwrap();
+        fs::write(temp.path().join("full/movie.mkv"), "data").unwrap();
+        fs::create_dir(temp.path().join("unrelated")).unwrap();
+
+        let archives = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        let removed = clean_empty_dirs(&archives, &OutputLayout::default()).unwrap();
+
+        let root = temp.path().canonicalize().unwrap();
+        assert_eq!(removed, vec![root.join("empty"), root.join("nested")]);
+        assert!(!root.join("empty").exists());
+        assert!(!root.join("nested").exists());
+        assert!(root.join("full/movie.mkv").exists());
+        assert!(root.join("unrelated").exists());
+    }
+
+    #[test]
+    fn test_rar_listing_encrypted() {
+        let plain = "\
+Archive: plain.rar
+Details: RAR 5
+
+ Attributes      Size     Date    Time   Name
+----------- ---------  ---------- -----  ----
+    ..A....         5  2024-01-01 12:00  notes.txt
+----------- ---------  ---------- -----  ----
+                    5                    1
+";
+        assert!(!rar_listing_encrypted(plain));
+        assert!(rar_listing_encrypted(
+            &plain.replace("    ..A....", "*   ..A....")
+        ));
+        assert!(rar_listing_encrypted(
+            "Archive: x.rar\nDetails: RAR 5, encrypted headers\n"
+        ));
+        assert!(rar_listing_encrypted(
+            "The specified password is incorrect.\n"
+        ));
+    }
+
+    #[test]
+    fn test_reextract_confirmation_decision() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("movie");
+        fs::create_dir(&dir).unwrap();
+
+        // Nothing to lose in an empty directory
+        assert!(!needs_reextract_confirmation(&dir, false, true));
+
+        fs::write(dir.join("part.mkv"), "").unwrap();
+        assert!(needs_reextract_confirmation(&dir, false, true));
+        assert!(!needs_reextract_confirmation(&dir, true, true));
+        assert!(!needs_reextract_confirmation(&dir, false, false));
+
+        assert!(parse_yes(Some("y\n")));
+        assert!(parse_yes(Some(" YES ")));
+        assert!(!parse_yes(Some("")));
+        assert!(!parse_yes(Some("n")));
+        assert!(!parse_yes(None));
+    }
+
+    #[test]
+    fn test_bare_001_detected_by_header() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut header = b"7z\xBC\xAF\x27\x1C".to_vec();
+        header.extend_from_slice(b"rest of volume");
+        fs::write(temp.path().join("backup.001"), &header).unwrap();
+        fs::write(temp.path().join("backup.002"), "more").unwrap();
+        fs::write(temp.path().join("movie.mkv.001"), b"\x1A\x45\xDF\xA3").unwrap();
+        fs::write(temp.path().join("movie.mkv.002"), "frames").unwrap();
+
+        let archives = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(archives.len(), 1);
+        let archive = &archives[0];
+        assert_eq!(archive.archive_type, ArchiveType::SevenZip);
+        assert_eq!(archive.base_name, "backup");
+        assert_eq!(archive.volumes().len(), 2);
+
+        // A header split across a tiny first volume is still found
+        fs::write(temp.path().join("small.001"), "Ra").unwrap();
+        fs::write(temp.path().join("small.002"), b"r!\x1A\x07\x01\x00").unwrap();
+        let header = read_split_header(&temp.path().join("small.001"), 8);
+        assert_eq!(sniff_type(&header), Some(ArchiveType::Rar));
+        assert!(Archive::detect(temp.path().join("small.001"), &[]).is_none());
+    }
+
+    #[test]
+    fn test_join_split() {
+        let temp = tempfile::tempdir().unwrap();
+        for (n, content) in ["one,", "two,", "three"].iter().enumerate() {
+            fs::write(temp.path().join(format!("movie.mkv.{:03}", n + 1)), content).unwrap();
+        }
+
+        let splits = joinable_splits(&[], &[temp.path().to_path_buf()], &[]);
+        assert_eq!(splits, vec![temp.path().join("movie.mkv.001")]);
+
+        let target = join_split(&splits[0], false).unwrap();
+        assert_eq!(target, temp.path().join("movie.mkv"));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "one,two,three");
+
+        // Never overwrites an existing file
+        assert!(join_split(&splits[0], false).is_err());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "one,two,three");
+    }
+
+    #[test]
+    fn test_concat_three_parts_and_delete() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut archive_header = b"7z\xBC\xAF\x27\x1C".to_vec();
+        archive_header.push(0);
+        let parts: [&[u8]; 3] = [&archive_header, b"\x00\xFFmiddle", b"end"];
+        for (n, content) in parts.iter().enumerate() {
+            fs::write(temp.path().join(format!("disk.img.{:03}", n + 1)), content).unwrap();
+        }
+
+        // Extractable as-is, so only --concat picks it up
+        let dirs = [temp.path().to_path_buf()];
+        assert!(joinable_splits(&[], &dirs, &[]).is_empty());
+        let splits = bare_splits(&[], &dirs);
+        assert_eq!(splits, vec![temp.path().join("disk.img.001")]);
+
+        let target = join_split(&splits[0], true).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), parts.concat());
+        assert!(!temp.path().join("disk.img.001").exists());
+        assert!(!temp.path().join("disk.img.003").exists());
+    }
+
+    #[test]
+    fn test_join_fails_on_gap() {
+        let temp = tempfile::tempdir().unwrap();
+        for n in [1, 2, 4] {
+            fs::write(temp.path().join(format!("movie.mkv.{:03}", n)), "x").unwrap();
+        }
+        let err = join_split(&temp.path().join("movie.mkv.001"), true).unwrap_err();
+        assert!(err.to_string().contains("movie.mkv.003"), "{}", err);
+        assert!(!temp.path().join("movie.mkv").exists());
+        assert!(temp.path().join("movie.mkv.001").exists());
+    }
+
+    #[test]
+    fn test_tar_wrapper_decision() {
+        let listing = |entries: &[&str]| entries.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+
+        let wrapped = listing(&[
+            "project/",
+            "project/src/",
+            "project/src/main.rs",
+            "project/README",
+        ]);
+        assert!(entries_have_wrapper(&wrapped, "project"));
+        assert!(entries_have_wrapper(
+            &listing(&["./project/a", "./project/b"]),
+            "project"
+        ));
+
+        // Loose files, a differently named folder, or a mix all need our own directory
+        assert!(!entries_have_wrapper(
+            &listing(&["a.txt", "b.txt"]),
+            "project"
+        ));
+        assert!(!entries_have_wrapper(
+            &listing(&["project-1.0/a"]),
+            "project"
+        ));
+        assert!(!entries_have_wrapper(
+            &listing(&["project/a", "stray.txt"]),
+            "project"
+        ));
+        assert!(!entries_have_wrapper(&listing(&["project"]), "project"));
+        assert!(!entries_have_wrapper(&[], "project"));
+    }
+
+    #[test]
+    fn test_lone_tar_member_decides_placement() {
+        let listing = |entries: &[&str]| entries.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(lone_tar_member(&listing(&["data.csv"])), Some("data.csv"));
+        assert_eq!(
+            lone_tar_member(&listing(&["./data.csv", ""])),
+            Some("data.csv")
+        );
+
+        // Several entries, a directory, or a nested file keep the folder
+        assert_eq!(lone_tar_member(&listing(&["a.csv", "b.csv"])), None);
+        assert_eq!(lone_tar_member(&listing(&["data/"])), None);
+        assert_eq!(lone_tar_member(&listing(&["data/a.csv"])), None);
+        assert_eq!(lone_tar_member(&[]), None);
+
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("data.csv"), "1,2").unwrap();
+        let tarball = temp.path().join("data.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(temp.path())
+            .arg("data.csv")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::remove_file(temp.path().join("data.csv")).unwrap();
+
+        let archive = Archive::new(tarball).unwrap();
+        let opts = ExtractOptions::from_args(&Args::parse_from(["un7z", "--no-subdir"])).unwrap();
+        let outcome = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap();
+        let placed = temp.path().join("data.csv");
+        assert!(matches!(outcome, Outcome::Extracted(ref path) if *path == placed));
+        assert_eq!(fs::read_to_string(&placed).unwrap(), "1,2");
+        assert!(!temp.path().join("data").exists());
+    }
+
+    #[test]
+    fn test_no_cleanup_keeps_partial_output() {
+        let temp = tempfile::tempdir().unwrap();
+        let tarball = temp.path().join("data.tar.gz");
+        fs::write(&tarball, "not gzip").unwrap();
+        let archive = Archive::new(tarball).unwrap();
+        let extract_dir = temp.path().join("data");
+
+        // By default a failed extraction leaves nothing behind
+        let opts = ExtractOptions::from_args(&Args::parse_from(["un7z", "-y"])).unwrap();
+        fs::create_dir(&extract_dir).unwrap();
+        fs::write(extract_dir.join("partial.bin"), "").unwrap();
+        assert!(extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).is_err());
+        assert!(!extract_dir.exists());
+
+        // With --no-cleanup the incomplete directory survives both the
+        // re-extract and the failure
+        let args = Args::parse_from(["un7z", "-y", "--no-cleanup"]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        fs::create_dir(&extract_dir).unwrap();
+        fs::write(extract_dir.join("partial.bin"), "").unwrap();
+        assert_eq!(
+            existing_output(&archive, &extract_dir, false),
+            ExistingOutput::Incomplete
+        );
+        assert!(extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).is_err());
+        assert!(extract_dir.join("partial.bin").exists());
+
+        // Also when the failed extraction created the directory itself
+        fs::remove_dir_all(&extract_dir).unwrap();
+        assert!(extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).is_err());
+        assert!(extract_dir.exists());
+    }
+
+    #[test]
+    fn test_pipe_tar() {
+        let cmd = pipe_tar_command(Path::new("/tmp/un7z-1"));
+        assert_eq!(cmd.get_program(), "tar");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["-cf", "-", "-C", "/tmp/un7z-1", "."]);
+        let argv = extract_to_temp_args(
+            ["--pipe-tar", "x.7z.001"].map(OsString::from),
+            Path::new("/tmp/un7z-1"),
+        );
+        assert_eq!(argv, ["x.7z.001", "--extract-into", "/tmp/un7z-1"]);
+
+        // The stream carries what was extracted, and the directory is gone after
+        let temp = tempfile::tempdir().unwrap();
+        let stream = temp.path().join("out.tar");
+        let target = create_temp_target().unwrap();
+        let extract = |dir: &Path| {
+            fs::create_dir(dir.join("data"))?;
+            fs::write(dir.join("data/file.txt"), "hello")?;
+            Ok(())
+        };
+        let out = Stdio::from(fs::File::create(&stream).unwrap());
+        stream_tar(&target, extract, out).unwrap();
+        assert!(!target.exists());
+        let listing = Command::new("tar")
+            .arg("-tf")
+            .arg(&stream)
+            .output()
+            .unwrap();
+        let listing = String::from_utf8(listing.stdout).unwrap();
+        assert!(
+            listing.lines().any(|l| l == "./data/file.txt"),
+            "{}",
+            listing
+        );
+
+        // A failed extraction writes nothing and still cleans up
+        let target = create_temp_target().unwrap();
+        let failed = temp.path().join("failed.tar");
+        let out = Stdio::from(fs::File::create(&failed).unwrap());
+        let error = stream_tar(&target, |_| anyhow::bail!("CRC failed"), out);
+        assert!(error.is_err());
+        assert!(!target.exists());
+        assert_eq!(fs::metadata(&failed).unwrap().len(), 0);
+        assert!(
+            Args::try_parse_from(["un7z", "--pipe-tar", "--extract-to-temp", "a.tgz"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_extract_into_temp_target() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp.path().join("src/data")).unwrap();
+        fs::write(temp.path().join("src/data/file.txt"), "hello").unwrap();
+        let tarball = temp.path().join("data.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(temp.path().join("src"))
+            .arg("data")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let target = create_temp_target().unwrap();
+        let other = create_temp_target().unwrap();
+        assert_ne!(other, target);
+        fs::remove_dir(other).unwrap();
+        let argv = extract_to_temp_args(
+            ["--extract-to-temp", "data.tar.gz"].map(OsString::from),
+            &target,
+        );
+        let mut full = vec![OsString::from("un7z")];
+        full.extend(argv.iter().cloned());
+        let args = Args::parse_from(full);
+        assert_eq!(args.extract_into.as_deref(), Some(target.as_path()));
+        assert!(!args.extract_to_temp);
+
+        // Even the tarball's own `data/` folder lands inside the target, not next to it
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        let archive = Archive::new(tarball).unwrap();
+        let outcome = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap();
+        assert_eq!(outcome, Outcome::Extracted(target.clone()));
+        let extracted = target.join("data/file.txt");
+        assert_eq!(fs::read_to_string(extracted).unwrap(), "hello");
+        assert!(!temp.path().join("data").exists());
+        fs::remove_dir_all(&target).unwrap();
+
+        assert!(Args::try_parse_from(["un7z", "--extract-to-temp"]).is_err());
+        assert!(Args::try_parse_from(["un7z", "--pipe-tar"]).is_err());
+        assert!(Args::try_parse_from(["un7z", "--extract-to-temp", "-o", "/x", "a.tgz"]).is_err());
+    }
+
+    #[test]
+    fn test_report_incomplete_only_leaves_output() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir(temp.path().join("src")).unwrap();
+        fs::write(temp.path().join("src/file.txt"), "hello").unwrap();
+        let tarball = temp.path().join("data.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(temp.path().join("src"))
+            .arg("file.txt")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let archive = Archive::new(tarball).unwrap();
+        let extract_dir = temp.path().join("data");
+        fs::create_dir(&extract_dir).unwrap();
+        fs::write(extract_dir.join("partial.bin"), "").unwrap();
+
+        let args = Args::parse_from(["un7z", "-y", "--report-incomplete-only"]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        let result = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {});
+        assert_eq!(
+            result.as_ref().unwrap(),
+            &Outcome::Incomplete(extract_dir.clone())
+        );
+        assert!(extract_dir.join("partial.bin").exists());
+        assert!(!extract_dir.join("file.txt").exists());
+
+        let mut stats = BatchStats::default();
+        stats.record(&result);
+        assert_eq!(stats.incomplete, vec![extract_dir.clone()]);
+        assert_eq!((stats.success, stats.skipped), (0, 0));
+
+        // Asked what to do, skipping keeps it as it is
+        let args = Args::parse_from(["un7z", "--dest-exists-action", "skip"]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        let result = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {});
+        assert_eq!(result.unwrap(), Outcome::Skipped);
+        assert!(extract_dir.join("partial.bin").exists());
+
+        // Without either flag it is deleted and re-extracted
+        let opts = ExtractOptions::from_args(&Args::parse_from(["un7z", "-y"])).unwrap();
+        extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap();
+        assert!(!extract_dir.join("partial.bin").exists());
+        assert_eq!(
+            fs::read_to_string(extract_dir.join("file.txt")).unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn test_tar_with_own_folder_is_not_nested() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp.path().join("src/project")).unwrap();
+        fs::write(temp.path().join("src/project/file.txt"), "hello").unwrap();
+        let tarball = temp.path().join("project.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(temp.path().join("src"))
+            .arg("project")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert!(tar_has_own_wrapper(&tarball, "project"));
+        assert!(!tar_has_own_wrapper(&tarball, "other"));
+    }
+
+    #[test]
+    fn test_output_tail_keeps_last_lines() {
+        let mut tail = OutputTail::default();
+        tail.push(b"7-Zip 23.01\r\n\x1b[1mScanning\x1b[0m\n");
+        // Progress redraws via \r and backspaces
+        tail.push(b"  5%\r 50%\x08\x08\x08100%\n");
+        for n in 0..OUTPUT_TAIL_LINES {
+            tail.push(format!("line {}\n", n).as_bytes());
+        }
+        tail.push(b"ERROR: Wrong password : movie.mkv\nArchives with Errors: 1");
+        let lines = tail.into_lines();
+        assert_eq!(lines.len(), OUTPUT_TAIL_LINES);
+        assert_eq!(lines[lines.len() - 2], "ERROR: Wrong password : movie.mkv");
+        assert_eq!(lines[lines.len() - 1], "Archives with Errors: 1");
+
+        let mut tail = OutputTail::default();
+        tail.push(b"\x1b[1mScanning\x1b[0m\r\n  5%\r 50%\x08\x08\x08100%\n");
+        assert_eq!(tail.into_lines(), vec!["Scanning", "100%"]);
+    }
+
+    #[test]
+    fn test_backend_error_includes_captured_output() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("file.txt"), "x").unwrap();
+        let tarball = temp.path().join("ok.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(temp.path())
+            .arg("file.txt")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg("echo file.txt; echo 'tar: file.txt: No space left on device' >&2; exit 2")
+            .arg("sh")
+            .arg(&tarball);
+        let err =
+            run_tar_with_progress(&mut cmd, &tarball, &MultiProgress::new(), &mut |_| {}, None)
+                .unwrap_err();
+        let text = format!("{:#}", err);
+        assert!(text.contains("exit code 2"), "{}", text);
+        assert!(text.contains("No space left on device"), "{}", text);
+
+        let err = BackendError {
+            reason: "Command failed with exit code 2 (fatal error)".to_string(),
+            output: vec![
+                "ERROR: Wrong password : a.mkv".into(),
+                "Archives with Errors: 1".into(),
+            ],
+        };
+        assert_eq!(
+            err.to_string(),
+            "Command failed with exit code 2 (fatal error): ERROR: Wrong password : a.mkv"
+        );
+    }
+
+    #[test]
+    fn test_quick_test_command_per_type() {
+        let command = |name: &str| {
+            let archive = Archive::new(PathBuf::from(name)).unwrap();
+            let cmd = archive.quick_test_command(&Some("pw".to_string())).unwrap();
+            redacted_command_string(&cmd)
+        };
+        assert_eq!(command("/dl/a.7z.001"), "7zz l -slt -p*** /dl/a.7z.001");
+        assert_eq!(command("/dl/a.zip.001"), "7zz l -slt -p*** /dl/a.zip.001");
+        assert_eq!(
+            command("/dl/a.part01.rar"),
+            "unrar lt -p*** /dl/a.part01.rar"
+        );
+        assert_eq!(command("/dl/a.tgz"), "gzip -l /dl/a.tgz");
+
+        let custom = CustomType::parse("lzh=lha x {archive}").unwrap();
+        let archive = Archive::detect(PathBuf::from("/dl/a.lzh"), &[custom]).unwrap();
+        assert!(archive.quick_test_command(&None).is_err());
+    }
+
+    #[test]
+    fn test_on_encrypted_backend_arguments() {
+        let seven = Archive::new(PathBuf::from("/dl/a.7z.001")).unwrap();
+        let rar = Archive::new(PathBuf::from("/dl/a.part01.rar")).unwrap();
+        let switches = |archive: &Archive, policy, password: Option<&str>| {
+            let password = effective_password(policy, &password.map(String::from));
+            let cmd = archive
+                .extract_command(false, &password, Path::new("/out"))
+                .unwrap();
+            cmd.get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .filter(|a| a.starts_with("-p"))
+                .collect::<Vec<_>>()
+        };
+
+        // Prompting is ours to do, so 7zz is left to its default
+        assert!(switches(&seven, OnEncrypted::Prompt, None).is_empty());
+        assert_eq!(switches(&rar, OnEncrypted::Prompt, None), ["-p-"]);
+        for policy in [OnEncrypted::Skip, OnEncrypted::Empty, OnEncrypted::Fail] {
+            assert_eq!(switches(&seven, policy, None), ["-p"]);
+            assert_eq!(switches(&rar, policy, None), ["-p-"]);
+        }
+        // An explicit password always wins
+        for policy in [OnEncrypted::Skip, OnEncrypted::Prompt, OnEncrypted::Empty] {
+            assert_eq!(switches(&seven, policy, Some("pw")), ["-ppw"]);
+            assert_eq!(switches(&rar, policy, Some("pw")), ["-ppw"]);
+        }
+
+        let args = Args::parse_from(["un7z", "--on-encrypted", "skip"]);
+        assert_eq!(args.on_encrypted, OnEncrypted::Skip);
+        assert_eq!(Args::parse_from(["un7z"]).on_encrypted, OnEncrypted::Prompt);
+    }
+
+    #[test]
+    fn test_sevenzip_listing_encrypted() {
+        let listing = "Path = secret.txt\nSize = 5\nEncrypted = -\nMethod = LZMA2:12\n";
+        assert!(!sevenzip_listing_encrypted(listing));
+        let encrypted = listing.replace("Encrypted = -", "Encrypted = +");
+        assert!(sevenzip_listing_encrypted(&encrypted));
+        let headers = "ERROR: a.7z : Can not open encrypted archive. Wrong password?";
+        assert!(sevenzip_listing_encrypted(headers));
+    }
+
+    #[test]
+    fn test_generate_completions_for_each_shell() {
+        use clap::ValueEnum;
+        for shell in clap_complete::Shell::value_variants() {
+            let mut script = Vec::new();
+            write_completions(*shell, &mut script);
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("un7z"), "{:?}", shell);
+            assert!(script.contains("on-encrypted"), "{:?}", shell);
+        }
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn test_colliding_targets_flagged() {
+        let archives: Vec<Archive> = [
+            "/a/movie.7z.001",
+            "/b/movie.part01.rar",
+            "/a/movie.tgz",
+            "/a/other.tgz",
+        ]
+        .iter()
+        .map(|p| Archive::new(PathBuf::from(p)).unwrap())
+        .collect();
+        let all = [0, 1, 2, 3];
+
+        // Next to each archive only the two in /a collide
+        let collisions =
+            colliding_targets(&archives, &all, |a| a.extract_dir(&OutputLayout::default()))
+                .unwrap();
+        assert_eq!(collisions, vec![(PathBuf::from("/a/movie"), vec![0, 2])]);
+
+        // A shared output root brings in the one from /b too
+        let layout = OutputLayout {
+            root: Some(PathBuf::from("/out")),
+            group_by_type: false,
+            scan_root: None,
+            here: false,
+        };
+        let collisions = colliding_targets(&archives, &all, |a| a.extract_dir(&layout)).unwrap();
+        assert_eq!(
+            collisions,
+            vec![(PathBuf::from("/out/movie"), vec![0, 1, 2])]
+        );
+
+        // ... which grouping by type resolves, as does leaving one out of the selection
+        let grouped = OutputLayout {
+            group_by_type: true,
+            ..layout.clone()
+        };
+        assert!(
+            colliding_targets(&archives, &all, |a| a.extract_dir(&grouped))
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            colliding_targets(&archives, &[0, 3], |a| a.extract_dir(&layout))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_touch_tree_applies_archive_mtime() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("out");
+        fs::create_dir_all(dir.join("nested/deeper")).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("nested/deeper/b.txt"), "b").unwrap();
+        let readonly = dir.join("nested/ro.txt");
+        fs::write(&readonly, "ro").unwrap();
+        let mut perms = fs::metadata(&readonly).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&readonly, perms).unwrap();
+
+        let archive_mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        assert_eq!(touch_tree(&dir, archive_mtime).unwrap(), 3);
+        for file in ["a.txt", "nested/deeper/b.txt", "nested/ro.txt"] {
+            let mtime = fs::metadata(dir.join(file)).unwrap().modified().unwrap();
+            assert_eq!(mtime, archive_mtime, "{}", file);
+        }
+    }
+
+    #[test]
+    fn test_probe_backends_with_mocked_path() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp = tempfile::tempdir().unwrap();
+        let fake = |name: &str, script: &str| {
+            let path = temp.path().join(name);
+            fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        fake(
+            "7zz",
+            "echo; echo '7-Zip (z) 23.01 (x64) : Copyright'; echo 'Usage: 7zz <command>'",
+        );
+        fake("tar", "echo \"tar (GNU tar) 1.35 $1\"");
+        // Not executable, so it doesn't count
+        fs::write(temp.path().join("unrar"), "").unwrap();
+
+        let probes = probe_backends(temp.path().as_os_str());
+        let names: Vec<_> = probes.iter().map(|p| p.name).collect();
+        assert_eq!(names, ["7zz", "unrar", "tar", "gzip", "xz", "zstd"]);
+        assert_eq!(
+            probes[0],
+            BackendProbe {
+                name: "7zz",
+                path: Some(temp.path().join("7zz")),
+                version: Some("7-Zip (z) 23.01 (x64) : Copyright".to_string()),
+            }
+        );
+        assert_eq!(probes[1].path, None);
+        assert_eq!(probes[1].version, None);
+        assert_eq!(
+            probes[2].version.as_deref(),
+            Some("tar (GNU tar) 1.35 --version")
+        );
+        assert!(probes[3..].iter().all(|p| p.path.is_none()));
+
+        let text = version_json(&probes).to_string();
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["format"], 1);
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        let backends = json["backends"].as_array().unwrap();
+        assert_eq!(backends.len(), 6);
+        assert_eq!(backends[0]["name"], "7zz");
+        assert_eq!(backends[0]["version"], "7-Zip (z) 23.01 (x64) : Copyright");
+        assert!(backends[0]["path"].is_string());
+        assert!(backends[1]["path"].is_null() && backends[1]["version"].is_null());
+        assert_eq!(json["platform"]["os"], std::env::consts::OS);
+        assert!(json["platform"]["pty"].is_boolean());
+    }
+
+    #[test]
+    fn test_parse_7z_version() {
+        let official =
+            "\n7-Zip (z) 23.01 (x64) : Copyright (c) 1999-2023 Igor Pavlov : 2023-06-20\n";
+        let version = parse_7z_version(official).unwrap();
+        assert_eq!(
+            (version.major, version.minor, version.p7zip),
+            (23, 1, false)
+        );
+        assert_eq!(
+            ProgressProfile::for_7z(Some(&version)),
+            ProgressProfile::SevenZip
+        );
+
+        let p7zip = "7-Zip [64] 16.02 : Copyright (c) 1999-2016 Igor Pavlov : 2016-05-21\n\
+                     p7zip Version 16.02 (locale=utf8,Utf16=on,HugeFiles=on,64 bits)\n";
+        let version = parse_7z_version(p7zip).unwrap();
+        assert_eq!((version.major, version.minor, version.p7zip), (16, 2, true));
+        assert_eq!(
+            ProgressProfile::for_7z(Some(&version)),
+            ProgressProfile::SevenZip
+        );
+
+        let old = "7-Zip [64] 9.20  Copyright (c) 1999-2010 Igor Pavlov  2010-11-18\n\
+                   p7zip Version 9.20 (locale=utf8,Utf16=on,HugeFiles=on,4 CPUs)\n";
+        let version = parse_7z_version(old).unwrap();
+        assert_eq!((version.major, version.minor), (9, 20));
+        assert_eq!(
+            ProgressProfile::for_7z(Some(&version)),
+            ProgressProfile::Generic
+        );
+        assert_eq!(parse_7z_version("command not found"), None);
+        assert_eq!(ProgressProfile::for_7z(None), ProgressProfile::Generic);
+    }
+
+    #[test]
+    fn test_progress_parser_profiles() {
+        fn feed_all(profile: ProgressProfile, chunks: &[&[u8]]) -> Vec<u64> {
+            let mut parser = ProgressParser::new(profile);
+            chunks
+                .iter()
+                .filter_map(|chunk| parser.feed(chunk))
+                .collect()
+        }
+
+        // 7-Zip 23.01: the name after the percent can hold a percent of its own
+        let official: &[&[u8]] = &[
+            b"\x08\x08\x08\x08    \x08\x08\x08\x08  0%",
+            b"\x08\x08\x08\x08    \x08\x08\x08\x08 3",
+            b"7% 2 - 100% juice.txt",
+            b"\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08\x08 88% 3 - b.mkv",
+            b"\r\nEverything is Ok\r\n",
+        ];
+        assert_eq!(feed_all(ProgressProfile::SevenZip, official), [0, 37, 88]);
+        assert_eq!(feed_all(ProgressProfile::Generic, official), [0, 100, 88]);
+
+        // p7zip 16.02 redraws the same way, with its count-less lines
+        let p7zip: &[&[u8]] = &[
+            b"  0%\x08\x08\x08\x08    \x08\x08\x08\x08 12% - data/50%.csv",
+            b"\x08\x08\x08\x08 64% - data/b.csv\x08\x08\x08\x08\x08\x08",
+        ];
+        assert_eq!(feed_all(ProgressProfile::SevenZip, p7zip), [12, 64]);
+
+        // unrar puts the percent after the name
+        let unrar: &[&[u8]] = &[
+            b"Extracting  100% juice.txt                                    ",
+            b"  5%\x08\x08\x08\x08 4",
+            b"5%\x08\x08\x08\x08\x08  OK \n",
+        ];
+        assert_eq!(feed_all(ProgressProfile::Unrar, unrar), [5, 45]);
+    }
+
+    #[test]
+    fn test_last_percent_from_backend_output() {
+        assert_eq!(last_percent(b"\r  7% 3 - a.mkv\r 12% 5 - b.mkv"), Some(12));
+        assert_eq!(
+            last_percent(b"Extracting  a.mkv      45%\x08\x08\x08\x08 46%"),
+            Some(46)
+        );
+        assert_eq!(last_percent(b"100%"), Some(100));
+        assert_eq!(last_percent(b"no progress here"), None);
+        assert_eq!(last_percent(b"% 1234% 250%"), None);
+
+        let archive = Archive::new(PathBuf::from("/data/a.7z.001")).unwrap();
+        let failed = Err(anyhow::anyhow!("Wrong password"));
+        match finished_event(&archive, &failed) {
+            Event::ArchiveFinished {
+                outcome,
+                dir,
+                error,
+                ..
+            } => {
+                assert_eq!(outcome, "failed");
+                assert_eq!(dir, None);
+                assert_eq!(error.as_deref(), Some("Wrong password"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_repair_command_only_for_rar() {
+        let rar = Archive::new(PathBuf::from("/data/show.part01.rar")).unwrap();
+        let cmd = rar.repair_command(Path::new("/data")).unwrap();
+        assert_eq!(cmd.get_program(), "rar");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["r", "-y", "/data/show.part01.rar", "/data/"]);
+
+        let sevenzip = Archive::new(PathBuf::from("/data/show.7z.001")).unwrap();
+        assert!(sevenzip.repair_command(Path::new("/data")).is_none());
+    }
+
+    #[test]
+    fn test_repair_then_retry_flow() {
+        let rar = Archive::new(PathBuf::from("/data/show.part01.rar")).unwrap();
+        let temp = tempfile::tempdir().unwrap();
+        let fixed = |archive: &Archive| {
+            let dir = temp.path().join("repair");
+            fs::create_dir(&dir)?;
+            let path = dir.join("fixed.show.part01.rar");
+            fs::write(&path, "rar")?;
+            Ok(Repaired {
+                dir,
+                archive: Archive {
+                    path,
+                    base_name: archive.base_name.clone(),
+                    archive_type: ArchiveType::Rar,
+                    type_suffix: false,
+                },
+            })
+        };
+
+        // Repaired and the retry on the repaired copy works; the copy is gone after
+        let mut retried = None;
+        let result = repair_and_retry(&rar, anyhow::anyhow!("CRC failed"), fixed, |a| {
+            retried = Some(a.path.clone());
+            assert!(a.path.is_file());
+            Ok(())
+        });
+        assert!(result.is_ok());
+        let copy = temp.path().join("repair/fixed.show.part01.rar");
+        assert_eq!(retried.as_ref(), Some(&copy));
+        assert!(!copy.parent().unwrap().exists());
+
+        // The retry's own failure is reported
+        let err = repair_and_retry(&rar, anyhow::anyhow!("CRC failed"), fixed, |_| {
+            Err(anyhow::anyhow!("still broken"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "still broken");
+        assert!(!copy.parent().unwrap().exists());
+
+        // A failed repair keeps the original error and never retries
+        let err = repair_and_retry(
+            &rar,
+            anyhow::anyhow!("CRC failed"),
+            |_| Err(anyhow::anyhow!("no recovery record")),
+            |_| panic!("retried without a repaired copy"),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "CRC failed");
+
+        // Other types are left alone
+        let sevenzip = Archive::new(PathBuf::from("/data/show.7z.001")).unwrap();
+        let err = repair_and_retry(
+            &sevenzip,
+            anyhow::anyhow!("CRC failed"),
+            |_| panic!("repair attempted on 7z"),
+            |_| panic!("retried 7z"),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "CRC failed");
+    }
+
+    #[test]
+    fn test_progress_fd_writes_lines_to_pipe() {
+        use std::os::fd::AsRawFd;
+        let (mut reader, writer) = std::io::pipe().unwrap();
+        let progress = ProgressFd::open(writer.as_raw_fd() as u32).unwrap();
+        progress.report(3, 12);
+        progress.report(3, 100);
+        progress.report(4, 0);
+        drop(progress);
+        drop(writer);
+
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "3 12\n3 100\n4 0\n");
+        for line in text.lines() {
+            let (number, percent) = line.split_once(' ').unwrap();
+            assert!(number.parse::<usize>().is_ok());
+            assert!(percent.parse::<u64>().unwrap() <= 100);
+        }
+
+        assert!(ProgressFd::open(987_654).is_err());
+    }
+
+    #[test]
+    fn test_retry_fs_retries_transient_locks() {
+        use std::io::{Error, ErrorKind};
+        let no_wait = Duration::ZERO;
+
+        // Locked twice, then free
+        let mut calls = 0;
+        let result = retry_fs_with(5, no_wait, || {
+            calls += 1;
+            if calls <= 2 {
+                Err(Error::from(ErrorKind::ResourceBusy))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Still locked after the last attempt
+        let mut calls = 0;
+        let err = retry_fs_with(3, no_wait, || -> std::io::Result<()> {
+            calls += 1;
+            Err(Error::from(ErrorKind::ResourceBusy))
+        })
+        .unwrap_err();
+        assert_eq!((calls, err.kind()), (3, ErrorKind::ResourceBusy));
+
+        // Anything else fails at once
+        let mut calls = 0;
+        let err = retry_fs_with(5, no_wait, || -> std::io::Result<()> {
+            calls += 1;
+            Err(Error::from(ErrorKind::NotFound))
+        })
+        .unwrap_err();
+        assert_eq!((calls, err.kind()), (1, ErrorKind::NotFound));
+
+        // A rename onto a non-empty directory, or (off Windows) a denied
+        // permission, won't change by waiting either
+        let mut kinds = vec![ErrorKind::DirectoryNotEmpty];
+        if !cfg!(windows) {
+            kinds.push(ErrorKind::PermissionDenied);
+        }
+        for kind in kinds {
+            let mut calls = 0;
+            let err = retry_fs_with(5, no_wait, || -> std::io::Result<()> {
+                calls += 1;
+                Err(Error::from(kind))
+            })
+            .unwrap_err();
+            assert_eq!((calls, err.kind()), (1, kind));
+        }
+    }
+
+    #[test]
+    fn test_listed_types_match_detection() {
+        for builtin in &BUILTIN_TYPES {
+            let example = builtin.pattern.example();
+            let name = example.replacen('*', "sample", 1);
+            let (archive_type, base) = Archive::parse_type(&name).unwrap();
+            assert_eq!(archive_type, builtin.archive_type, "{}", example);
+            assert_eq!(base, "sample", "{}", example);
+            assert_eq!(parse_format(archive_type.as_str()).unwrap(), archive_type);
+        }
+
+        // Every built-in type is listed
+        let listed: Vec<&str> = BUILTIN_TYPES
+            .iter()
+            .map(|b| b.archive_type.as_str())
+            .collect();
+        for name in ["7z", "zip", "rar", "tar.gz"] {
+            assert!(listed.contains(&name), "{}", name);
+        }
+        // Zero padding still counts as the first volume
+        assert!(Archive::parse_type("sample.7z.0001").is_some());
+        assert!(Archive::parse_type("sample.part02.rar").is_none());
+    }
+
+    #[test]
+    fn test_parse_type_suffix_precedence() {
+        let parse =
+            |name: &str| Archive::parse_type(name).map(|(t, base)| (t.as_str().to_string(), base));
+        let parsed = |t: &str, base: &str| Some((t.to_string(), base.to_string()));
+
+        // The whole `.tar.gz` is the suffix; a bare `.gz` is no archive type
+        assert_eq!(parse("logs.tar.gz"), parsed("tar.gz", "logs"));
+        assert_eq!(parse("logs.gz"), None);
+        assert_eq!(parse("logs.tgz"), parsed("tar.gz", "logs"));
+
+        // `.7z.001` is a 7z set; a bare `.001` is left to header probing
+        assert_eq!(parse("movie.7z.001"), parsed("7z", "movie"));
+        assert_eq!(parse("movie.zip.001"), parsed("zip", "movie"));
+        assert_eq!(parse("movie.001"), None);
+        assert_eq!(parse("movie.7z"), None);
+
+        // Names that contain another type's suffix take the longest match
+        assert_eq!(parse("a.tar.gz.7z.001"), parsed("7z", "a.tar.gz"));
+        assert_eq!(parse("a.7z.tar.gz"), parsed("tar.gz", "a.7z"));
+        assert_eq!(parse("show.part001.rar"), parsed("rar", "show"));
+        assert_eq!(parse("show.part01.rar"), parsed("rar", "show"));
+        assert_eq!(parse("show.rar"), None);
+    }
+
+    #[test]
+    fn test_prefetch_reads_upcoming_volumes() {
+        use std::sync::atomic::AtomicBool;
+        let temp = tempfile::tempdir().unwrap();
+        for n in [1, 2, 3] {
+            fs::write(temp.path().join(format!("movie.7z.{:03}", n)), "data").unwrap();
+        }
+        fs::write(temp.path().join("movie.7z.txt"), "not a volume").unwrap();
+        let archive = Archive::new(temp.path().join("movie.7z.001")).unwrap();
+        let volumes = archive.volumes();
+
+        let upcoming = upcoming_volumes(&volumes);
+        assert_eq!(
+            upcoming,
+            [
+                temp.path().join("movie.7z.002"),
+                temp.path().join("movie.7z.003")
+            ]
+        );
+        assert_eq!(
+            prefetch_volumes(upcoming, &AtomicBool::new(false)),
+            upcoming
+        );
+        assert!(prefetch_volumes(upcoming, &AtomicBool::new(true)).is_empty());
+
+        // Nothing to read ahead for a single volume
+        let single = [temp.path().join("movie.7z.001")];
+        assert!(upcoming_volumes(&single).is_empty());
+        assert!(Prefetch::start(single.to_vec()).is_none());
+        let read = Prefetch::start(volumes.clone()).unwrap().finish();
+        assert!(upcoming.starts_with(&read));
+    }
+
+    #[test]
+    fn test_sandbox_wraps_backend_command() {
+        let archive = Archive::new(PathBuf::from("/dl/movie.7z.001")).unwrap();
+        let out = Path::new("/dl/movie");
+        let mut cmd = archive.extract_command(false, &None, out).unwrap();
+        cmd.current_dir("/dl").env("LANG", "C");
+
+        let dl = [Path::new("/dl")];
+        let bwrap = Sandbox::Bwrap.wrap(&cmd, &dl, Some(out));
+        assert_eq!(bwrap.get_program(), "bwrap");
+        assert_eq!(
+            redacted_command_string(&bwrap),
+            "bwrap --ro-bind / / --dev /dev --proc /proc --tmpfs /tmp --ro-bind /dl /dl \
+             --bind /dl/movie /dl/movie --unshare-all --die-with-parent --new-session -- 7zz x -y /dl/movie.7z.001 -o/dl/movie"
+        );
+        assert_eq!(bwrap.get_current_dir(), Some(Path::new("/dl")));
+        assert!(bwrap
+            .get_envs()
+            .any(|(k, v)| k == "LANG" && v == Some("C".as_ref())));
+
+        let firejail = Sandbox::Firejail.wrap(&cmd, &dl, Some(out));
+        assert_eq!(
+            redacted_command_string(&firejail),
+            "firejail --quiet --noprofile --net=none --private-tmp --read-only=/ \
+             --read-write=/dl/movie -- 7zz x -y /dl/movie.7z.001 -o/dl/movie"
+        );
+
+        // Testing writes nothing
+        let args: Vec<_> = Sandbox::Bwrap
+            .wrap(&cmd, &dl, None)
+            .get_args()
+            .map(|a| a.to_owned())
+            .collect();
+        assert!(!args.iter().any(|a| a == "--bind"));
+
+        // Detection prefers bwrap, then firejail
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().as_os_str();
+        assert_eq!(Sandbox::detect(path), None);
+        let fake = |name: &str| {
+            use std::os::unix::fs::PermissionsExt;
+            let tool = temp.path().join(name);
+            fs::write(&tool, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        fake("firejail");
+        assert_eq!(Sandbox::detect(path), Some(Sandbox::Firejail));
+        fake("bwrap");
+        assert_eq!(Sandbox::detect(path), Some(Sandbox::Bwrap));
+        assert_eq!(Sandbox::resolve(false), None);
+    }
+
+    #[test]
+    fn test_sandbox_keeps_archive_in_temp_dir_visible() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir(temp.path().join("src")).unwrap();
+        fs::write(temp.path().join("src/file.txt"), "hello").unwrap();
+        let tarball = temp.path().join("data.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(temp.path().join("src"))
+            .arg("file.txt")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let archive = Archive::new(tarball).unwrap();
+        let out = temp.path().join("data");
+        let cmd = archive.extract_command(false, &None, &out).unwrap();
+
+        // The archive's directory is mounted back over the private /tmp
+        let bwrap: Vec<_> = Sandbox::Bwrap
+            .wrap(&cmd, &[temp.path()], Some(&out))
+            .get_args()
+            .map(|a| a.to_owned())
+            .collect();
+        let tmpfs = bwrap.iter().position(|a| a == "--tmpfs").unwrap();
+        let bind = bwrap.iter().position(|a| a == temp.path()).unwrap();
+        assert!(bind > tmpfs);
+        assert_eq!(bwrap[bind - 1], "--ro-bind");
+        let firejail =
+            redacted_command_string(&Sandbox::Firejail.wrap(&cmd, &[temp.path()], Some(&out)));
+        if temp.path().starts_with("/tmp") {
+            let whitelist = format!("--whitelist={}", temp.path().display());
+            assert!(firejail.contains(&whitelist));
+        }
+
+        // With a sandbox tool installed, the backend really finds the archive
+        if let Some(sandbox) = Sandbox::detect(&std::env::var_os("PATH").unwrap_or_default()) {
+            let mut opts = ExtractOptions::from_args(&Args::parse_from(["un7z", "-y"])).unwrap();
+            opts.sandbox = Some(sandbox);
+            extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap();
+            assert_eq!(fs::read_to_string(out.join("file.txt")).unwrap(), "hello");
+        }
+    }
+
+    #[test]
+    fn test_pump_pty_output_discarding() {
+        let output = b"Extracting\r  5% 1\r 42% 7\r100%\nERROR: Wrong password\n";
+        let mut seen = Vec::new();
+
+        // Shown: everything is copied through
+        let mut shown = Vec::new();
+        let parser = &mut ProgressParser::new(ProgressProfile::Generic);
+        let tail = pump_pty_output(&mut &output[..], &mut shown, parser, &mut |p| seen.push(p));
+        assert_eq!(shown, output);
+        assert_eq!(tail.into_lines(), ["100%", "ERROR: Wrong password"]);
+
+        // Discarded: drained to the end, nothing written, progress still read
+        let mut master = std::io::Cursor::new(&output[..]);
+        let mut discarded = Vec::new();
+        let mut sink = std::io::sink();
+        let parser = &mut ProgressParser::new(ProgressProfile::Generic);
+        let tail = pump_pty_output(&mut master, &mut sink, parser, &mut |p| discarded.push(p));
+        assert_eq!(master.position() as usize, output.len());
+        assert_eq!(discarded, seen);
+        assert_eq!(tail.into_lines(), ["100%", "ERROR: Wrong password"]);
+    }
+
+    #[test]
+    fn test_compression_ratio() {
+        assert_eq!(compression_ratio(100, 340), Some(3.4));
+        assert_eq!(compression_ratio(1 << 20, 1 << 20), Some(1.0));
+        assert_eq!(compression_ratio(200, 50), Some(0.25));
+        assert_eq!(compression_ratio(100, 0), Some(0.0));
+        assert_eq!(compression_ratio(0, 100), None);
+        assert_eq!(format!("{:.1}x", compression_ratio(3, 10).unwrap()), "3.3x");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("500M").unwrap(), 500 << 20);
+        assert_eq!(parse_size("20g").unwrap(), 20 << 30);
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1TB").unwrap(), 1 << 40);
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn test_output_guard_kills_past_limit() {
+        let temp = tempfile::tempdir().unwrap();
+        let out = temp.path().join("out");
+        fs::create_dir(&out).unwrap();
+
+        // Keeps writing for a long time unless stopped
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("while :; do printf 0123456789 >> big; sleep 0.001; done")
+            .current_dir(&out)
+            .spawn()
+            .unwrap();
+        let guard = OutputGuard::new(out.clone(), 2000);
+        guard.set_pid(child.id());
+        let (_stop, stopped) = std::sync::mpsc::channel();
+        guard.watch(stopped, Duration::from_millis(20));
+
+        assert!(guard.tripped());
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+        assert!(tree_size(&out) > 2000);
+
+        // Stopped before the limit: nothing happens
+        let small = OutputGuard::new(out.clone(), u64::MAX);
+        let (stop, stopped) = std::sync::mpsc::channel();
+        stop.send(()).unwrap();
+        small.watch(stopped, Duration::from_millis(20));
+        assert!(!small.tripped());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(parse_format("7z").unwrap(), ArchiveType::SevenZip);
+        assert_eq!(parse_format(".RAR").unwrap(), ArchiveType::Rar);
+        assert_eq!(parse_format("tgz").unwrap(), ArchiveType::TarGz);
+        assert!(parse_format("bin").is_err());
+    }
+
+    #[test]
+    fn test_exists_action_from_input() {
+        assert_eq!(parse_exists_action(Some("o\n")), ExistsAction::Overwrite);
+        assert_eq!(
+            parse_exists_action(Some("Overwrite")),
+            ExistsAction::Overwrite
+        );
+        assert_eq!(parse_exists_action(Some(" r ")), ExistsAction::Rename);
+        assert_eq!(parse_exists_action(Some("rename")), ExistsAction::Rename);
+        assert_eq!(parse_exists_action(Some("s")), ExistsAction::Skip);
+        assert_eq!(parse_exists_action(Some("")), ExistsAction::Skip);
+        assert_eq!(parse_exists_action(Some("yes")), ExistsAction::Skip);
+        assert_eq!(parse_exists_action(None), ExistsAction::Skip);
+    }
+
+    #[test]
+    fn test_unique_dir_picks_free_name() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("movie");
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(unique_dir(&dir), temp.path().join("movie (2)"));
+        fs::create_dir_all(temp.path().join("movie (2)")).unwrap();
+        assert_eq!(unique_dir(&dir), temp.path().join("movie (3)"));
+    }
+
+    #[test]
+    fn test_deadline_decides_whether_to_start() {
+        let now = Instant::now();
+        let minute = Duration::from_secs(60);
+        let deadline = now + 10 * minute;
+        assert!(deadline_allows(deadline, now, None));
+        assert!(deadline_allows(deadline, now, Some(9 * minute)));
+        // Expected to run past the deadline, or the deadline is over
+        assert!(!deadline_allows(deadline, now, Some(11 * minute)));
+        assert!(!deadline_allows(deadline, deadline, None));
+        assert!(!deadline_allows(deadline, deadline + minute, Some(minute)));
+
+        // 100 bytes took a minute, so 300 should take three
+        assert_eq!(expected_duration(300, 100, minute), Some(3 * minute));
+        assert_eq!(expected_duration(300, 0, Duration::ZERO), None);
+
+        // In the loop: archives that can't make it aren't started, smaller ones still are
+        let sizes = [100, 1000, 100];
+        let mut clock = now;
+        let mut stats = run_batch(&[0, 1, 2], false, |n, _| {
+            let expected = expected_duration(sizes[n], 100, minute);
+            if !deadline_allows(now + 5 * minute, clock, expected) {
+                return Ok(Outcome::OutOfTime);
+            }
+            clock += expected.unwrap();
+            Ok(Outcome::Tested)
+        });
+        assert_eq!((stats.success, stats.out_of_time), (2, 1));
+        let lines = summary_lines(3, &mut stats);
+        assert!(lines.iter().any(|l| l.contains("Deadline reached")));
+    }
+
+    #[test]
+    fn test_batch_eta() {
+        let minute = Duration::from_secs(60);
+        // 1 GB of 4 GB in one minute: three minutes to go
+        assert_eq!(batch_eta(1_000, 4_000, minute), Some(minute * 3));
+        assert_eq!(batch_eta(4_000, 4_000, minute), Some(Duration::ZERO));
+        assert_eq!(batch_eta(0, 4_000, minute), None);
+        assert_eq!(batch_eta(1_000, 4_000, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_partition_missing_filters_extracted() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in ["done.7z.001", "todo.7z.001", "empty.7z.001"] {
+            fs::write(temp.path().join(name), "x").unwrap();
+        }
+        fs::create_dir_all(temp.path().join("done")).unwrap();
+        fs::write(temp.path().join("done/file.txt"), "content").unwrap();
+        fs::create_dir_all(temp.path().join("empty")).unwrap();
+
+        let archives = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        let opts = ExtractOptions::from_args(&Args::parse_from(["un7z"])).unwrap();
+        let (missing, done) = partition_missing(&archives, (0..archives.len()).collect(), &opts);
+        let names: Vec<&str> = missing
+            .iter()
+            .map(|&i| archives[i].base_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["empty", "todo"]);
+        assert_eq!(done, 1);
+    }
+
+    #[test]
+    fn test_first_volume_digit_widths() {
+        for (name, base, archive_type) in [
+            ("data.7z.001", "data", ArchiveType::SevenZip),
+            ("data.7z.0001", "data", ArchiveType::SevenZip),
+            ("data.zip.001", "data", ArchiveType::Zip),
+            ("data.zip.00001", "data", ArchiveType::Zip),
+        ] {
+            let archive = Archive::new(PathBuf::from(name)).unwrap();
+            assert_eq!(archive.base_name, base, "{}", name);
+            assert_eq!(archive.archive_type, archive_type, "{}", name);
+        }
+
+        for name in [
+            "data.7z.002",
+            "data.7z.0002",
+            "data.7z.0010",
+            "data.7z.01",
+            "data.7z.1",
+        ] {
+            assert!(Archive::new(PathBuf::from(name)).is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_four_digit_volumes_enumerated() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in ["data.7z.0001", "data.7z.0002", "data.7z.0010"] {
+            fs::write(temp.path().join(name), "x").unwrap();
+        }
+        let archives = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(archives.len(), 1);
+        let volumes = archives[0].volumes();
+        let names: Vec<_> = volumes.iter().map(|v| v.file_name().unwrap()).collect();
+        assert_eq!(names, ["data.7z.0001", "data.7z.0002", "data.7z.0010"]);
+    }
+
+    #[test]
+    fn test_merge_keeps_unrelated_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("src");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("a.csv"), "new").unwrap();
+        fs::write(source.join("b.csv"), "2").unwrap();
+        let tarball = temp.path().join("data.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(&source)
+            .args(["a.csv", "b.csv"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = temp.path().join("data");
+        fs::create_dir(&output).unwrap();
+        fs::write(output.join("a.csv"), "old").unwrap();
+        fs::write(output.join("notes.txt"), "mine").unwrap();
+
+        let archive = Archive::new(tarball).unwrap();
+        let args = Args::parse_from(["un7z", "--dest-exists-action", "merge"]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        let outcome = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap();
+        assert_eq!(outcome, Outcome::Extracted(output.clone()));
+        let read = |name: &str| fs::read_to_string(output.join(name)).unwrap();
+        assert_eq!(read("notes.txt"), "mine");
+        assert_eq!(read("a.csv"), "new");
+        assert_eq!(read("b.csv"), "2");
+
+        // The archive's switches let the backend overwrite without asking
+        assert_eq!(archive.merge_args(), ["--overwrite"]);
+        let rar = Archive::new(PathBuf::from("/dl/a.part01.rar")).unwrap();
+        assert_eq!(rar.merge_args(), ["-o+"]);
+        let seven = Archive::new(PathBuf::from("/dl/a.7z.001")).unwrap();
+        assert_eq!(seven.merge_args(), ["-aoa"]);
+
+        // Skip, by contrast, leaves the existing output alone
+        let args = Args::parse_from(["un7z", "--dest-exists-action", "skip"]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        let outcome = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap();
+        assert_eq!(outcome, Outcome::Skipped);
+    }
+
+    #[test]
+    fn test_extract_here() {
+        let here = OutputLayout {
+            here: true,
+            ..Default::default()
+        };
+        for name in [
+            "/dl/a.7z.001",
+            "/dl/a.zip.001",
+            "/dl/a.part01.rar",
+            "/dl/a.tar.gz",
+        ] {
+            let archive = Archive::new(PathBuf::from(name)).unwrap();
+            assert_eq!(archive.extract_dir(&here).unwrap(), PathBuf::from("/dl"));
+        }
+        let rooted = OutputLayout {
+            root: Some(PathBuf::from("/out")),
+            group_by_type: true,
+            ..here.clone()
+        };
+        let rar = Archive::new(PathBuf::from("/dl/a.part01.rar")).unwrap();
+        assert_eq!(rar.extract_dir(&rooted).unwrap(), PathBuf::from("/out/rar"));
+        // A bare file name resolves to the current directory, not ""
+        let bare = Archive::new(PathBuf::from("foo.tar.gz")).unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(bare.extract_dir(&here).unwrap(), cwd);
+
+        let seven = Archive::new(PathBuf::from("/dl/a.7z.001")).unwrap();
+        let tar = Archive::new(PathBuf::from("/dl/a.tar.gz")).unwrap();
+        assert_eq!(seven.here_args(ExistsAction::Skip), Some(&["-aos"][..]));
+        assert_eq!(seven.here_args(ExistsAction::Rename), Some(&["-aou"][..]));
+        assert_eq!(rar.here_args(ExistsAction::Skip), Some(&["-o-"][..]));
+        assert_eq!(rar.here_args(ExistsAction::Rename), Some(&["-or"][..]));
+        assert_eq!(rar.here_args(ExistsAction::Overwrite), Some(&["-o+"][..]));
+        assert_eq!(
+            tar.here_args(ExistsAction::Skip),
+            Some(&["--skip-old-files"][..])
+        );
+        assert_eq!(tar.here_args(ExistsAction::Rename), None);
+        // The output flag names the directory itself
+        let cmd = rar.extract_command(false, &None, Path::new("/dl")).unwrap();
+        assert_eq!(cmd.get_args().last(), Some(std::ffi::OsStr::new("/dl/")));
+        let cmd = seven
+            .extract_command(false, &None, Path::new("/dl"))
+            .unwrap();
+        assert_eq!(cmd.get_args().last(), Some(std::ffi::OsStr::new("-o/dl")));
+
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("src");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("a.csv"), "new").unwrap();
+        fs::write(source.join("b.csv"), "2").unwrap();
+        let dl = temp.path().join("dl");
+        fs::create_dir(&dl).unwrap();
+        let tarball = dl.join("data.tar.gz");
+        let status = Command::new("tar")
+            .arg("czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(&source)
+            .args(["a.csv", "b.csv"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::write(dl.join("a.csv"), "mine").unwrap();
+
+        // Existing files are kept by default, the rest lands beside the archive
+        let archive = Archive::new(tarball).unwrap();
+        let args = Args::parse_from(["un7z", "--extract-here"]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        let outcome = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap();
+        assert_eq!(outcome, Outcome::Extracted(dl.clone()));
+        let read = |name: &str| fs::read_to_string(dl.join(name)).unwrap();
+        assert_eq!(read("a.csv"), "mine");
+        assert_eq!(read("b.csv"), "2");
+        assert!(!dl.join("data").exists());
+
+        let args = Args::parse_from([
+            "un7z",
+            "--extract-here",
+            "--dest-exists-action",
+            "overwrite",
+        ]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap();
+        assert_eq!(read("a.csv"), "new");
+        assert!(archive.path.exists());
+
+        // tar can't rename on its own, so that fails without touching anything
+        let args = Args::parse_from(["un7z", "--extract-here", "--dest-exists-action", "rename"]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        let error = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {});
+        assert!(format!("{:#}", error.unwrap_err()).contains("cannot rename"));
+        assert!(dl.exists() && archive.path.exists());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_verbatim_windows_path() {
+        assert_eq!(
+            verbatim_windows_path(r"C:\Users\me\dl\film").as_deref(),
+            Some(r"\\?\C:\Users\me\dl\film")
+        );
+        assert_eq!(
+            verbatim_windows_path("D:/dl/deep/film").as_deref(),
+            Some(r"\\?\D:\dl\deep\film")
+        );
+        assert_eq!(
+            verbatim_windows_path(r"\\nas\share\film").as_deref(),
+            Some(r"\\?\UNC\nas\share\film")
+        );
+        assert_eq!(
+            verbatim_windows_path(r"\\?\C:\film").as_deref(),
+            Some(r"\\?\C:\film")
+        );
+        assert_eq!(verbatim_windows_path(r"dl\film"), None);
+        assert_eq!(verbatim_windows_path("C:film"), None);
+
+        // Short paths reach the backend as they are
+        let short = Path::new(r"C:\dl\film");
+        assert_eq!(backend_path(short), short);
+        let long = PathBuf::from(format!(r"C:\dl\{}", "d\\".repeat(WINDOWS_MAX_PATH)));
+        assert!(backend_path(&long).starts_with(r"\\?\C:\"));
+    }
+
+    #[test]
+    fn test_detect_matches_parse_type() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in [
+            "a.7z.001",
+            "b.zip.001",
+            "c.part01.rar",
+            "d.part001.rar",
+            "e.tar.gz",
+            "f.tgz",
+            "notes.txt",
+            "g.7z.002",
+        ] {
+            let path = temp.path().join(name);
+            fs::write(&path, "x").unwrap();
+            let by_name = Archive::parse_type(name).map(|(archive_type, _)| archive_type);
+            assert_eq!(detected_type(&path, &[]), by_name, "{}", name);
+        }
+
+        // No type in the name: the split's header magic decides
+        let split = temp.path().join("backup.001");
+        fs::write(&split, b"7z\xBC\xAF\x27\x1C rest").unwrap();
+        assert_eq!(Archive::parse_type("backup.001"), None);
+        assert_eq!(detected_type(&split, &[]), Some(ArchiveType::SevenZip));
+        let zip = temp.path().join("data.001");
+        fs::write(&zip, b"PK\x03\x04 rest").unwrap();
+        assert_eq!(detected_type(&zip, &[]), Some(ArchiveType::Zip));
+        let junk = temp.path().join("junk.001");
+        fs::write(&junk, "nothing known").unwrap();
+        assert_eq!(detected_type(&junk, &[]), None);
+
+        let custom = [CustomType::parse("lzh=lha xw={outdir} {archive}").unwrap()];
+        let lzh = temp.path().join("old.lzh");
+        let detected = detected_type(&lzh, &custom).unwrap();
+        assert_eq!(detected.as_str(), "lzh");
+
+        let path = Path::new("/dl/a.7z.001");
+        assert_eq!(
+            detect_line(path, Some(&ArchiveType::SevenZip), false),
+            "/dl/a.7z.001\t7z"
+        );
+        assert_eq!(detect_line(path, None, false), "/dl/a.7z.001\tunknown");
+        let json: serde_json::Value = serde_json::from_str(&detect_line(path, None, true)).unwrap();
+        assert_eq!(json["path"], "/dl/a.7z.001");
+        assert!(json["type"].is_null());
+        assert!(run_detect(&[split, junk], &[], false).is_err());
+
+        let args = Args::parse_from(["un7z", "detect", "--json", "a.7z.001", "b.bin"]);
+        let Some(Cmd::Detect { files, json }) = args.command else {
+            panic!("detect not parsed");
+        };
+        assert_eq!(files, [PathBuf::from("a.7z.001"), PathBuf::from("b.bin")]);
+        assert!(json);
+        // Without the subcommand, files are still archives to extract
+        let args = Args::parse_from(["un7z", "a.7z.001"]);
+        assert!(args.command.is_none());
+        assert_eq!(args.files, [PathBuf::from("a.7z.001")]);
+    }
+
+    #[test]
+    fn test_update_args() {
+        let archive = |name: &str| Archive::new(PathBuf::from(name)).unwrap();
+        let tar = archive("/dl/a.tar.gz");
+        assert_eq!(tar.update_args(), Some(&["--keep-newer-files"][..]));
+        let mut cmd = tar
+            .extract_command(false, &None, Path::new("/out"))
+            .unwrap();
+        cmd.args(tar.update_args().unwrap());
+        assert_eq!(
+            cmd.get_args().last(),
+            Some(std::ffi::OsStr::new("--keep-newer-files"))
+        );
+        assert_eq!(archive("/dl/a.part01.rar").update_args(), Some(&["-u"][..]));
+        // 7zz has no such mode: the output is merged from a staging folder
+        assert_eq!(archive("/dl/a.7z.001").update_args(), None);
+        assert_eq!(archive("/dl/a.zip.001").update_args(), None);
+    }
+
+    #[test]
+    fn test_merge_newer_keeps_newer_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let (staging, output) = (temp.path().join("staging"), temp.path().join("out"));
+        fs::create_dir_all(staging.join("sub")).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(3600);
+        let write = |path: PathBuf, data: &str, mtime: SystemTime| {
+            fs::write(&path, data).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        };
+        write(staging.join("old.txt"), "archive", now - hour);
+        write(output.join("old.txt"), "edited", now);
+        write(staging.join("stale.txt"), "archive", now);
+        write(output.join("stale.txt"), "outdated", now - hour);
+        write(staging.join("sub/new.txt"), "archive", now);
+
+        assert_eq!(merge_newer(&staging, &output).unwrap(), (2, 1));
+        let read = |name: &str| fs::read_to_string(output.join(name)).unwrap();
+        assert_eq!(read("old.txt"), "edited");
+        assert_eq!(read("stale.txt"), "archive");
+        assert_eq!(read("sub/new.txt"), "archive");
+
+        assert!(replaces_existing(now, None));
+        assert!(replaces_existing(now, Some(now - hour)));
+        assert!(!replaces_existing(now - hour, Some(now)));
+        assert!(!replaces_existing(now, Some(now)));
+    }
+
+    #[test]
+    fn test_max_output_size_with_overwrite_newer() {
+        let temp = tempfile::tempdir().unwrap();
+        // The "archive" is a script that keeps writing into {outdir} unless stopped
+        let archive = temp.path().join("data.grow");
+        fs::write(
+            &archive,
+            "while :; do printf 0123456789 >> \"$1/big\"; sleep 0.001; done",
+        )
+        .unwrap();
+        let output = temp.path().join("data");
+        fs::create_dir(&output).unwrap();
+        fs::write(output.join("kept.txt"), "mine").unwrap();
+
+        let args = Args::parse_from([
+            "un7z",
+            "--custom-type",
+            "grow=sh {archive} {outdir}",
+            "--overwrite-newer",
+            "--max-output-size",
+            "2K",
+            "--no-pty-progress",
+        ]);
+        let opts = ExtractOptions::from_args(&args).unwrap();
+        let archive = Archive::detect(archive, &args.custom_types).unwrap();
+        // Custom types have no update switch, so this writes to a staging directory
+        assert_eq!(archive.update_args(), None);
+        let err = extract_archive(&archive, &MultiProgress::new(), &opts, &mut |_| {}).unwrap_err();
+        assert!(err.to_string().contains("--max-output-size"), "{:#}", err);
+        assert!(!temp.path().join(".data.un7z-update").exists());
+    }
+
+    #[test]
+    fn test_spec_settings_override_the_command_line() {
+        let args = Args::parse_from(["un7z", "--password", "cli", "--touch", "-o", "/out"]);
+        let text = r#"
+[[archive]]
+path = "/dl/film.7z.001"
+password = "spec"
+output = "/media"
+test = true
+
+[[archive]]
+path = "/dl/show.part01.rar"
+touch = false
+"#;
+        let opts = ExtractOptions {
+            spec: spec::Spec::parse(text, Path::new("/jobs")).unwrap(),
+            ..ExtractOptions::from_args(&args).unwrap()
+        };
+
+        let film = Archive::new(PathBuf::from("/dl/film.7z.001")).unwrap();
+        let settings = opts.settings_for(&film);
+        assert_eq!(settings.password.as_deref(), Some("spec"));
+        assert!(settings.test && settings.touch && !settings.force);
+        assert_eq!(
+            film.extract_dir(&settings.layout).unwrap(),
+            PathBuf::from("/media/film")
+        );
+
+        // Unset keys fall back to the command line
+        let show = Archive::new(PathBuf::from("/dl/show.part01.rar")).unwrap();
+        let settings = opts.settings_for(&show);
+        assert_eq!(settings.password.as_deref(), Some("cli"));
+        assert!(!settings.test && !settings.touch);
+        assert_eq!(
+            show.extract_dir(&settings.layout).unwrap(),
+            PathBuf::from("/out/show")
+        );
+
+        // ... as does everything for an archive the spec doesn't list
+        let other = Archive::new(PathBuf::from("/dl/other.zip.001")).unwrap();
+        let settings = opts.settings_for(&other);
+        assert_eq!(settings.password.as_deref(), Some("cli"));
+        assert!(settings.touch);
+        assert!(matches!(settings.layout, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_downloads_extract_into_the_current_directory() {
+        let args = Args::parse_from(["un7z", "https://example.com/film.7z.001", "/dl/show.tgz"]);
+        let download = PathBuf::from("/tmp/un7z-download-1/film.7z.001");
+        let mut opts = ExtractOptions::from_args(&args).unwrap();
+        opts.extract_downloads_into(vec![download.clone()], PathBuf::from("/work"));
+
+        let film = Archive::new(download.clone()).unwrap();
+        let layout = opts.settings_for(&film).layout;
+        assert_eq!(film.extract_dir(&layout).unwrap(), Path::new("/work/film"));
+        // The local file listed with the URL still extracts next to itself
+        let show = Archive::new(PathBuf::from("/dl/show.tgz")).unwrap();
+        let layout = opts.settings_for(&show).layout;
+        assert_eq!(show.extract_dir(&layout).unwrap(), Path::new("/dl/show"));
+
+        // --output applies to both
+        let args = Args::parse_from(["un7z", "-o", "/out", "https://example.com/film.7z.001"]);
+        let mut opts = ExtractOptions::from_args(&args).unwrap();
+        opts.extract_downloads_into(vec![download], PathBuf::from("/work"));
+        let layout = opts.settings_for(&film).layout;
+        assert_eq!(film.extract_dir(&layout).unwrap(), Path::new("/out/film"));
+    }
+
+    #[test]
+    fn test_session_password_tried_before_prompt() {
+        let session = SessionPassword::default();
+        let prompts = std::cell::Cell::new(0);
+        let prompt = |answer: &'static str| {
+            let prompts = &prompts;
+            move || {
+                prompts.set(prompts.get() + 1);
+                Some(answer.to_string())
+            }
+        };
+
+        // Nothing kept yet: the prompt is asked, whatever the tester says
+        let got = session.get_or_prompt(|_| panic!("nothing to test"), prompt("first"));
+        assert_eq!(got, (Some("first".to_string()), true));
+        session.remember("first");
+
+        // The kept password is tried first and used when it works
+        let got = session.get_or_prompt(|pw| pw == "first", prompt("unused"));
+        assert_eq!(got, (Some("first".to_string()), false));
+        assert_eq!(prompts.get(), 1);
+
+        // Rejected for this archive: prompt again, but keep the first one
+        let got = session.get_or_prompt(|_| false, prompt("second"));
+        assert_eq!(got, (Some("second".to_string()), true));
+        session.remember("second");
+        assert_eq!(session.0.lock().unwrap().as_deref(), Some("first"));
+        assert_eq!(prompts.get(), 2);
+    }
+
+    #[test]
+    fn test_compact_status() {
+        assert_eq!(
+            compact_status(12, 50, "extracting", "foo", Some(43)),
+            "[12/50] extracting foo (43%)"
         );
-    } else {
-        println!(
-            "{} {}",
-            style("⟳").cyan(),
-            style(format!("Extracting: {}", base_name)).cyan()
+        assert_eq!(
+            compact_status(1, 3, "testing", "bar", None),
+            "[1/3] testing bar"
         );
     }
 
-    // Run command with PTY for real progress display
-    let result = if test {
-        let mut cmd = archive.extract_command(true, password);
-        run_with_pty(&mut cmd, &archive.path)
-    } else {
-        let mut cmd = archive.extract_command(false, password);
-        run_with_pty(&mut cmd, &archive.path)
-    };
+    #[test]
+    fn test_notification_text() {
+        let mut stats = BatchStats {
+            success: 3,
+            skipped: 1,
+            ..Default::default()
+        };
+        let (title, body) = notification_text(4, &stats);
+        assert_eq!(title, "un7z: done");
+        assert_eq!(body, "4 archive(s): 3 extracted, 0 failed, 1 skipped");
 
-    // Handle result
-    match &result {
-        Ok(()) => {
-            println!(
-                "{} {}",
-                style("✓").green(),
-                style(base_name).green()
-            );
+        stats.failed = 2;
+        stats.not_attempted = 1;
+        stats.incomplete.push(PathBuf::from("/dl/x"));
+        let (title, body) = notification_text(7, &stats);
+        assert_eq!(title, "un7z: 2 failed");
+        assert_eq!(
+            body,
+            "7 archive(s): 3 extracted, 2 failed, 1 skipped, 1 incomplete, 1 not attempted"
+        );
+    }
+
+    #[test]
+    fn test_summary_from_events_report() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("events.ndjson");
+        let log = EventLog::create(&path).unwrap();
+        let archive = Path::new("/d/a.7z.001");
+        log.emit(&Event::ScanComplete { archives: 6 });
+        log.emit(&Event::ArchiveStarted { archive, name: "a" });
+        let finished = [
+            ("extracted", Some("/d/a"), None),
+            ("failed", None, Some("Wrong password")),
+            ("skipped", None, None),
+            ("incomplete", Some("/d/e"), None),
+            ("quick_checked", None, None),
+        ];
+        for (outcome, dir, error) in finished {
+            log.emit(&Event::ArchiveFinished {
+                archive,
+                outcome,
+                dir: dir.map(Path::new),
+                error: error.map(str::to_string),
+            });
         }
-        Err(e) => {
-            println!(
-                "{} {}",
-                style("✗").red(),
-                style(base_name).red()
-            );
-            println!("  {} Error: {}", style("┖─").dim(), e);
+
+        let report = fs::read_to_string(&path).unwrap();
+        let (finished, mut stats) = stats_from_events(report.as_bytes()).unwrap();
+        assert_eq!(finished, 5);
+        let lines: Vec<String> = summary_lines(finished, &mut stats)
+            .iter()
+            .map(|l| console::strip_ansi_codes(l).into_owned())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                format!("\n{}", "═".repeat(50)),
+                "Total: 5 | Success: 1 | Failed: 1 | Skipped: 2".to_string(),
+                "\nℹ 1 passed a quick check only (headers/CRC tables, not a full test)".to_string(),
+                "\n⚠ 1 output director(ies) look incomplete, left as they are:".to_string(),
+                "  • /d/e".to_string(),
+                "\n⚠ See failed.log for details".to_string(),
+            ]
+        );
+
+        let broken = r#"{"event":"archive_finished","archive":"/d/a","outcome":"exploded"}"#;
+        assert!(stats_from_events(broken.as_bytes()).is_err());
+        assert!(stats_from_events("not json\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_selection_survives_scan_order() {
+        let temp = tempfile::tempdir().unwrap();
+        for name in ["a.7z.001", "b.tgz", "c.part01.rar"] {
+            fs::write(temp.path().join(name), "").unwrap();
         }
+        let archives = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        let names = |archives: &[Archive], indices: &[usize]| {
+            indices
+                .iter()
+                .map(|&i| archives[i].base_name.clone())
+                .collect::<Vec<_>>()
+        };
+        let picked = parse_selection("1,3", archives.len()).unwrap();
+        assert_eq!(names(&archives, &picked), ["a", "c"]);
+
+        let saved = temp.path().join("selection.txt");
+        save_selection(&saved, &archives, &picked).unwrap();
+
+        // A later run lists them in another order, and one is gone
+        let mut later = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        later.reverse();
+        let (indices, missing) = load_selection(&saved, &later).unwrap();
+        assert_eq!(names(&later, &indices), ["c", "a"]);
+        assert!(missing.is_empty());
+
+        fs::remove_file(temp.path().join("a.7z.001")).unwrap();
+        let after = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        let (indices, missing) = load_selection(&saved, &after).unwrap();
+        assert_eq!(names(&after, &indices), ["c"]);
+        assert_eq!(missing, vec![success_log_key(&archives[0])]);
     }
 
-    if result.is_err() {
-        if extract_dir.exists() {
-            fs::remove_dir_all(&extract_dir)?;
+    #[test]
+    fn test_select_option_matches_prompt() {
+        for input in ["1,3,5-7", "2", "4-6, 1", "8,8,1"] {
+            assert_eq!(
+                parse_select_option(input, 8).unwrap(),
+                parse_selection(input, 8).unwrap()
+            );
         }
-        return result;
+        assert_eq!(parse_select_option("all", 3).unwrap(), [0, 1, 2]);
+
+        // The prompt drops numbers it has no archive for; --select refuses them
+        assert_eq!(parse_selection("1,9", 8).unwrap(), [0]);
+        let error = parse_select_option("1,9", 8).unwrap_err().to_string();
+        assert!(error.contains("9 is out of range, 8 archive(s) found"));
+        assert!(parse_select_option("5-9", 8).is_err());
+        assert!(parse_select_option("0", 8).is_err());
+        assert!(parse_select_option("x", 8).is_err());
+
+        let args = Args::parse_from(["un7z", "--select", "1,3"]);
+        assert_eq!(args.select.as_deref(), Some("1,3"));
+        assert!(Args::try_parse_from(["un7z", "--select", "1", "--all"]).is_err());
     }
 
-    Ok(())
-}
+    #[test]
+    fn test_prompt_timeout_falls_back() {
+        use std::os::fd::AsFd;
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        let fd = reader.as_fd().try_clone_to_owned().unwrap();
+        let read = &mut std::io::BufReader::new(reader);
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+        // Nobody answers: nothing is read, and nothing is left reading
+        let started = Instant::now();
+        let answer = read_line_within(fd.as_fd(), read, Some(Duration::from_millis(50)));
+        assert_eq!(answer.unwrap(), None);
+        assert!(started.elapsed() < Duration::from_secs(5));
 
-    // Print banner
-    println!(
-        "\n{} {} {}",
-        style("un7z").bold().cyan(),
-        style(format!("v{}", env!("CARGO_PKG_VERSION"))).dim(),
-        style("- Modern Batch Extraction").cyan()
-    );
+        // So the next line goes to the next read
+        writer.write_all(b"1,3\n").unwrap();
+        let answered = read_line_within(fd.as_fd(), read, Some(Duration::from_secs(5)));
+        assert_eq!(answered.unwrap().as_deref(), Some("1,3\n"));
+        writer.write_all(b"all\n").unwrap();
+        let untimed = read_line_within(fd.as_fd(), read, None);
+        assert_eq!(untimed.unwrap().as_deref(), Some("all\n"));
+        // A timeout past what the clock can hold waits like none at all
+        writer.write_all(b"2\n").unwrap();
+        let endless = read_line_within(fd.as_fd(), read, Some(Duration::MAX));
+        assert_eq!(endless.unwrap().as_deref(), Some("2\n"));
 
-    // Scan for archives
-    let spinner_style = ProgressStyle::default_spinner()
-        .template("{spinner:.cyan} {msg}")
-        .unwrap();
+        // Two lines at once: the second is in the buffer, not the pipe,
+        // and is read without waiting for more
+        writer.write_all(b"1\n3\n").unwrap();
+        let first = read_line_within(fd.as_fd(), read, Some(Duration::from_secs(5)));
+        assert_eq!(first.unwrap().as_deref(), Some("1\n"));
+        let second = read_line_within(fd.as_fd(), read, Some(Duration::from_millis(50)));
+        assert_eq!(second.unwrap().as_deref(), Some("3\n"));
+
+        assert_eq!(
+            selection_from_answer(None, 3, OnPromptTimeout::All).unwrap(),
+            [0, 1, 2]
+        );
+        let error = selection_from_answer(None, 3, OnPromptTimeout::Abort).unwrap_err();
+        assert!(error.to_string().contains("--prompt-timeout"));
+        // An answer is taken as the prompt always took it
+        let answer = |input| selection_from_answer(Some(input), 3, OnPromptTimeout::All).unwrap();
+        assert_eq!(answer("2-3\n"), [1, 2]);
+        assert_eq!(answer("\n"), [0, 1, 2]);
 
-    let scan_spinner = ProgressBar::new(1);
-    scan_spinner.set_style(spinner_style);
-    scan_spinner.set_message("Scanning for archives...");
-    scan_spinner.enable_steady_tick(std::time::Duration::from_millis(80));
+        let args = Args::parse_from([
+            "un7z",
+            "--prompt-timeout",
+            "30s",
+            "--on-prompt-timeout",
+            "all",
+        ]);
+        assert_eq!(args.prompt_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(args.on_prompt_timeout, OnPromptTimeout::All);
+        assert!(Args::try_parse_from(["un7z", "--on-prompt-timeout", "all"]).is_err());
+    }
 
-    let archives = scan_archives(&args.dir)?;
+    #[test]
+    fn test_run_batch_fail_fast() {
+        let results = |i: usize| match i {
+            1 => Ok(Outcome::Skipped),
+            2 => Err(anyhow::anyhow!("broken")),
+            _ => Ok(Outcome::Tested),
+        };
+        let indices = [0, 1, 2, 3, 4];
 
-    scan_spinner.finish_with_message(format!(
-        "{} Found {} archive(s)",
-        style("✓").green(),
-        style(archives.len()).yellow()
-    ));
+        let mut seen = Vec::new();
+        let stats = run_batch(&indices, true, |_, i| {
+            seen.push(i);
+            results(i)
+        });
+        assert_eq!(seen, vec![0, 1, 2]);
+        assert_eq!(
+            stats,
+            BatchStats {
+                success: 1,
+                failed: 1,
+                skipped: 1,
+                quick_checked: 0,
+                not_attempted: 2,
+                out_of_time: 0,
+                incomplete: vec![],
+            }
+        );
 
-    if archives.is_empty() {
-        println!("\n{}", style("No archives found.").yellow().dim());
-        return Ok(());
+        // Without the flag every archive is attempted
+        let stats = run_batch(&indices, false, |_, i| results(i));
+        assert_eq!(stats.success, 3);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.not_attempted, 0);
     }
 
-    // Select archives
-    let indices = if args.all {
-        (0..archives.len()).collect()
-    } else {
-        select_archives(&archives)?
-    };
+    #[test]
+    fn test_jobs_per_disk_is_not_interactive() {
+        let args = ["un7z", "--jobs-per-disk", "2", "--interactive-overwrite"];
+        assert!(Args::try_parse_from(args).is_err());
+        let args = Args::parse_from(["un7z", "--jobs-per-disk", "2"]);
+        assert!(ExtractOptions::from_args(&args).unwrap().no_pty_progress);
+        let args = Args::parse_from(["un7z"]);
+        assert!(!ExtractOptions::from_args(&args).unwrap().no_pty_progress);
+    }
 
-    if indices.is_empty() {
-        println!("\n{}", style("No archives selected.").yellow().dim());
-        return Ok(());
+    #[test]
+    fn test_group_by_device_with_synthetic_ids() {
+        let devices = [Some(7), Some(3), Some(7), None, Some(3), Some(7)];
+        let groups = group_by_device(devices.len(), |n| devices[n]);
+        assert_eq!(groups, vec![vec![0, 2, 5], vec![1, 4], vec![3]]);
+        assert!(group_by_device(0, |_| Some(1)).is_empty());
+
+        let temp = tempfile::tempdir().unwrap();
+        let missing = temp.path().join("not/yet/created");
+        assert_eq!(device_of(&missing), device_of(temp.path()));
     }
 
-    // Extract
-    let multi_progress = MultiProgress::new();
+    #[test]
+    fn test_run_batch_by_device_serializes_each_disk() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
 
-    println!(
-        "\n{} {} {}",
-        style("→").bold().cyan(),
-        style("Extracting").bold(),
-        style(indices.len()).bold().yellow()
-    );
+        // Positions 0, 2, 4 share disk A; 1 and 3 share disk B
+        let indices = [10, 11, 12, 13, 14];
+        let devices = [1, 2, 1, 2, 1];
+        let groups = group_by_device(indices.len(), |n| Some(devices[n]));
+        let running = [
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+        ];
+        let peak = [
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+        ];
+        let order = Mutex::new(Vec::new());
 
-    let mut success = 0;
-    let mut failed = 0;
-    let skipped = 0;
+        let stats = run_batch_by_device(&indices, &groups, 1, false, |n, i| {
+            let dev = devices[n] as usize;
+            let now = running[dev].fetch_add(1, Ordering::SeqCst) + 1;
+            peak[dev].fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            order.lock().unwrap().push(i);
+            running[dev].fetch_sub(1, Ordering::SeqCst);
+            Ok(Outcome::Tested)
+        });
+        assert_eq!(stats.success, 5);
+        assert_eq!(peak[1].load(Ordering::SeqCst), 1);
+        assert_eq!(peak[2].load(Ordering::SeqCst), 1);
 
-    for i in &indices {
-        let archive = &archives[*i];
+        // Each disk's archives still run in selection order
+        let order = order.into_inner().unwrap();
+        let on_a: Vec<_> = order.iter().filter(|&&i| i % 2 == 0).collect();
+        assert_eq!(on_a, [&10, &12, &14]);
 
-        match extract_archive(archive, &multi_progress, args.test, &args.password, false) {
-            Ok(()) => {
-                success += 1;
+        // Two jobs per disk let disk A run two at once
+        let stats = run_batch_by_device(&indices, &groups, 2, false, |n, _| {
+            let dev = devices[n] as usize;
+            let now = running[dev].fetch_add(1, Ordering::SeqCst) + 1;
+            peak[dev].fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(50));
+            running[dev].fetch_sub(1, Ordering::SeqCst);
+            Ok(Outcome::Tested)
+        });
+        assert_eq!(stats.success, 5);
+        assert_eq!(peak[1].load(Ordering::SeqCst), 2);
+
+        // Fail fast: nothing new starts after the failure
+        let stats = run_batch_by_device(&indices, &[vec![0, 1, 2, 3, 4]], 1, true, |n, _| {
+            if n == 1 {
+                Err(anyhow::anyhow!("broken"))
+            } else {
+                Ok(Outcome::Tested)
             }
-            Err(e) => {
-                failed += 1;
-                eprintln!(
-                    "\n{} {}: {}",
-                    style("✗").red(),
-                    style(archive.base_name.clone()).red(),
-                    e
-                );
-                log_failed_archive(archive, &e);
+        });
+        assert_eq!(
+            (stats.success, stats.failed, stats.not_attempted),
+            (1, 1, 3)
+        );
+    }
+
+    #[test]
+    fn test_archive_records_for_directory() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp.path().join("sub")).unwrap();
+        fs::write(temp.path().join("sub/movie.part01.rar"), vec![0u8; 100]).unwrap();
+        fs::write(temp.path().join("sub/movie.part02.rar"), vec![0u8; 30]).unwrap();
+        fs::write(temp.path().join("docs.tgz"), vec![0u8; 12]).unwrap();
+
+        let archives = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        let records: Vec<ArchiveRecord> = archives.iter().map(ArchiveRecord::new).collect();
+        let json: serde_json::Value = serde_json::to_value(&records).unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "path": root.join("docs.tgz"),
+                    "base_name": "docs",
+                    "type": "tar.gz",
+                    "volumes": 1,
+                    "total_size": 12
+                },
+                {
+                    "path": root.join("sub/movie.part01.rar"),
+                    "base_name": "movie",
+                    "type": "rar",
+                    "volumes": 2,
+                    "total_size": 130
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_display_name_renders_control_chars_inert() {
+        assert_eq!(display_name("plain name ünïcode"), "plain name ünïcode");
+        let spoof = "evil\x1b[2J\x1b[32m✓ fake\nline\r\x07";
+        let shown = display_name(spoof);
+        assert!(!shown.chars().any(|c| c.is_control()), "{:?}", shown);
+        assert_eq!(shown, "evil\\u{1b}[2J\\u{1b}[32m✓ fake\\nline\\r\\u{7}");
+        assert_eq!(display_path(Path::new("dir\n/x.7z.001")), "dir\\n/x.7z.001");
+    }
+
+    #[test]
+    fn test_move_dir_falls_back_to_copy_across_filesystems() {
+        let temp = tempfile::tempdir().unwrap();
+        let src = temp.path().join("scratch/data");
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(src.join("a.txt"), "alpha").unwrap();
+        fs::write(src.join("nested/b.txt"), "beta").unwrap();
+        std::os::unix::fs::symlink("a.txt", src.join("link")).unwrap();
+        let dst = temp.path().join("bulk/data");
+        fs::create_dir_all(temp.path().join("bulk")).unwrap();
+
+        // Simulate EXDEV from rename
+        let cross_device = |_: &Path, _: &Path| Err(std::io::ErrorKind::CrossesDevices.into());
+        move_dir_with(&src, &dst, cross_device).unwrap();
+
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(dst.join("a.txt")).unwrap(), "alpha");
+        assert_eq!(
+            fs::read_to_string(dst.join("nested/b.txt")).unwrap(),
+            "beta"
+        );
+        assert_eq!(
+            fs::read_link(dst.join("link")).unwrap(),
+            PathBuf::from("a.txt")
+        );
+
+        // Other errors are not papered over
+        let denied = |_: &Path, _: &Path| Err(std::io::ErrorKind::PermissionDenied.into());
+        assert!(move_dir_with(&dst, &src, denied).is_err());
+        assert!(dst.exists());
+    }
+
+    #[test]
+    fn test_relocate_output_keeps_output_on_collision() {
+        let temp = tempfile::tempdir().unwrap();
+        let extract_dir = temp.path().join("data");
+        fs::create_dir_all(&extract_dir).unwrap();
+        fs::write(extract_dir.join("new.txt"), "new").unwrap();
+        let move_to = temp.path().join("store");
+        fs::create_dir_all(move_to.join("data")).unwrap();
+
+        let result = relocate_output(&extract_dir, &move_to, None, false).unwrap();
+        assert_eq!(result, extract_dir);
+        assert!(extract_dir.join("new.txt").exists());
+
+        fs::remove_dir_all(move_to.join("data")).unwrap();
+        let result = relocate_output(&extract_dir, &move_to, None, false).unwrap();
+        assert_eq!(result, move_to.join("data"));
+        assert!(move_to.join("data/new.txt").exists());
+        assert!(!extract_dir.exists());
+    }
+
+    #[test]
+    fn test_resolve_collision_strategies() {
+        let temp = tempfile::tempdir().unwrap();
+        let incoming = temp.path().join("staging/movie.mkv");
+        fs::create_dir_all(incoming.parent().unwrap()).unwrap();
+        fs::write(&incoming, "new").unwrap();
+        let target = temp.path().join("movie.mkv");
+        let resolve = |collision| resolve_collision(&target, &incoming, collision).unwrap();
+
+        // A free name is used as it is, whatever the strategy
+        assert_eq!(resolve(Collision::Skip), Some(target.clone()));
+
+        fs::write(&target, "old").unwrap();
+        assert_eq!(resolve(Collision::Skip), None);
+        assert_eq!(
+            resolve(Collision::Counter),
+            Some(temp.path().join("movie (2).mkv"))
+        );
+        fs::write(temp.path().join("movie (2).mkv"), "older").unwrap();
+        assert_eq!(
+            resolve(Collision::Counter),
+            Some(temp.path().join("movie (3).mkv"))
+        );
+
+        let hash = content_hash(&incoming).unwrap();
+        assert_eq!(hash.len(), 8);
+        assert_ne!(hash, content_hash(&target).unwrap());
+        let hashed = temp.path().join(format!("movie ({}).mkv", hash));
+        assert_eq!(resolve(Collision::Hash), Some(hashed.clone()));
+        // The same content under its hashed name already: nothing to place
+        fs::copy(&incoming, &hashed).unwrap();
+        assert_eq!(resolve(Collision::Hash), None);
+
+        assert_eq!(resolve(Collision::Overwrite), Some(target.clone()));
+        assert!(!target.exists());
+
+        // Directories keep dots in their names and are replaced whole
+        let dir = temp.path().join("show.s01");
+        fs::create_dir_all(dir.join("old")).unwrap();
+        let new_dir = temp.path().join("staging/show.s01");
+        fs::create_dir_all(&new_dir).unwrap();
+        let resolve_dir = |collision| resolve_collision(&dir, &new_dir, collision).unwrap();
+        assert_eq!(
+            resolve_dir(Collision::Counter),
+            Some(temp.path().join("show.s01 (2)"))
+        );
+        assert_eq!(resolve_dir(Collision::Overwrite), Some(dir.clone()));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_relocate_output_collision_strategy() {
+        let temp = tempfile::tempdir().unwrap();
+        let move_to = temp.path().join("store");
+        fs::create_dir_all(move_to.join("data")).unwrap();
+        fs::write(move_to.join("data/old.txt"), "old").unwrap();
+        let extract = |name: &str| {
+            let dir = temp.path().join(name).join("data");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("new.txt"), name).unwrap();
+            dir
+        };
+
+        let counter = Some(Collision::Counter);
+        let result = relocate_output(&extract("a"), &move_to, counter, true).unwrap();
+        assert_eq!(result, move_to.join("data (2)"));
+        assert!(move_to.join("data/old.txt").exists());
+
+        let overwrite = Some(Collision::Overwrite);
+        let result = relocate_output(&extract("b"), &move_to, overwrite, false).unwrap();
+        assert_eq!(result, move_to.join("data"));
+        assert_eq!(
+            fs::read_to_string(move_to.join("data/new.txt")).unwrap(),
+            "b"
+        );
+        assert!(!move_to.join("data/old.txt").exists());
+    }
+
+    #[test]
+    fn test_remove_special_files() {
+        use std::os::unix::fs::symlink;
+        let temp = tempfile::tempdir().unwrap();
+        let out = temp.path().join("out");
+        fs::create_dir_all(out.join("sub")).unwrap();
+        fs::write(out.join("sub/file.txt"), "data").unwrap();
+        symlink("file.txt", out.join("sub/ok")).unwrap();
+        symlink("../sub/file.txt", out.join("sub/also-ok")).unwrap();
+        symlink("../../secret", out.join("sub/escape")).unwrap();
+        symlink("/etc/passwd", out.join("absolute")).unwrap();
+        let fifo = Command::new("mkfifo")
+            .arg(out.join("pipe"))
+            .status()
+            .unwrap();
+        assert!(fifo.success());
+        // Device nodes need privileges; check one only where mknod works
+        let device = Command::new("mknod")
+            .arg(out.join("null"))
+            .args(["c", "1", "3"])
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success());
+
+        let mut removed = remove_special_files(&out).unwrap();
+        removed.sort();
+        let mut expected = vec![
+            (out.join("absolute"), "symlink out of the output"),
+            (out.join("pipe"), "FIFO"),
+            (out.join("sub/escape"), "symlink out of the output"),
+        ];
+        if device {
+            expected.push((out.join("null"), "device node"));
+        }
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert!(out.join("sub/ok").exists());
+        assert!(out.join("sub/also-ok").exists());
+        assert!(fs::symlink_metadata(out.join("sub/escape")).is_err());
+        assert!(remove_special_files(&out).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_file_count_check() {
+        let listing = "----------\nPath = a\nFolder = +\n\nPath = a/1\n\nPath = a/2\n";
+        let entries = listing::parse_7z_slt(&format!("\n{}", listing)).len();
+        assert_eq!(entries, 3);
+        let check = |max, yes, tty| file_count_check(entries, max, yes, tty);
+        assert_eq!(check(3, false, false), FileCountCheck::Within);
+        assert_eq!(check(2, false, true), FileCountCheck::Ask);
+        assert_eq!(check(2, false, false), FileCountCheck::Skip);
+        assert_eq!(check(2, true, false), FileCountCheck::Proceed);
+        assert_eq!(check(0, true, true), FileCountCheck::Proceed);
+    }
+
+    #[test]
+    fn test_rebase_command_sets_per_command_dir() {
+        let cwd = std::env::current_dir().unwrap();
+        let build = |path: &str| {
+            let path = Path::new(path);
+            let mut cmd = Command::new("7zz");
+            cmd.arg("x").arg(path).arg("-o/out").env("LANG", "C");
+            rebase_command(&cmd, path, path.parent().unwrap()).unwrap()
+        };
+        let first = build("/dl/movies/data.7z.001");
+        let second = build("/dl/shows/data.7z.001");
+
+        let args = |cmd: &Command| cmd.get_args().map(|a| a.to_owned()).collect::<Vec<_>>();
+        assert_eq!(args(&first), ["x", "data.7z.001", "-o/out"]);
+        assert_eq!(args(&second), args(&first));
+        assert_eq!(first.get_current_dir(), Some(Path::new("/dl/movies")));
+        assert_eq!(second.get_current_dir(), Some(Path::new("/dl/shows")));
+        assert_eq!(first.get_envs().count(), 1);
+        // Building them never touches this process's directory
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+
+        let cmd = Command::new("7zz");
+        assert!(rebase_command(&cmd, Path::new("/dl/a.7z.001"), Path::new("/dl")).is_err());
+    }
+
+    #[test]
+    fn test_password_args_are_single_arguments() {
+        let seven = Archive::new(PathBuf::from("/dl/a.7z.001")).unwrap();
+        let rar = Archive::new(PathBuf::from("/dl/a.part01.rar")).unwrap();
+        let pw = |p: &str| Some(p.to_string());
+
+        for secret in ["with space", "a=b", "--leading", "-x", "quote\"d"] {
+            for archive in [&seven, &rar] {
+                let cmd = archive
+                    .extract_command(false, &pw(secret), Path::new("/out"))
+                    .unwrap();
+                let args: Vec<_> = cmd.get_args().map(|a| a.to_str().unwrap()).collect();
+                let expected = format!("-p{}", secret);
+                assert_eq!(args.iter().filter(|a| a.starts_with("-p")).count(), 1);
+                assert!(args.contains(&expected.as_str()), "{:?}", args);
             }
         }
+
+        let seven_type = &seven.archive_type;
+        let rar_type = &rar.archive_type;
+        assert!(password_args(seven_type, &None).unwrap().is_empty());
+        assert_eq!(password_args(seven_type, &pw("")).unwrap(), ["-p"]);
+        assert_eq!(password_args(rar_type, &None).unwrap(), ["-p-"]);
+        assert_eq!(password_args(rar_type, &pw("")).unwrap(), ["-p-"]);
+        assert!(password_args(rar_type, &pw("-")).is_err());
+        assert_eq!(password_args(seven_type, &pw("-")).unwrap(), ["-p-"]);
     }
 
-    // Summary
-    println!("\n{}", style("═".repeat(50)).dim());
-    println!(
-        "{} {} | {} {} | {} {} | {} {}",
-        style("Total:").bold(),
-        style(indices.len()).yellow(),
-        style("Success:").green(),
-        style(success).green(),
-        style("Failed:").red(),
-        style(failed).red(),
-        style("Skipped:").yellow(),
-        style(skipped).yellow()
-    );
+    /// Captures log messages so tests can assert on them.
+    struct CaptureLogger;
+
+    static CAPTURED_LOGS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::max_level()
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                let line = format!("{} {}", record.level(), record.args());
+                CAPTURED_LOGS.lock().unwrap().push(line);
+            }
+        }
 
-    if failed > 0 {
-        println!("\n{} See {} for details", style("⚠").yellow(), style("failed.log").yellow());
+        fn flush(&self) {}
     }
 
-    Ok(())
-}
+    fn capture_logs() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Debug);
+        });
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_debug_log_emits_command_line() {
+        capture_logs();
+        let archive = Archive::new(PathBuf::from("/dl/my movie.7z.001")).unwrap();
+        let cmd = archive
+            .extract_command(false, &None, Path::new("/dl/my movie"))
+            .unwrap();
+        log_command(&cmd, Path::new("/dl"));
+
+        let logs = CAPTURED_LOGS.lock().unwrap();
+        let expected = r#"DEBUG Running `7zz x -y "/dl/my movie.7z.001" "-o/dl/my movie"` in /dl"#;
+        assert!(logs.iter().any(|l| l == expected), "{:?}", logs);
+    }
 
     #[test]
-    fn test_archive_new_and_extract_dir() {
-        // Archive in subdirectory: extract_dir should be parent/base_name
-        let archive = Archive::new(PathBuf::from("a/b/c/archive.7z.001")).unwrap();
-        assert_eq!(archive.base_name, "archive");
+    fn test_redacted_command_string_masks_password() {
+        let archive = Archive::new(PathBuf::from("/dl/a.7z.001")).unwrap();
+        let secret = Some("hunter 2".to_string());
+        let cmd = archive
+            .extract_command(false, &secret, Path::new("/dl/a"))
+            .unwrap();
+        let shown = redacted_command_string(&cmd);
+        assert!(!shown.contains("hunter"), "{}", shown);
+        assert!(shown.contains("-p***"), "{}", shown);
+        assert!(
+            shown.contains("/dl/a.7z.001") && shown.contains("-o/dl/a"),
+            "{}",
+            shown
+        );
+        // The real value still reaches the child
+        assert!(cmd.get_args().any(|a| a == "-phunter 2"));
+
+        let archive = Archive::new(PathBuf::from("/dl/b.part01.rar")).unwrap();
+        let cmd = archive
+            .extract_command(false, &None, Path::new("/dl/b"))
+            .unwrap();
+        assert!(redacted_command_string(&cmd).contains(" -p- "));
+    }
+
+    #[test]
+    fn test_from_volume_recognizes_any_part() {
+        let (archive, number) = Archive::from_volume(PathBuf::from("/dl/data.7z.005")).unwrap();
         assert_eq!(archive.archive_type, ArchiveType::SevenZip);
-        let extract_dir = archive.extract_dir().unwrap();
-        assert_eq!(extract_dir, PathBuf::from("a/b/c/archive"));
+        assert_eq!(archive.base_name, "data");
+        assert_eq!(archive.path, PathBuf::from("/dl/data.7z.005"));
+        assert_eq!(number, 5);
+        assert!(Archive::new(PathBuf::from("/dl/data.7z.005")).is_none());
 
-        // Tar.gz in root
-        let archive2 = Archive::new(PathBuf::from("foo.tar.gz")).unwrap();
-        assert_eq!(archive2.base_name, "foo");
-        assert_eq!(archive2.archive_type, ArchiveType::TarGz);
-        let extract_dir2 = archive2.extract_dir().unwrap();
-        assert_eq!(extract_dir2, PathBuf::from("foo"));
+        let (archive, number) =
+            Archive::from_volume(PathBuf::from("/dl/show.s01.part12.rar")).unwrap();
+        assert_eq!(archive.archive_type, ArchiveType::Rar);
+        assert_eq!(archive.base_name, "show.s01");
+        assert_eq!(number, 12);
 
-        // Unrecognized extensions return None
-        assert!(Archive::new(PathBuf::from("other.txt")).is_none());
+        for name in [
+            "data.7z",
+            "data.rar",
+            "data.7z.000",
+            "data.tar.005",
+            ".7z.002",
+        ] {
+            assert!(
+                Archive::from_volume(PathBuf::from(name)).is_none(),
+                "{}",
+                name
+            );
+        }
     }
 
     #[test]
-    fn test_scan_archives_finds_subfolder_archives() {
+    fn test_part_test_command() {
+        let (rar, number) = Archive::from_volume(PathBuf::from("/dl/x.part05.rar")).unwrap();
+        let cmd = rar.part_test_command(number, &None).unwrap();
+        assert_eq!(
+            redacted_command_string(&cmd),
+            "unrar t -p- /dl/x.part05.rar"
+        );
+
+        let (first, number) = Archive::from_volume(PathBuf::from("/dl/x.7z.001")).unwrap();
+        let cmd = first.part_test_command(number, &None).unwrap();
+        assert_eq!(cmd.get_args().next().unwrap(), "t");
+
+        let (later, number) = Archive::from_volume(PathBuf::from("/dl/x.7z.005")).unwrap();
+        let err = later.part_test_command(number, &None).unwrap_err();
+        assert!(err.to_string().contains("x.7z.001"), "{}", err);
+    }
+
+    #[test]
+    fn test_hook_command_substitutes_dir_and_name() {
         let temp = tempfile::tempdir().unwrap();
-        let temp_path = temp.path();
+        let archive = Archive::new(temp.path().join("my show.7z.001")).unwrap();
+        let out = temp.path().join("my show");
+        fs::create_dir(&out).unwrap();
 
-        // Create temp/subdir/file.7z.001
-        let subdir = temp_path.join("subdir");
-        fs::create_dir_all(&subdir).unwrap();
-        let archive_path = subdir.join("file.7z.001");
-        fs::write(&archive_path, "dummy").unwrap();
+        let cmd = hook_command("import --into {dir} --tag={name}", &out, &archive).unwrap();
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(cmd.get_program(), "import");
+        assert_eq!(args[1], out.as_os_str());
+        assert_eq!(args[2], "--tag=my show");
 
-        let archives = scan_archives(temp_path).unwrap();
-        assert_eq!(archives.len(), 1);
-        assert_eq!(archives[0].base_name, "file");
-        assert!(archives[0].path.ends_with("file.7z.001"));
-        assert!(archives[0].path.parent().unwrap().ends_with("subdir"));
+        let mut cmd = hook_command("touch {dir}/{name}.done", &out, &archive).unwrap();
+        assert!(run_with_timeout(&mut cmd, Duration::from_secs(10))
+            .unwrap()
+            .success());
+        assert!(out.join("my show.done").exists());
+    }
+
+    #[test]
+    fn test_hook_timeout_kills_command() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("10");
+        let started = std::time::Instant::now();
+        let err = run_with_timeout(&mut cmd, Duration::from_millis(100)).unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // A timeout too large to add to the clock doesn't panic
+        let status = run_with_timeout(&mut Command::new("true"), Duration::MAX).unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_parallel_scan_matches_sequential_walk() {
+        let temp = tempfile::tempdir().unwrap();
+        for a in 0..12 {
+            for b in 0..8 {
+                let dir = temp.path().join(format!("d{}/e{}/f", a, b));
+                fs::create_dir_all(&dir).unwrap();
+                for c in 0..10 {
+                    let name = match c % 5 {
+                        0 => format!("s{}.7z.001", c),
+                        1 => format!("s{}.7z.002", c),
+                        2 => format!("s{}.part01.rar", c),
+                        3 => format!("s{}.tar.gz", c),
+                        _ => format!("s{}.txt", c),
+                    };
+                    fs::write(dir.join(name), b"x").unwrap();
+                }
+            }
+        }
+
+        let expected: std::collections::BTreeSet<_> =
+            WalkDir::new(temp.path().canonicalize().unwrap())
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .filter_map(|e| Archive::new(e.into_path()))
+                .map(|a| a.path)
+                .collect();
+        assert_eq!(expected.len(), 12 * 8 * 6);
+
+        let first = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        let found: std::collections::BTreeSet<_> = first.iter().map(|a| a.path.clone()).collect();
+        assert_eq!(found, expected);
+        assert_eq!(first.len(), expected.len());
+
+        // Same order every time
+        let second = scan_archives(temp.path(), &ScanOptions::default()).unwrap();
+        let paths = |v: &[Archive]| v.iter().map(|a| a.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&first), paths(&second));
+    }
+
+    #[test]
+    fn test_walk_queue_survives_a_panicking_worker() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("a.7z.001"), "").unwrap();
+        let queue = WalkQueue::new(PathBuf::from("/nonexistent"), true);
+        {
+            let mut state = queue.state.lock().unwrap();
+            state.0.clear();
+            state.1 = 1;
+        }
+        // A worker dies with a directory claimed and one subdirectory found
+        let died = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _claim = Claim {
+                        queue: &queue,
+                        subdirs: vec![temp.path().to_path_buf()],
+                    };
+                    panic!("worker failed");
+                })
+                .join()
+        });
+        assert!(died.is_err());
+
+        // The rest neither hang nor lose what it found
+        let (files, found) = std::sync::mpsc::channel();
+        queue.work(&files);
+        drop(files);
+        assert_eq!(
+            found.iter().collect::<Vec<_>>(),
+            [temp.path().join("a.7z.001")]
+        );
+    }
+
+    #[test]
+    fn test_scan_dir_errors_name_the_path() {
+        let temp = tempfile::tempdir().unwrap();
+        let missing = temp.path().join("no/such/dir");
+        let err = resolve_scan_dir(&missing).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains(&missing.display().to_string()),
+            "{}",
+            message
+        );
+        assert!(message.contains("does not exist"), "{}", message);
+
+        let file = temp.path().join("file.txt");
+        fs::write(&file, "").unwrap();
+        let err = resolve_scan_dir(&file).unwrap_err();
+        assert!(err.to_string().contains("is not a directory"), "{}", err);
+
+        // Trailing slashes and `-` are fine
+        let canonical = temp.path().canonicalize().unwrap();
+        let slashed = PathBuf::from(format!("{}/", temp.path().display()));
+        assert_eq!(resolve_scan_dir(&slashed).unwrap(), canonical);
+        for argv in [vec!["un7z", "-d", "-"], vec!["un7z"]] {
+            assert_eq!(Args::parse_from(argv).dir, PathBuf::from("."));
+        }
+    }
+
+    #[test]
+    fn test_log_level_argument() {
+        let args = Args::parse_from(["un7z", "--log-level", "debug"]);
+        assert_eq!(args.log_level, Some(log::LevelFilter::Debug));
+        assert!(Args::try_parse_from(["un7z", "--log-level", "loud"]).is_err());
+
+        // --verbose turns on the debug lines that echo each backend command
+        let level = |argv: &[&str]| log_filter(&Args::parse_from(argv));
+        assert_eq!(level(&["un7z"]), None);
+        assert_eq!(level(&["un7z", "-v"]), Some(log::LevelFilter::Debug));
+        assert_eq!(
+            level(&["un7z", "-v", "--log-level", "info"]),
+            Some(log::LevelFilter::Info)
+        );
+
+        // At that level the redacted command line and its directory are echoed
+        capture_logs();
+        log::set_max_level(level(&["un7z", "--verbose"]).unwrap());
+        let archive = Archive::new(PathBuf::from("/dl/verbose.7z.001")).unwrap();
+        let secret = Some("hunter2".to_string());
+        let cmd = archive
+            .extract_command(false, &secret, Path::new("/dl/verbose"))
+            .unwrap();
+        log_command(&cmd, Path::new("/dl"));
+        let logs = CAPTURED_LOGS.lock().unwrap();
+        let expected = "DEBUG Running `7zz x -y /dl/verbose.7z.001 -p*** -o/dl/verbose` in /dl";
+        assert!(logs.iter().any(|l| l == expected), "{:?}", logs);
     }
 
     #[test]
@@ -658,7 +9942,7 @@ mod tests {
         fs::write(&archive_path, "").unwrap(); // Empty file, just for path test
 
         let archive = Archive::new(archive_path.clone()).unwrap();
-        let extract_dir = archive.extract_dir().unwrap();
+        let extract_dir = archive.extract_dir(&OutputLayout::default()).unwrap();
         assert_eq!(extract_dir, subdir.join("data"));
         assert!(!extract_dir.exists());
         fs::create_dir_all(&extract_dir).unwrap();
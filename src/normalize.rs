@@ -0,0 +1,144 @@
+//! `--normalize-unicode {nfc,nfd}`: rename extracted files to one Unicode
+//! normalization form, so names from macOS (NFD) and Linux (NFC) archives
+//! stop looking like duplicates of each other. Only the canonical forms are
+//! offered; compatibility forms would change what names mean.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum UnicodeForm {
+    Nfc,
+    Nfd,
+}
+
+impl UnicodeForm {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            UnicodeForm::Nfc => "NFC",
+            UnicodeForm::Nfd => "NFD",
+        }
+    }
+}
+
+/// One renamed entry; `collided` when the normalized name was taken by
+/// another file and a ` (N)` suffix was added.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Renamed {
+    pub(crate) from: PathBuf,
+    pub(crate) to: PathBuf,
+    pub(crate) collided: bool,
+}
+
+/// `s` in the normalization form `form`.
+pub(crate) fn normalize(s: &str, form: UnicodeForm) -> String {
+    match form {
+        UnicodeForm::Nfc => s.nfc().collect(),
+        UnicodeForm::Nfd => s.nfd().collect(),
+    }
+}
+
+/// Rename everything below `root` (or `root` itself when it is a file) to
+/// the normalization form `form`, deepest entries first.
+pub(crate) fn normalize_tree(root: &Path, form: UnicodeForm) -> Result<Vec<Renamed>> {
+    let min_depth = if root.is_dir() { 1 } else { 0 };
+    let entries = walkdir::WalkDir::new(root)
+        .min_depth(min_depth)
+        .contents_first(true)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Cannot walk {}", root.display()))?;
+
+    let mut renamed = Vec::new();
+    for entry in entries {
+        let from = entry.path();
+        let Some(name) = from.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let normalized = normalize(name, form);
+        if normalized == name {
+            continue;
+        }
+        let mut to = from.with_file_name(&normalized);
+        let collided = fs::symlink_metadata(&to).is_ok() && !same_entry(from, &to);
+        if collided {
+            to = crate::unique_name(&to, !entry.file_type().is_dir());
+        }
+        fs::rename(from, &to)
+            .with_context(|| format!("Cannot rename {} to {}", from.display(), to.display()))?;
+        renamed.push(Renamed {
+            from: from.to_path_buf(),
+            to,
+            collided,
+        });
+    }
+    Ok(renamed)
+}
+
+/// Whether both paths name the same entry, as on a file system that ignores
+/// normalization differences.
+fn same_entry(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_forms() {
+        let samples = [
+            ("Cafe\u{301}.txt", "Caf\u{e9}.txt"),
+            ("\u{1100}\u{1161}\u{11a8}", "\u{ac01}"),
+            ("\u{304b}\u{3099}\u{30cf}\u{309a}", "\u{304c}\u{30d1}"),
+            // Marks out of canonical order still compose (ệ)
+            ("e\u{302}\u{323}", "\u{1ec7}"),
+            ("\u{418}\u{306}", "\u{419}"),
+            ("plain name", "plain name"),
+        ];
+        for (nfd, nfc) in samples {
+            assert_eq!(normalize(nfd, UnicodeForm::Nfc), nfc, "{:?}", nfd);
+            assert_eq!(normalize(nfc, UnicodeForm::Nfc), nfc, "{:?}", nfc);
+            let decomposed = normalize(nfc, UnicodeForm::Nfd);
+            assert_eq!(normalize(&decomposed, UnicodeForm::Nfc), nfc);
+        }
+        assert_eq!(normalize("\u{1ec7}", UnicodeForm::Nfd), "e\u{323}\u{302}");
+        assert_eq!(
+            normalize("\u{ac01}", UnicodeForm::Nfd),
+            "\u{1100}\u{1161}\u{11a8}"
+        );
+    }
+
+    #[test]
+    fn test_normalize_tree_renames_and_resolves_collisions() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let nfd_dir = root.join("Re\u{301}sume\u{301}");
+        fs::create_dir(&nfd_dir).unwrap();
+        fs::write(nfd_dir.join("cafe\u{301}.txt"), "nfd").unwrap();
+        fs::write(nfd_dir.join("caf\u{e9}.txt"), "nfc").unwrap();
+        if fs::read(nfd_dir.join("cafe\u{301}.txt")).unwrap() != b"nfd" {
+            // The file system folds normalization; nothing to collide
+            return;
+        }
+
+        let renamed = normalize_tree(root, UnicodeForm::Nfc).unwrap();
+        let dir = root.join("R\u{e9}sum\u{e9}");
+        assert!(dir.is_dir());
+        assert_eq!(fs::read(dir.join("caf\u{e9}.txt")).unwrap(), b"nfc");
+        assert_eq!(fs::read(dir.join("caf\u{e9} (2).txt")).unwrap(), b"nfd");
+        assert_eq!(renamed.len(), 2);
+        assert!(renamed[0].collided);
+        assert!(!renamed[1].collided);
+        assert_eq!(renamed[1].to, dir);
+
+        // Already normalized: nothing to do
+        assert!(normalize_tree(root, UnicodeForm::Nfc).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+//! Standard locations for the files un7z keeps between runs.
+//!
+//! Resolved per platform with `directories` (`~/.config/un7z`, `~/.cache/un7z`
+//! and `~/.local/share/un7z` on Linux). Each can be overridden with an
+//! environment variable, e.g. `UN7Z_CONFIG_DIR`.
+
+use directories::ProjectDirs;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Pick the override from `var` if set and non-empty, else the platform default.
+fn resolve(
+    var: &str,
+    env: impl Fn(&str) -> Option<OsString>,
+    default: impl FnOnce(&ProjectDirs) -> &Path,
+) -> Option<PathBuf> {
+    match env(var) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => ProjectDirs::from("", "", "un7z").map(|dirs| default(&dirs).to_path_buf()),
+    }
+}
+
+fn from_env(var: &str) -> Option<OsString> {
+    std::env::var_os(var)
+}
+
+/// Configuration files (`UN7Z_CONFIG_DIR`).
+#[allow(dead_code)] // No config file is read yet
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    resolve("UN7Z_CONFIG_DIR", from_env, ProjectDirs::config_dir)
+}
+
+/// Disposable data such as scan caches (`UN7Z_CACHE_DIR`).
+pub(crate) fn cache_dir() -> Option<PathBuf> {
+    resolve("UN7Z_CACHE_DIR", from_env, ProjectDirs::cache_dir)
+}
+
+/// Data worth keeping, such as reports (`UN7Z_DATA_DIR`).
+#[allow(dead_code)] // No report is written here yet
+pub(crate) fn data_dir() -> Option<PathBuf> {
+    resolve("UN7Z_DATA_DIR", from_env, ProjectDirs::data_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_override_wins() {
+        let env = |var: &str| (var == "UN7Z_CONFIG_DIR").then(|| OsString::from("/tmp/un7z-conf"));
+        let dir = resolve("UN7Z_CONFIG_DIR", env, ProjectDirs::config_dir);
+        assert_eq!(dir, Some(PathBuf::from("/tmp/un7z-conf")));
+    }
+
+    #[test]
+    fn test_default_without_override() {
+        let unset = |_: &str| None;
+        let empty = |_: &str| Some(OsString::new());
+        let default = ProjectDirs::from("", "", "un7z").map(|d| d.cache_dir().to_path_buf());
+        assert_eq!(
+            resolve("UN7Z_CACHE_DIR", unset, ProjectDirs::cache_dir),
+            default
+        );
+        assert_eq!(
+            resolve("UN7Z_CACHE_DIR", empty, ProjectDirs::cache_dir),
+            default
+        );
+        if let Some(dir) = default {
+            assert!(dir.ends_with("un7z"), "{}", dir.display());
+        }
+    }
+}
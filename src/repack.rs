@@ -0,0 +1,225 @@
+//! `--repack FORMAT`: compress the extracted output into another archive
+//! format next to it (`movie/` → `movie.tar.zst`), optionally removing the
+//! extracted files afterwards.
+//!
+//! Packing is left to `tar` (with `gzip` or `zstd`) and `7zz`.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum RepackFormat {
+    #[value(name = "tar.gz")]
+    TarGz,
+    #[value(name = "tar.zst")]
+    TarZst,
+    #[value(name = "zip")]
+    Zip,
+    #[value(name = "7z")]
+    SevenZip,
+}
+
+impl RepackFormat {
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            RepackFormat::TarGz => "tar.gz",
+            RepackFormat::TarZst => "tar.zst",
+            RepackFormat::Zip => "zip",
+            RepackFormat::SevenZip => "7z",
+        }
+    }
+
+    /// Where the repacked archive of `source` goes: beside it, named after it.
+    pub(crate) fn output_path(self, source: &Path) -> Result<PathBuf> {
+        let name = source
+            .file_name()
+            .with_context(|| format!("{} has no name to repack under", source.display()))?;
+        let mut file_name = name.to_os_string();
+        file_name.push(".");
+        file_name.push(self.extension());
+        Ok(source.with_file_name(file_name))
+    }
+
+    /// The command packing `source` (a directory or a file) into `output`.
+    /// It runs from the parent of `source`, so the archive holds `name/...`.
+    pub(crate) fn command(self, source: &Path, output: &Path) -> Result<Command> {
+        let name = source
+            .file_name()
+            .with_context(|| format!("{} has no name to repack under", source.display()))?;
+        let parent = source
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let mut cmd = match self {
+            RepackFormat::TarGz | RepackFormat::TarZst => {
+                let mut cmd = Command::new("tar");
+                if self == RepackFormat::TarZst {
+                    cmd.args(["--use-compress-program", "zstd"]);
+                } else {
+                    cmd.arg("-z");
+                }
+                cmd.arg("-cf")
+                    .arg(output)
+                    .arg("-C")
+                    .arg(parent)
+                    .arg("--")
+                    .arg(name);
+                cmd
+            }
+            RepackFormat::Zip | RepackFormat::SevenZip => {
+                let kind = if self == RepackFormat::Zip {
+                    "-tzip"
+                } else {
+                    "-t7z"
+                };
+                let mut cmd = Command::new("7zz");
+                cmd.args(["a", kind, "-bd", "-y"])
+                    .arg(output)
+                    .arg("--")
+                    .arg(name);
+                cmd.current_dir(parent);
+                cmd
+            }
+        };
+        cmd.stdin(Stdio::null());
+        Ok(cmd)
+    }
+}
+
+/// Pack `source` as `format` and return the new archive; with `remove`,
+/// delete `source` once that worked. A failed run leaves no partial archive.
+pub(crate) fn repack(source: &Path, format: RepackFormat, remove: bool) -> Result<PathBuf> {
+    let output = format.output_path(source)?;
+    // An absolute path, since 7zz runs from the parent directory
+    let output = std::path::absolute(&output).unwrap_or(output);
+    if output.exists() {
+        anyhow::bail!("{} already exists, not repacking", output.display());
+    }
+    let mut cmd = format.command(source, &output)?;
+    log::debug!("Repacking with {:?}", cmd);
+    let result = cmd
+        .output()
+        .with_context(|| format!("Failed to run {}", cmd.get_program().to_string_lossy()));
+    let failure = match result {
+        Ok(out) if out.status.success() => None,
+        Ok(out) => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            let last = stderr
+                .lines()
+                .rev()
+                .find(|l| !l.trim().is_empty())
+                .unwrap_or("");
+            Some(anyhow::anyhow!(
+                "Repacking failed ({}): {}",
+                out.status,
+                last.trim()
+            ))
+        }
+        Err(e) => Some(e),
+    };
+    if let Some(e) = failure {
+        let _ = fs::remove_file(&output);
+        return Err(e);
+    }
+
+    if remove {
+        let removed = if source.is_dir() {
+            fs::remove_dir_all(source)
+        } else {
+            fs::remove_file(source)
+        };
+        removed.with_context(|| format!("Cannot remove {}", source.display()))?;
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn args(cmd: &Command) -> Vec<&OsStr> {
+        cmd.get_args().collect()
+    }
+
+    #[test]
+    fn test_repack_commands() {
+        let source = Path::new("/dl/movie");
+        let out = RepackFormat::TarZst.output_path(source).unwrap();
+        assert_eq!(out, PathBuf::from("/dl/movie.tar.zst"));
+        let cmd = RepackFormat::TarZst.command(source, &out).unwrap();
+        assert_eq!(cmd.get_program(), "tar");
+        assert_eq!(
+            args(&cmd),
+            [
+                "--use-compress-program",
+                "zstd",
+                "-cf",
+                "/dl/movie.tar.zst",
+                "-C",
+                "/dl",
+                "--",
+                "movie"
+            ]
+        );
+
+        let cmd = RepackFormat::TarGz
+            .command(Path::new("movie"), Path::new("/o/m.tgz"))
+            .unwrap();
+        assert_eq!(
+            args(&cmd),
+            ["-z", "-cf", "/o/m.tgz", "-C", ".", "--", "movie"]
+        );
+
+        let out = RepackFormat::Zip
+            .output_path(Path::new("/dl/show.s01"))
+            .unwrap();
+        assert_eq!(out, PathBuf::from("/dl/show.s01.zip"));
+        let cmd = RepackFormat::Zip
+            .command(Path::new("/dl/show.s01"), &out)
+            .unwrap();
+        assert_eq!(cmd.get_program(), "7zz");
+        assert_eq!(
+            args(&cmd),
+            [
+                "a",
+                "-tzip",
+                "-bd",
+                "-y",
+                "/dl/show.s01.zip",
+                "--",
+                "show.s01"
+            ]
+        );
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("/dl")));
+
+        let cmd = RepackFormat::SevenZip
+            .command(source, Path::new("/dl/movie.7z"))
+            .unwrap();
+        assert_eq!(args(&cmd)[1], "-t7z");
+    }
+
+    #[test]
+    fn test_repack_tar_gz() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("movie");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("a.txt"), "data").unwrap();
+
+        let out = repack(&source, RepackFormat::TarGz, true).unwrap();
+        assert_eq!(out, temp.path().join("movie.tar.gz"));
+        assert!(out.is_file());
+        assert!(!source.exists());
+
+        let listing = Command::new("tar").arg("-tzf").arg(&out).output().unwrap();
+        let listing = String::from_utf8_lossy(&listing.stdout);
+        assert!(listing.lines().any(|l| l == "movie/a.txt"), "{}", listing);
+
+        // The target is never overwritten
+        fs::create_dir_all(&source).unwrap();
+        assert!(repack(&source, RepackFormat::TarGz, true).is_err());
+        assert!(source.exists());
+    }
+}
@@ -0,0 +1,150 @@
+//! `--spec PATH`: a batch described in a file, one `[[archive]]` table per
+//! archive, with settings that override the command line for that archive:
+//!
+//! ```toml
+//! [[archive]]
+//! path = "movies/film.7z"
+//! password = "secret"
+//! output = "/media/films"
+//! force = true
+//! ```
+//!
+//! Unknown tables and keys are errors, so a typo never silently drops a
+//! setting. Relative paths are taken from the spec file's directory.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One `[[archive]]` table. `None` keeps the command line's setting.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SpecEntry {
+    pub(crate) path: PathBuf,
+    pub(crate) password: Option<String>,
+    /// Extract under this directory, like `--output`.
+    pub(crate) output: Option<PathBuf>,
+    pub(crate) test: Option<bool>,
+    pub(crate) force: Option<bool>,
+    pub(crate) touch: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpecFile {
+    #[serde(default)]
+    archive: Vec<SpecEntry>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Spec {
+    entries: Vec<SpecEntry>,
+    /// Entry index by absolute and by canonical path.
+    by_path: HashMap<PathBuf, usize>,
+}
+
+impl Spec {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read spec {}", path.display()))?;
+        let base = std::path::absolute(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Self::parse(&text, &base).with_context(|| format!("Invalid spec {}", path.display()))
+    }
+
+    /// Parse spec text, resolving relative paths against `base`.
+    pub(crate) fn parse(text: &str, base: &Path) -> Result<Self> {
+        let file: SpecFile = toml::from_str(text)?;
+        let mut entries = file.archive;
+        let mut by_path = HashMap::new();
+        for (i, entry) in entries.iter_mut().enumerate() {
+            entry.path = std::path::absolute(base.join(&entry.path))?;
+            if let Some(output) = &mut entry.output {
+                *output = std::path::absolute(base.join(&*output))?;
+            }
+            if let Ok(canonical) = entry.path.canonicalize() {
+                by_path.entry(canonical).or_insert(i);
+            }
+            by_path.entry(entry.path.clone()).or_insert(i);
+        }
+        Ok(Spec { entries, by_path })
+    }
+
+    /// The archives to extract, in spec order.
+    pub(crate) fn paths(&self) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    /// The settings for the archive at `path`, if the spec lists it.
+    pub(crate) fn entry(&self, path: &Path) -> Option<&SpecEntry> {
+        self.by_path.get(path).map(|&i| &self.entries[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let text = r#"
+# nightly job
+[[archive]]
+path = "movies/film.7z"   # relative to the spec
+password = "p\"w#1"
+output = '/media/films'
+force = true
+
+[[archive]]
+path = "/dl/show.part1.rar"
+test = false
+"#;
+        let spec = Spec::parse(text, Path::new("/jobs")).unwrap();
+        assert_eq!(
+            spec.paths(),
+            [
+                PathBuf::from("/jobs/movies/film.7z"),
+                PathBuf::from("/dl/show.part1.rar")
+            ]
+        );
+        let film = spec.entry(Path::new("/jobs/movies/film.7z")).unwrap();
+        assert_eq!(film.password.as_deref(), Some("p\"w#1"));
+        assert_eq!(film.output, Some(PathBuf::from("/media/films")));
+        assert_eq!(
+            (film.force, film.test, film.touch),
+            (Some(true), None, None)
+        );
+        let show = spec.entry(Path::new("/dl/show.part1.rar")).unwrap();
+        assert_eq!(show.test, Some(false));
+        assert_eq!(show.password, None);
+        assert!(spec.entry(Path::new("/dl/other.7z")).is_none());
+
+        // Any TOML spelling of the same tables is read
+        let inline = r#"archive = [{ path = "/dl/a.7z", force = true }]"#;
+        let spec = Spec::parse(inline, Path::new("/jobs")).unwrap();
+        let entry = spec.entry(Path::new("/dl/a.7z")).unwrap();
+        assert_eq!(entry.force, Some(true));
+    }
+
+    #[test]
+    fn test_parse_spec_errors() {
+        let base = Path::new("/jobs");
+        let error = |text: &str| format!("{:#}", Spec::parse(text, base).unwrap_err());
+        assert!(error("path = \"a.7z\"").contains("unknown field `path`"));
+        let typo = error("[[archive]]\npath = \"a.7z\"\npasword = \"x\"");
+        assert!(typo.contains("line 3") && typo.contains("unknown field `pasword`"));
+        assert!(
+            error("[[archive]]\npath = \"a.7z\"\nforce = \"yes\"").contains("expected a boolean")
+        );
+        assert!(error("[[archive]]\npath = \"a.7z").contains("line 2"));
+        assert!(error("[[archive]]\nforce = true\n").contains("missing field `path`"));
+        assert!(error("[settings]").contains("unknown field `settings`"));
+    }
+}